@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use mqttrust::encoding::v4::{decode_slice, encode_slice};
+
+fuzz_target!(|data: &[u8]| {
+    // Decoding must never panic, and every decoded packet must encode again
+    if let Ok(Some(packet)) = decode_slice(data) {
+        let mut buf = vec![0u8; packet.len()];
+        encode_slice(&packet, &mut buf).unwrap();
+    }
+});
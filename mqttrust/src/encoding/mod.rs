@@ -1 +1,2 @@
 pub mod v4;
+pub mod v5;
@@ -1,4 +1,5 @@
 use super::{decoder::*, encoder::*, *};
+use crate::topic::validate_topic_name;
 
 /// Protocol version.
 ///
@@ -21,33 +22,28 @@ impl Protocol {
         match (name, level) {
             ("MQIsdp", 3) => Ok(Protocol::MQIsdp),
             ("MQTT", 4) => Ok(Protocol::MQTT311),
-            _ => Err(Error::InvalidProtocol(name.into(), level)),
+            _ => Err(Error::invalid_protocol(name, level)),
         }
     }
     pub(crate) fn from_buffer(buf: &[u8], offset: &mut usize) -> Result<Self, Error> {
         let protocol_name = read_str(buf, offset)?;
-        let protocol_level = buf[*offset];
-        *offset += 1;
+        let protocol_level = read_u8(buf, offset)?;
 
         Protocol::new(protocol_name, protocol_level)
     }
-    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+    /// Encoded protocol name and level.
+    fn as_bytes(self) -> &'static [u8] {
         match self {
-            Protocol::MQTT311 => {
-                let slice = &[0u8, 4, b'M', b'Q', b'T', b'T', 4];
-                for &byte in slice {
-                    write_u8(buf, offset, byte)?;
-                }
-                Ok(slice.len())
-            }
-            Protocol::MQIsdp => {
-                let slice = &[0u8, 4, b'M', b'Q', b'i', b's', b'd', b'p', 4];
-                for &byte in slice {
-                    write_u8(buf, offset, byte)?;
-                }
-                Ok(slice.len())
-            }
+            Protocol::MQTT311 => &[0u8, 4, b'M', b'Q', b'T', b'T', 4],
+            Protocol::MQIsdp => &[0u8, 6, b'M', b'Q', b'I', b's', b'd', b'p', 3],
+        }
+    }
+    pub(crate) fn to_buffer(self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        let slice = self.as_bytes();
+        for &byte in slice {
+            write_u8(buf, offset, byte)?;
         }
+        Ok(slice.len())
     }
 }
 
@@ -132,14 +128,14 @@ impl<'a> Connect<'a> {
     pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
         let protocol = Protocol::from_buffer(buf, offset)?;
 
-        let connect_flags = buf[*offset];
-        let keep_alive = ((buf[*offset + 1] as u16) << 8) | buf[*offset + 2] as u16;
-        *offset += 3;
+        let connect_flags = read_u8(buf, offset)?;
+        let keep_alive = read_u16(buf, offset)?;
 
         let client_id = read_str(buf, offset)?;
 
         let last_will = if connect_flags & 0b100 != 0 {
             let will_topic = read_str(buf, offset)?;
+            validate_topic_name(will_topic)?;
             let will_message = read_bytes(buf, offset)?;
             let will_qod = QoS::from_u8((connect_flags & 0b11000) >> 3)?;
             Some(LastWill {
@@ -178,7 +174,8 @@ impl<'a> Connect<'a> {
     }
 
     pub(crate) fn len(&self) -> usize {
-        let mut length: usize = 6 + 1 + 1; // NOTE: protocol_name(6) + protocol_level(1) + flags(1);
+        // NOTE: protocol_name(2+len) + protocol_level(1) + flags(1);
+        let mut length: usize = self.protocol.as_bytes().len() + 1;
         length += 2 + self.client_id.len();
         length += 2; // keep alive
         if let Some(username) = self.username {
@@ -198,6 +195,10 @@ impl<'a> Connect<'a> {
     }
 
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        if let Some(last_will) = &self.last_will {
+            validate_topic_name(last_will.topic)?;
+        }
+
         let header: u8 = 0b00010000;
         let mut connect_flags: u8 = 0b00000000;
         if self.clean_session {
@@ -232,7 +233,7 @@ impl<'a> Connect<'a> {
 
         if let Some(last_will) = &self.last_will {
             write_string(buf, offset, last_will.topic)?;
-            write_bytes(buf, offset, &last_will.message)?;
+            write_bytes(buf, offset, last_will.message)?;
         };
 
         if let Some(username) = self.username {
@@ -248,15 +249,14 @@ impl<'a> Connect<'a> {
 
 impl Connack {
     pub(crate) fn from_buffer(buf: &[u8], offset: &mut usize) -> Result<Self, Error> {
-        let flags = buf[*offset];
-        let return_code = buf[*offset + 1];
-        *offset += 2;
+        let flags = read_u8(buf, offset)?;
+        let return_code = read_u8(buf, offset)?;
         Ok(Connack {
             session_present: (flags & 0b1 == 1),
             code: ConnectReturnCode::from_u8(return_code)?,
         })
     }
-    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+    pub(crate) fn to_buffer(self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
         check_remaining(buf, offset, 4)?;
         let header: u8 = 0b00100000;
         let length: u8 = 2;
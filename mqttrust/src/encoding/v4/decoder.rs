@@ -3,7 +3,9 @@ use super::*;
 pub fn decode_slice(buf: &[u8]) -> Result<Option<Packet<'_>>, Error> {
     let mut offset = 0;
     if let Some((header, remaining_len)) = read_header(buf, &mut offset)? {
-        let r = read_packet(header, remaining_len, buf, &mut offset)?;
+        // Restrict the packet parsers to the bytes of this packet only
+        let buf = &buf[..offset + remaining_len];
+        let r = read_packet(header, buf, &mut offset)?;
         Ok(Some(r))
     } else {
         // Don't have a full packet
@@ -11,26 +13,21 @@ pub fn decode_slice(buf: &[u8]) -> Result<Option<Packet<'_>>, Error> {
     }
 }
 
-fn read_packet<'a>(
-    header: Header,
-    remaining_len: usize,
-    buf: &'a [u8],
-    offset: &mut usize,
-) -> Result<Packet<'a>, Error> {
+fn read_packet<'a>(header: Header, buf: &'a [u8], offset: &mut usize) -> Result<Packet<'a>, Error> {
     Ok(match header.typ {
         PacketType::Pingreq => Packet::Pingreq,
         PacketType::Pingresp => Packet::Pingresp,
         PacketType::Disconnect => Packet::Disconnect,
         PacketType::Connect => Connect::from_buffer(buf, offset)?.into(),
         PacketType::Connack => Connack::from_buffer(buf, offset)?.into(),
-        PacketType::Publish => Publish::from_buffer(&header, remaining_len, buf, offset)?.into(),
+        PacketType::Publish => Publish::from_buffer(&header, buf, offset)?.into(),
         PacketType::Puback => Packet::Puback(Pid::from_buffer(buf, offset)?),
         PacketType::Pubrec => Packet::Pubrec(Pid::from_buffer(buf, offset)?),
         PacketType::Pubrel => Packet::Pubrel(Pid::from_buffer(buf, offset)?),
         PacketType::Pubcomp => Packet::Pubcomp(Pid::from_buffer(buf, offset)?),
-        PacketType::Subscribe => Subscribe::from_buffer(remaining_len, buf, offset)?.into(),
-        PacketType::Suback => Suback::from_buffer(remaining_len, buf, offset)?.into(),
-        PacketType::Unsubscribe => Unsubscribe::from_buffer(remaining_len, buf, offset)?.into(),
+        PacketType::Subscribe => Subscribe::from_buffer(buf, offset)?.into(),
+        PacketType::Suback => Suback::from_buffer(buf, offset)?.into(),
+        PacketType::Unsubscribe => Unsubscribe::from_buffer(buf, offset)?.into(),
         PacketType::Unsuback => Packet::Unsuback(Pid::from_buffer(buf, offset)?),
     })
 }
@@ -106,17 +103,93 @@ pub(crate) fn read_str<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a str,
 }
 
 pub(crate) fn read_bytes<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a [u8], Error> {
-    if buf[*offset..].len() < 2 {
-        return Err(Error::InvalidLength);
+    let len = read_u16(buf, offset)? as usize;
+    let bytes = buf
+        .get(*offset..*offset + len)
+        .ok_or(Error::InvalidLength)?;
+    *offset += len;
+    Ok(bytes)
+}
+
+pub(crate) fn read_u8(buf: &[u8], offset: &mut usize) -> Result<u8, Error> {
+    let byte = *buf.get(*offset).ok_or(Error::InvalidLength)?;
+    *offset += 1;
+    Ok(byte)
+}
+
+pub(crate) fn read_u16(buf: &[u8], offset: &mut usize) -> Result<u16, Error> {
+    Ok(((read_u8(buf, offset)? as u16) << 8) | read_u8(buf, offset)? as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn truncated_packets() {
+        let packets: &[&[u8]] = &[
+            // Subscribe topic without QoS
+            &[0x82, 0x05, 0x00, 0x01, 0x00, 0x01, b'a'],
+            // Suback without pid
+            &[0x90, 0x01, 0x00],
+            // Connect with protocol name but without level
+            &[0x10, 0x06, 0x00, 0x04, b'M', b'Q', b'T', b'T'],
+            // Connect without flags and keep alive
+            &[0x10, 0x07, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04],
+            // Connack without return code
+            &[0x20, 0x01, 0x00],
+            // Puback without pid
+            &[0x40, 0x01, 0x00],
+            // Publish topic exceeding the packet
+            &[0x32, 0x04, 0x00, 0x08, b'a', b'b', b'c', b'd'],
+            // Publish without pid
+            &[0x32, 0x03, 0x00, 0x01, b'a'],
+        ];
+        for packet in packets {
+            assert_eq!(
+                decode_slice(packet),
+                Err(Error::InvalidLength),
+                "{:?}",
+                packet
+            );
+        }
     }
-    let len = ((buf[*offset] as usize) << 8) | buf[*offset + 1] as usize;
 
-    *offset += 2;
-    if len > buf[*offset..].len() {
-        Err(Error::InvalidLength)
-    } else {
-        let bytes = &buf[*offset..*offset + len];
-        *offset += len;
-        Ok(bytes)
+    #[test]
+    fn packet_restricted_to_remaining_length() {
+        // Unsubscribe topic exceeding the remaining length of 4
+        let buf = [0xa2, 0x04, 0x00, 0x01, 0x00, 0x01, b'a', 0xc0, 0x00];
+        match decode_slice(&buf) {
+            Err(Error::InvalidLength) => {}
+            p => panic!("Unexpected result {:?}", p),
+        }
+
+        // Suback return codes are not part of the pid
+        let buf = [0x90, 0x03, 0x00, 0x01, 0x80, 0xc0, 0x00];
+        match decode_slice(&buf) {
+            Ok(Some(Packet::Suback(suback))) => {
+                assert_eq!(suback.pid.get(), 1);
+                assert!(suback.return_codes().eq([SubscribeReturnCodes::Failure]));
+            }
+            p => panic!("Unexpected result {:?}", p),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(buf in proptest::collection::vec(any::<u8>(), 0..512)) {
+            let _ = decode_slice(&buf);
+        }
+
+        #[test]
+        fn decode_valid_header_never_panics(
+            header in any::<u8>(),
+            body in proptest::collection::vec(any::<u8>(), 0..127),
+        ) {
+            let mut buf = std::vec![header, body.len() as u8];
+            buf.extend_from_slice(&body);
+            let _ = decode_slice(&buf);
+        }
     }
 }
@@ -110,7 +110,7 @@ pub fn encode_slice(packet: &Packet, buf: &mut [u8]) -> Result<usize, Error> {
 /// Check wether buffer has `len` bytes of write capacity left. Use this to return a clean
 /// Result::Err instead of panicking.
 pub(crate) fn check_remaining(buf: &mut [u8], offset: &mut usize, len: usize) -> Result<(), Error> {
-    if buf[*offset..].len() < len {
+    if buf.len().saturating_sub(*offset) < len {
         Err(Error::WriteZero)
     } else {
         Ok(())
@@ -153,7 +153,7 @@ pub(crate) fn write_length(buf: &mut [u8], offset: &mut usize, len: usize) -> Re
 }
 
 pub(crate) fn write_u8(buf: &mut [u8], offset: &mut usize, val: u8) -> Result<(), Error> {
-    buf[*offset] = val;
+    *buf.get_mut(*offset).ok_or(Error::WriteZero)? = val;
     *offset += 1;
     Ok(())
 }
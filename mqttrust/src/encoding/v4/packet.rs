@@ -85,6 +85,7 @@ impl<'a> Packet<'a> {
         }
     }
 
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         let variable_len = match self {
             Packet::Connect(c) => c.len(),
@@ -95,7 +96,7 @@ impl<'a> Packet<'a> {
             | Packet::Pubrel(_)
             | Packet::Pubcomp(_)
             | Packet::Unsuback(_) => PID_LEN,
-            Packet::Suback(_) => PID_LEN + 1,
+            Packet::Suback(s) => PID_LEN + s.return_codes.len(),
             Packet::Subscribe(s) => s.len(),
             Packet::Unsubscribe(u) => u.len(),
             Packet::Pingreq | Packet::Pingresp | Packet::Disconnect => 0,
@@ -150,3 +151,226 @@ pub enum PacketType {
     Pingresp,
     Disconnect,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::convert::TryFrom;
+    use proptest::{collection::vec, option, prelude::*};
+    use std::{string::String, vec::Vec};
+
+    fn pid() -> impl Strategy<Value = Pid> {
+        (1..=u16::MAX).prop_map(|pid| Pid::try_from(pid).unwrap())
+    }
+
+    fn qos() -> impl Strategy<Value = QoS> {
+        (0..3u8).prop_map(|qos| QoS::from_u8(qos).unwrap())
+    }
+
+    fn string() -> impl Strategy<Value = String> {
+        "\\PC{0,32}"
+    }
+
+    fn topic_name() -> impl Strategy<Value = String> {
+        "[^\\p{C}+#]{1,32}"
+    }
+
+    fn topic_filter() -> impl Strategy<Value = String> {
+        "([^\\p{C}+#/]{0,8}|\\+)(/([^\\p{C}+#/]{0,8}|\\+)){0,3}(/#)?|#"
+            .prop_filter("Topic filters are non-empty", |filter| !filter.is_empty())
+    }
+
+    fn bytes() -> impl Strategy<Value = Vec<u8>> {
+        vec(any::<u8>(), 0..64)
+    }
+
+    fn round_trip<'a>(packet: &Packet<'_>, buf: &'a mut [u8]) -> Packet<'a> {
+        let len = encode_slice(packet, buf).unwrap();
+        assert!(len <= packet.len());
+        decode_slice(&buf[..len]).unwrap().unwrap()
+    }
+
+    #[test]
+    fn empty_packets() {
+        let mut buf = [0u8; 8];
+        for packet in [Packet::Pingreq, Packet::Pingresp, Packet::Disconnect] {
+            assert_eq!(round_trip(&packet, &mut buf), packet);
+        }
+    }
+
+    #[test]
+    fn unknown_protocol() {
+        let mut packet = std::vec![0x10, 15, 0, 12];
+        packet.extend_from_slice(b"MQTTMQTTMQTT");
+        packet.push(4);
+        assert_eq!(
+            decode_slice(&packet),
+            Err(Error::InvalidProtocol("MQTTMQTTMQ".into(), 4))
+        );
+    }
+
+    #[test]
+    fn invalid_topics() {
+        let mut buf = [0u8; 64];
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            pid: None,
+            retain: false,
+            topic_name: "a/+",
+            payload: b"",
+        };
+        assert_eq!(
+            encode_slice(&publish.into(), &mut buf),
+            Err(Error::InvalidTopicName)
+        );
+
+        let topics = [SubscribeTopic {
+            topic_path: "a/#/b",
+            qos: QoS::AtMostOnce,
+        }];
+        assert_eq!(
+            encode_slice(&Subscribe::new(&topics).into(), &mut buf),
+            Err(Error::InvalidTopicFilter)
+        );
+        assert_eq!(
+            encode_slice(&Unsubscribe::new(&["foo+"]).into(), &mut buf),
+            Err(Error::InvalidTopicFilter)
+        );
+
+        // Decoding rejects them alike, such that decoded packets encode again
+        assert_eq!(
+            decode_slice(&[0x30, 4, 0, 2, b'a', b'+']),
+            Err(Error::InvalidTopicName)
+        );
+        assert_eq!(
+            decode_slice(&[0x82, 7, 0, 1, 0, 2, b'a', b'#', 0]),
+            Err(Error::InvalidTopicFilter)
+        );
+        assert_eq!(
+            decode_slice(&[0xa2, 6, 0, 1, 0, 2, b'a', b'+']),
+            Err(Error::InvalidTopicFilter)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn connect(
+            protocol in prop_oneof![Just(Protocol::MQTT311), Just(Protocol::MQIsdp)],
+            keep_alive in any::<u16>(),
+            client_id in string(),
+            clean_session in any::<bool>(),
+            last_will in option::of((topic_name(), bytes(), qos(), any::<bool>())),
+            username in option::of(string()),
+            password in option::of(bytes()),
+        ) {
+            let packet = Packet::Connect(Connect {
+                protocol,
+                keep_alive,
+                client_id: &client_id,
+                clean_session,
+                last_will: last_will.as_ref().map(|(topic, message, qos, retain)| LastWill {
+                    topic,
+                    message,
+                    qos: *qos,
+                    retain: *retain,
+                }),
+                username: username.as_deref(),
+                password: password.as_deref(),
+            });
+            let mut buf = [0u8; 1024];
+            prop_assert_eq!(round_trip(&packet, &mut buf), packet);
+        }
+
+        #[test]
+        fn connack(session_present in any::<bool>(), code in 0..6u8) {
+            let packet = Packet::Connack(Connack {
+                session_present,
+                code: ConnectReturnCode::from_u8(code).unwrap(),
+            });
+            let mut buf = [0u8; 8];
+            prop_assert_eq!(round_trip(&packet, &mut buf), packet);
+        }
+
+        #[test]
+        fn publish(
+            dup in any::<bool>(),
+            qos in qos(),
+            pid in pid(),
+            retain in any::<bool>(),
+            topic_name in topic_name(),
+            payload in vec(any::<u8>(), 0..1024),
+        ) {
+            let packet = Packet::Publish(Publish {
+                dup,
+                qos,
+                pid: if qos == QoS::AtMostOnce { None } else { Some(pid) },
+                retain,
+                topic_name: &topic_name,
+                payload: &payload,
+            });
+            let mut buf = [0u8; 2048];
+            prop_assert_eq!(round_trip(&packet, &mut buf), packet);
+        }
+
+        #[test]
+        fn acks(pid in pid()) {
+            let mut buf = [0u8; 8];
+            for packet in [
+                Packet::Puback(pid),
+                Packet::Pubrec(pid),
+                Packet::Pubrel(pid),
+                Packet::Pubcomp(pid),
+                Packet::Unsuback(pid),
+            ] {
+                prop_assert_eq!(round_trip(&packet, &mut buf), packet);
+            }
+        }
+
+        #[test]
+        fn subscribe(topics in vec((topic_filter(), qos()), 1..8)) {
+            let topics: Vec<_> = topics
+                .iter()
+                .map(|(topic_path, qos)| SubscribeTopic { topic_path, qos: *qos })
+                .collect();
+            let mut buf = [0u8; 1024];
+            match round_trip(&Subscribe::new(&topics).into(), &mut buf) {
+                Packet::Subscribe(subscribe) => {
+                    prop_assert_eq!(subscribe.pid(), Some(Pid::default()));
+                    prop_assert!(subscribe.topics().eq(topics.iter().cloned()));
+                }
+                p => prop_assert!(false, "Unexpected packet {:?}", p),
+            }
+        }
+
+        #[test]
+        fn unsubscribe(pid in pid(), topics in vec(topic_filter(), 1..8)) {
+            let topics: Vec<_> = topics.iter().map(String::as_str).collect();
+            let mut unsubscribe = Unsubscribe::new(&topics);
+            unsubscribe.pid = Some(pid);
+            let mut buf = [0u8; 1024];
+            match round_trip(&unsubscribe.into(), &mut buf) {
+                Packet::Unsubscribe(unsubscribe) => {
+                    prop_assert_eq!(unsubscribe.pid, Some(pid));
+                    prop_assert!(unsubscribe.topics().eq(topics.iter().cloned()));
+                }
+                p => prop_assert!(false, "Unexpected packet {:?}", p),
+            }
+        }
+
+        #[test]
+        fn suback(pid in pid(), codes in vec(prop_oneof![
+            qos().prop_map(SubscribeReturnCodes::Success),
+            Just(SubscribeReturnCodes::Failure),
+        ], 0..8)) {
+            let mut buf = [0u8; 16];
+            match round_trip(&Suback::new(pid, &codes).into(), &mut buf) {
+                Packet::Suback(suback) => {
+                    prop_assert_eq!(suback.pid, pid);
+                    prop_assert!(suback.return_codes().eq(codes.iter().cloned()));
+                }
+                p => prop_assert!(false, "Unexpected packet {:?}", p),
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use super::{decoder::*, encoder::*, *};
+use crate::topic::validate_topic_name;
 
 /// Publish packet ([MQTT 3.3]).
 ///
@@ -16,12 +17,11 @@ pub struct Publish<'a> {
 impl<'a> Publish<'a> {
     pub(crate) fn from_buffer(
         header: &Header,
-        remaining_len: usize,
         buf: &'a [u8],
         offset: &mut usize,
     ) -> Result<Self, Error> {
-        let payload_end = *offset + remaining_len;
         let topic_name = read_str(buf, offset)?;
+        validate_topic_name(topic_name)?;
 
         let (qos, pid) = match header.qos {
             QoS::AtMostOnce => (QoS::AtMostOnce, None),
@@ -29,13 +29,16 @@ impl<'a> Publish<'a> {
             QoS::ExactlyOnce => (QoS::ExactlyOnce, Some(Pid::from_buffer(buf, offset)?)),
         };
 
+        let payload = &buf[*offset..];
+        *offset = buf.len();
+
         Ok(Publish {
             dup: header.dup,
             qos,
             pid,
             retain: header.retain,
             topic_name,
-            payload: &buf[*offset..payload_end],
+            payload,
         })
     }
 
@@ -50,6 +53,8 @@ impl<'a> Publish<'a> {
     }
 
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        validate_topic_name(self.topic_name)?;
+
         // Header
         let mut header: u8 = match self.qos {
             QoS::AtMostOnce => 0b00110000,
@@ -71,15 +76,9 @@ impl<'a> Publish<'a> {
         // Topic
         write_string(buf, offset, self.topic_name)?;
 
-        // Pid to be overwritten later on
-        match self.qos {
-            QoS::AtMostOnce => (),
-            QoS::AtLeastOnce => {
-                write_u16(buf, offset, 0)?;
-            }
-            QoS::ExactlyOnce => {
-                write_u16(buf, offset, 0)?;
-            }
+        // Pid might be overwritten later on
+        if self.qos != QoS::AtMostOnce {
+            write_u16(buf, offset, self.pid.map(Pid::get).unwrap_or(0))?;
         }
 
         // Payload
@@ -1,6 +1,7 @@
 use core::marker::PhantomData;
 
 use super::{decoder::*, encoder::*, *};
+use crate::topic::validate_topic_filter;
 
 /// Subscribe topic.
 ///
@@ -18,8 +19,7 @@ impl<'a> FromBuffer<'a> for SubscribeTopic<'a> {
 
     fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self::Item, Error> {
         let topic_path = read_str(buf, offset)?;
-        let qos = QoS::from_u8(buf[*offset])?;
-        *offset += 1;
+        let qos = QoS::from_u8(read_u8(buf, offset)?)?;
         Ok(SubscribeTopic { topic_path, qos })
     }
 }
@@ -41,8 +41,9 @@ pub trait FromBuffer<'a> {
 ///
 /// [Suback] packets contain a `Vec` of those.
 ///
-/// [Suback]: struct.Subscribe.html
+/// [Suback]: struct.Suback.html
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
 pub enum SubscribeReturnCodes {
     Success(QoS),
     Failure,
@@ -52,8 +53,7 @@ impl<'a> FromBuffer<'a> for SubscribeReturnCodes {
     type Item = Self;
 
     fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self::Item, Error> {
-        let code = buf[*offset];
-        *offset += 1;
+        let code = read_u8(buf, offset)?;
 
         if code == 0x80 {
             Ok(SubscribeReturnCodes::Failure)
@@ -72,6 +72,20 @@ impl SubscribeReturnCodes {
     }
 }
 
+/// Parse and validate all items of a lazy list, consuming the rest of `buf`.
+pub(crate) fn read_list<'a, T>(buf: &'a [u8], offset: &mut usize) -> Result<List<'a, T>, Error>
+where
+    T: FromBuffer<'a>,
+{
+    let data = &buf[*offset..];
+    let mut pos = 0;
+    while pos < data.len() {
+        T::from_buffer(data, &mut pos)?;
+    }
+    *offset = buf.len();
+    Ok(List::Lazy(LazyList(data, PhantomData)))
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum List<'a, T> {
     Owned(&'a [T]),
@@ -95,6 +109,10 @@ where
             }
         }
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 impl<'a, T> IntoIterator for &'a List<'a, T>
@@ -114,7 +132,7 @@ where
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct LazyList<'a, T>(&'a [u8], PhantomData<T>);
+pub struct LazyList<'a, T>(pub(crate) &'a [u8], pub(crate) PhantomData<T>);
 
 pub struct ListIter<'a, T> {
     list: &'a List<'a, T>,
@@ -155,7 +173,7 @@ pub struct Subscribe<'a> {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Suback<'a> {
     pub pid: Pid,
-    pub return_codes: &'a [SubscribeReturnCodes],
+    pub return_codes: List<'a, SubscribeReturnCodes>,
 }
 
 /// Unsubscribe packet ([MQTT 3.10]).
@@ -183,18 +201,17 @@ impl<'a> Subscribe<'a> {
         self.pid
     }
 
-    pub(crate) fn from_buffer(
-        remaining_len: usize,
-        buf: &'a [u8],
-        offset: &mut usize,
-    ) -> Result<Self, Error> {
-        let payload_end = *offset + remaining_len;
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
         let pid = Pid::from_buffer(buf, offset)?;
 
-        Ok(Subscribe {
+        let subscribe = Subscribe {
             pid: Some(pid),
-            topics: List::Lazy(LazyList(&buf[*offset..payload_end], PhantomData)),
-        })
+            topics: read_list(buf, offset)?,
+        };
+        for topic in subscribe.topics() {
+            validate_topic_filter(topic.topic_path)?;
+        }
+        Ok(subscribe)
     }
 
     /// Length: pid(2) + topic.for_each(2+len + qos(1))
@@ -207,6 +224,10 @@ impl<'a> Subscribe<'a> {
     }
 
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        for topic in self.topics() {
+            validate_topic_filter(topic.topic_path)?;
+        }
+
         let header: u8 = 0b10000010;
         check_remaining(buf, offset, 1)?;
         write_u8(buf, offset, header)?;
@@ -242,18 +263,17 @@ impl<'a> Unsubscribe<'a> {
         self.pid
     }
 
-    pub(crate) fn from_buffer(
-        remaining_len: usize,
-        buf: &'a [u8],
-        offset: &mut usize,
-    ) -> Result<Self, Error> {
-        let payload_end = *offset + remaining_len;
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
         let pid = Pid::from_buffer(buf, offset)?;
 
-        Ok(Unsubscribe {
+        let unsubscribe = Unsubscribe {
             pid: Some(pid),
-            topics: List::Lazy(LazyList(&buf[*offset..payload_end], PhantomData)),
-        })
+            topics: read_list(buf, offset)?,
+        };
+        for topic in unsubscribe.topics() {
+            validate_topic_filter(topic)?;
+        }
+        Ok(unsubscribe)
     }
 
     /// Length: pid(2) + topic.for_each(2+len)
@@ -266,6 +286,10 @@ impl<'a> Unsubscribe<'a> {
     }
 
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        for topic in self.topics() {
+            validate_topic_filter(topic)?;
+        }
+
         let header: u8 = 0b10100010;
 
         check_remaining(buf, offset, 1)?;
@@ -284,23 +308,23 @@ impl<'a> Unsubscribe<'a> {
 }
 
 impl<'a> Suback<'a> {
-    pub(crate) fn from_buffer(
-        _remaining_len: usize,
-        buf: &'a [u8],
-        offset: &mut usize,
-    ) -> Result<Self, Error> {
-        // FIXME:
-        // let payload_end = *offset + remaining_len;
-        let pid = Pid::from_buffer(buf, offset)?;
+    pub fn new(pid: Pid, return_codes: &'a [SubscribeReturnCodes]) -> Self {
+        Self {
+            pid,
+            return_codes: List::Owned(return_codes),
+        }
+    }
 
-        // let mut return_codes = LimitedVec::new();
-        // while *offset < payload_end {
-        //     let _res = return_codes.push(SubscribeReturnCodes::from_buffer(buf, offset)?);
-        // }
+    pub fn return_codes(&self) -> impl Iterator<Item = SubscribeReturnCodes> + '_ {
+        self.return_codes.into_iter()
+    }
+
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let pid = Pid::from_buffer(buf, offset)?;
 
         Ok(Suback {
             pid,
-            return_codes: &[],
+            return_codes: read_list(buf, offset)?,
         })
     }
 
@@ -312,7 +336,7 @@ impl<'a> Suback<'a> {
 
         let write_len = write_length(buf, offset, length)? + 1;
         self.pid.to_buffer(buf, offset)?;
-        for rc in self.return_codes {
+        for rc in self.return_codes() {
             write_u8(buf, offset, rc.as_u8())?;
         }
         Ok(write_len)
@@ -1,4 +1,4 @@
-use super::encoder::write_u16;
+use super::{decoder::read_u16, encoder::write_u16};
 use core::{convert::TryFrom, fmt, num::NonZeroU16};
 
 #[cfg(feature = "derive")]
@@ -32,6 +32,29 @@ pub enum Error {
     InvalidLength,
     /// Trying to decode a non-utf8 string.
     InvalidString,
+    /// Tried to decode an unknown MQTT 5 reason code.
+    InvalidReasonCode(u8),
+    /// Tried to decode an unknown MQTT 5 property identifier.
+    InvalidProperty(u8),
+    /// Tried to encode or decode an empty topic name, or one containing
+    /// wildcards or the null character.
+    InvalidTopicName,
+    /// Tried to encode or decode an empty topic filter, one containing the
+    /// null character, or one with wildcards not occupying an entire level.
+    InvalidTopicFilter,
+}
+
+impl Error {
+    /// Unknown protocol, keeping as much of its name as fits.
+    pub(crate) fn invalid_protocol(name: &str, level: u8) -> Self {
+        let mut truncated = heapless::String::new();
+        for c in name.chars() {
+            if truncated.push(c).is_err() {
+                break;
+            }
+        }
+        Error::InvalidProtocol(truncated, level)
+    }
 }
 
 impl fmt::Display for Error {
@@ -84,9 +107,7 @@ impl Pid {
     }
 
     pub(crate) fn from_buffer(buf: &[u8], offset: &mut usize) -> Result<Self, Error> {
-        let pid = ((buf[*offset] as u16) << 8) | buf[*offset + 1] as u16;
-        *offset += 2;
-        Self::try_from(pid)
+        Self::try_from(read_u16(buf, offset)?)
     }
 
     pub fn to_buffer(self, buf: &mut [u8], offset: &mut usize) -> Result<(), Error> {
@@ -119,7 +140,7 @@ impl core::ops::Sub<u16> for Pid {
     /// Adding a `u16` to a `Pid` will wrap around and avoid 0.
     fn sub(self, u: u16) -> Pid {
         let n = match self.get().overflowing_sub(u) {
-            (0, _) => core::u16::MAX,
+            (0, _) => u16::MAX,
             (n, false) => n,
             (n, true) => n - 1,
         };
@@ -232,15 +253,15 @@ mod test {
         let t: Vec<(u16, u16, u16, u16)> = vec![
             (2, 1, 1, 3),
             (100, 1, 99, 101),
-            (1, 1, core::u16::MAX, 2),
-            (1, 2, core::u16::MAX - 1, 3),
-            (1, 3, core::u16::MAX - 2, 4),
-            (core::u16::MAX, 1, core::u16::MAX - 1, 1),
-            (core::u16::MAX, 2, core::u16::MAX - 2, 2),
-            (10, core::u16::MAX, 10, 10),
+            (1, 1, u16::MAX, 2),
+            (1, 2, u16::MAX - 1, 3),
+            (1, 3, u16::MAX - 2, 4),
+            (u16::MAX, 1, u16::MAX - 1, 1),
+            (u16::MAX, 2, u16::MAX - 2, 2),
+            (10, u16::MAX, 10, 10),
             (10, 0, 10, 10),
             (1, 0, 1, 1),
-            (core::u16::MAX, 0, core::u16::MAX, core::u16::MAX),
+            (u16::MAX, 0, u16::MAX, u16::MAX),
         ];
         for (cur, d, prev, next) in t {
             let sub = Pid::try_from(cur).unwrap() - d;
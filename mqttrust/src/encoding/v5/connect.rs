@@ -0,0 +1,213 @@
+use super::{decoder::*, encoder::*, *};
+use crate::topic::validate_topic_name;
+
+/// Protocol name and level of MQTT 5.0: "MQTT", 5.
+const PROTOCOL: [u8; 7] = [0u8, 4, b'M', b'Q', b'T', b'T', 5];
+
+/// Message that the server should publish when the client disconnects.
+///
+/// Sent by the client in the [Connect] packet. [MQTT 3.1.3.2].
+///
+/// [Connect]: struct.Connect.html
+/// [MQTT 3.1.3.2]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901060
+#[derive(Debug, Clone, PartialEq)]
+pub struct LastWill<'a> {
+    pub topic: &'a str,
+    pub message: &'a [u8],
+    pub qos: QoS,
+    pub retain: bool,
+    pub properties: Properties<'a>,
+}
+
+/// Connect packet ([MQTT 3.1]).
+///
+/// [MQTT 3.1]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901033
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connect<'a> {
+    pub keep_alive: u16,
+    pub client_id: &'a str,
+    pub clean_start: bool,
+    pub last_will: Option<LastWill<'a>>,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a [u8]>,
+    pub properties: Properties<'a>,
+}
+
+/// Connack packet ([MQTT 3.2]).
+///
+/// [MQTT 3.2]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901074
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connack<'a> {
+    pub session_present: bool,
+    pub reason_code: ReasonCode,
+    pub properties: Properties<'a>,
+}
+
+impl<'a> Connect<'a> {
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let protocol_name = read_str(buf, offset)?;
+        let protocol_level = read_u8(buf, offset)?;
+        if (protocol_name, protocol_level) != ("MQTT", 5) {
+            return Err(Error::invalid_protocol(protocol_name, protocol_level));
+        }
+
+        let connect_flags = read_u8(buf, offset)?;
+        let keep_alive = read_u16(buf, offset)?;
+        let properties = Properties::from_buffer(buf, offset)?;
+
+        let client_id = read_str(buf, offset)?;
+
+        let last_will = if connect_flags & 0b100 != 0 {
+            let will_properties = Properties::from_buffer(buf, offset)?;
+            let will_topic = read_str(buf, offset)?;
+            validate_topic_name(will_topic)?;
+            let will_message = read_bytes(buf, offset)?;
+            let will_qos = QoS::from_u8((connect_flags & 0b11000) >> 3)?;
+            Some(LastWill {
+                topic: will_topic,
+                message: will_message,
+                qos: will_qos,
+                retain: (connect_flags & 0b00100000) != 0,
+                properties: will_properties,
+            })
+        } else {
+            None
+        };
+
+        let username = if connect_flags & 0b10000000 != 0 {
+            Some(read_str(buf, offset)?)
+        } else {
+            None
+        };
+
+        let password = if connect_flags & 0b01000000 != 0 {
+            Some(read_bytes(buf, offset)?)
+        } else {
+            None
+        };
+
+        let clean_start = (connect_flags & 0b10) != 0;
+
+        Ok(Connect {
+            keep_alive,
+            client_id,
+            clean_start,
+            last_will,
+            username,
+            password,
+            properties,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        // NOTE: protocol_name(6) + protocol_level(1) + flags(1) + keep_alive(2)
+        let mut length: usize = PROTOCOL.len() + 1 + 2;
+        length += self.properties.len();
+        length += 2 + self.client_id.len();
+        if let Some(username) = self.username {
+            length += username.len();
+            length += 2;
+        };
+        if let Some(password) = self.password {
+            length += password.len();
+            length += 2;
+        };
+        if let Some(last_will) = &self.last_will {
+            length += last_will.properties.len();
+            length += last_will.message.len();
+            length += last_will.topic.len();
+            length += 4;
+        };
+        length
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        if let Some(last_will) = &self.last_will {
+            validate_topic_name(last_will.topic)?;
+        }
+
+        let header: u8 = 0b00010000;
+        let mut connect_flags: u8 = 0b00000000;
+        if self.clean_start {
+            connect_flags |= 0b10;
+        };
+        if self.username.is_some() {
+            connect_flags |= 0b10000000;
+        };
+        if self.password.is_some() {
+            connect_flags |= 0b01000000;
+        };
+        if let Some(last_will) = &self.last_will {
+            connect_flags |= 0b00000100;
+            connect_flags |= last_will.qos.as_u8() << 3;
+            if last_will.retain {
+                connect_flags |= 0b00100000;
+            };
+        };
+        let length = self.len();
+        check_remaining(buf, offset, length + 1)?;
+
+        // NOTE: putting data into buffer.
+        write_u8(buf, offset, header)?;
+
+        let write_len = write_length(buf, offset, length)? + 1;
+        for &byte in &PROTOCOL {
+            write_u8(buf, offset, byte)?;
+        }
+
+        write_u8(buf, offset, connect_flags)?;
+        write_u16(buf, offset, self.keep_alive)?;
+        self.properties.to_buffer(buf, offset)?;
+
+        write_string(buf, offset, self.client_id)?;
+
+        if let Some(last_will) = &self.last_will {
+            last_will.properties.to_buffer(buf, offset)?;
+            write_string(buf, offset, last_will.topic)?;
+            write_bytes(buf, offset, last_will.message)?;
+        };
+
+        if let Some(username) = self.username {
+            write_string(buf, offset, username)?;
+        };
+        if let Some(password) = self.password {
+            write_bytes(buf, offset, password)?;
+        };
+        // NOTE: END
+        Ok(write_len)
+    }
+}
+
+impl<'a> Connack<'a> {
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let flags = read_u8(buf, offset)?;
+        let reason_code = ReasonCode::from_u8(read_u8(buf, offset)?)?;
+        let properties = Properties::from_buffer(buf, offset)?;
+        Ok(Connack {
+            session_present: (flags & 0b1 == 1),
+            reason_code,
+            properties,
+        })
+    }
+
+    /// Length: flags(1) + reason_code(1) + properties
+    pub(crate) fn len(&self) -> usize {
+        2 + self.properties.len()
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        let header: u8 = 0b00100000;
+        let mut flags: u8 = 0b00000000;
+        if self.session_present {
+            flags |= 0b1;
+        };
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, header)?;
+
+        let write_len = write_length(buf, offset, self.len())? + 1;
+        write_u8(buf, offset, flags)?;
+        write_u8(buf, offset, self.reason_code.as_u8())?;
+        self.properties.to_buffer(buf, offset)?;
+        Ok(write_len)
+    }
+}
@@ -0,0 +1,237 @@
+use super::*;
+use core::convert::TryFrom;
+
+pub(crate) use crate::encoding::v4::decoder::{read_bytes, read_str, read_u16, read_u8};
+
+/// Decode a MQTT 5 [Packet] from a u8 slice.
+///
+/// Returns `Ok(None)` if the slice does not yet contain a full packet.
+///
+/// ```
+/// # use mqttrust::encoding::v5::*;
+/// // CONNACK with session present and a Receive Maximum of 10
+/// let buf = [0x20, 0x06, 0x01, 0x00, 0x03, 0x21, 0x00, 0x0A];
+/// match decode_slice(&buf).unwrap() {
+///     Some(Packet::Connack(connack)) => {
+///         assert!(connack.session_present);
+///         assert_eq!(connack.reason_code, ReasonCode::Success);
+///         assert_eq!(
+///             connack.properties.iter().next(),
+///             Some(Property::ReceiveMaximum(10))
+///         );
+///     }
+///     _ => panic!(),
+/// }
+/// ```
+///
+/// [Packet]: enum.Packet.html
+pub fn decode_slice(buf: &[u8]) -> Result<Option<Packet<'_>>, Error> {
+    let mut offset = 0;
+    if let Some((header, remaining_len)) = read_header(buf, &mut offset)? {
+        // Restrict the packet parsers to the bytes of this packet only
+        let buf = &buf[..offset + remaining_len];
+        let r = read_packet(header, buf, &mut offset)?;
+        if offset != buf.len() {
+            return Err(Error::InvalidLength);
+        }
+        Ok(Some(r))
+    } else {
+        // Don't have a full packet
+        Ok(None)
+    }
+}
+
+fn read_packet<'a>(header: Header, buf: &'a [u8], offset: &mut usize) -> Result<Packet<'a>, Error> {
+    Ok(match header.typ {
+        PacketType::Pingreq => Packet::Pingreq,
+        PacketType::Pingresp => Packet::Pingresp,
+        PacketType::Connect => Connect::from_buffer(buf, offset)?.into(),
+        PacketType::Connack => Connack::from_buffer(buf, offset)?.into(),
+        PacketType::Publish => Publish::from_buffer(&header, buf, offset)?.into(),
+        PacketType::Puback => Packet::Puback(Ack::from_buffer(buf, offset)?),
+        PacketType::Pubrec => Packet::Pubrec(Ack::from_buffer(buf, offset)?),
+        PacketType::Pubrel => Packet::Pubrel(Ack::from_buffer(buf, offset)?),
+        PacketType::Pubcomp => Packet::Pubcomp(Ack::from_buffer(buf, offset)?),
+        PacketType::Subscribe => Subscribe::from_buffer(buf, offset)?.into(),
+        PacketType::Suback => Suback::from_buffer(buf, offset)?.into(),
+        PacketType::Unsubscribe => Unsubscribe::from_buffer(buf, offset)?.into(),
+        PacketType::Unsuback => Unsuback::from_buffer(buf, offset)?.into(),
+        PacketType::Disconnect => Disconnect::from_buffer(buf, offset)?.into(),
+        PacketType::Auth => Auth::from_buffer(buf, offset)?.into(),
+    })
+}
+
+/// Read the parsed header and remaining_len from the buffer. Only return Some() and advance the
+/// buffer position if there is enough data in the buffer to read the full packet.
+pub fn read_header(buf: &[u8], offset: &mut usize) -> Result<Option<(Header, usize)>, Error> {
+    let mut len: usize = 0;
+    for pos in 0..=3 {
+        if let Some(&byte) = buf.get(*offset + pos + 1) {
+            len += (byte as usize & 0x7F) << (pos * 7);
+            if (byte & 0x80) == 0 {
+                // Continuation bit == 0, length is parsed
+                if buf.len() < *offset + 2 + pos + len {
+                    // Won't be able to read full packet
+                    return Ok(None);
+                }
+                // Parse header byte, skip past the header, and return
+                let header = Header::new(buf[*offset])?;
+                *offset += pos + 2;
+                return Ok(Some((header, len)));
+            }
+        } else {
+            // Couldn't read full length
+            return Ok(None);
+        }
+    }
+    // Continuation byte == 1 four times, that's illegal.
+    Err(Error::InvalidHeader)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub typ: PacketType,
+    pub dup: bool,
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+impl Header {
+    pub fn new(hd: u8) -> Result<Header, Error> {
+        let (typ, flags_ok) = match hd >> 4 {
+            1 => (PacketType::Connect, hd & 0b1111 == 0),
+            2 => (PacketType::Connack, hd & 0b1111 == 0),
+            3 => (PacketType::Publish, true),
+            4 => (PacketType::Puback, hd & 0b1111 == 0),
+            5 => (PacketType::Pubrec, hd & 0b1111 == 0),
+            6 => (PacketType::Pubrel, hd & 0b1111 == 0b0010),
+            7 => (PacketType::Pubcomp, hd & 0b1111 == 0),
+            8 => (PacketType::Subscribe, hd & 0b1111 == 0b0010),
+            9 => (PacketType::Suback, hd & 0b1111 == 0),
+            10 => (PacketType::Unsubscribe, hd & 0b1111 == 0b0010),
+            11 => (PacketType::Unsuback, hd & 0b1111 == 0),
+            12 => (PacketType::Pingreq, hd & 0b1111 == 0),
+            13 => (PacketType::Pingresp, hd & 0b1111 == 0),
+            14 => (PacketType::Disconnect, hd & 0b1111 == 0),
+            15 => (PacketType::Auth, hd & 0b1111 == 0),
+            _ => (PacketType::Connect, false),
+        };
+        if !flags_ok {
+            return Err(Error::InvalidHeader);
+        }
+        Ok(Header {
+            typ,
+            dup: hd & 0b1000 != 0,
+            qos: QoS::from_u8((hd & 0b110) >> 1)?,
+            retain: hd & 1 == 1,
+        })
+    }
+}
+
+pub(crate) fn read_u32(buf: &[u8], offset: &mut usize) -> Result<u32, Error> {
+    Ok(((read_u16(buf, offset)? as u32) << 16) | read_u16(buf, offset)? as u32)
+}
+
+pub(crate) fn read_pid(buf: &[u8], offset: &mut usize) -> Result<Pid, Error> {
+    Pid::try_from(read_u16(buf, offset)?)
+}
+
+/// https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901011
+pub(crate) fn read_variable_int(buf: &[u8], offset: &mut usize) -> Result<u32, Error> {
+    let mut value = 0;
+    for pos in 0..4 {
+        let byte = read_u8(buf, offset)?;
+        value |= ((byte & 0x7F) as u32) << (pos * 7);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::InvalidLength)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn connack_with_properties() {
+        let buf = [
+            0x20, 0x14, 0x00, 0x00, 0x11, 0x21, 0x00, 0x05, 0x27, 0x00, 0x00, 0x10, 0x00, 0x12,
+            0x00, 0x03, b'a', b'b', b'c', 0x13, 0x00, 0x3C,
+        ];
+        // Not a full packet yet
+        assert_eq!(decode_slice(&buf[..buf.len() - 1]), Ok(None));
+
+        let connack = match decode_slice(&buf).unwrap() {
+            Some(Packet::Connack(c)) => c,
+            p => panic!("Unexpected packet {:?}", p),
+        };
+        assert!(!connack.session_present);
+        assert_eq!(connack.reason_code, ReasonCode::Success);
+        let mut props = connack.properties.iter();
+        assert_eq!(props.next(), Some(Property::ReceiveMaximum(5)));
+        assert_eq!(props.next(), Some(Property::MaximumPacketSize(4096)));
+        assert_eq!(
+            props.next(),
+            Some(Property::AssignedClientIdentifier("abc"))
+        );
+        assert_eq!(props.next(), Some(Property::ServerKeepAlive(60)));
+        assert_eq!(props.next(), None);
+    }
+
+    #[test]
+    fn connack_refused() {
+        let buf = [0x20, 0x03, 0x00, 0x87, 0x00];
+        match decode_slice(&buf).unwrap() {
+            Some(Packet::Connack(c)) => assert_eq!(c.reason_code, ReasonCode::NotAuthorized),
+            p => panic!("Unexpected packet {:?}", p),
+        }
+    }
+
+    #[test]
+    fn invalid_property() {
+        let buf = [0x20, 0x05, 0x00, 0x00, 0x02, 0x7F, 0x00];
+        assert_eq!(decode_slice(&buf), Err(Error::InvalidProperty(0x7F)));
+
+        // Property length exceeds the packet
+        let buf = [0x20, 0x04, 0x00, 0x00, 0x05, 0x21, 0x00];
+        assert_eq!(decode_slice(&buf), Err(Error::InvalidLength));
+    }
+
+    #[test]
+    fn short_acks() {
+        let pid = Pid::try_from(10).unwrap();
+
+        // Remaining length 2: Reason code and properties omitted
+        let buf = [0x40, 0x02, 0x00, 0x0A];
+        assert_eq!(
+            decode_slice(&buf),
+            Ok(Some(Packet::Puback(Ack::new(pid, ReasonCode::Success))))
+        );
+
+        // Remaining length 3: Properties omitted
+        let buf = [0x50, 0x03, 0x00, 0x0A, 0x10];
+        assert_eq!(
+            decode_slice(&buf),
+            Ok(Some(Packet::Pubrec(Ack::new(
+                pid,
+                ReasonCode::NoMatchingSubscribers
+            ))))
+        );
+
+        // Remaining length 0: Normal disconnection
+        let buf = [0xE0, 0x00];
+        assert_eq!(
+            decode_slice(&buf),
+            Ok(Some(Packet::Disconnect(Disconnect::new(
+                ReasonCode::Success
+            ))))
+        );
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let buf = [0xD0, 0x01, 0x00];
+        assert_eq!(decode_slice(&buf), Err(Error::InvalidLength));
+    }
+}
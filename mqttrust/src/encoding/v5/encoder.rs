@@ -0,0 +1,104 @@
+use super::{Error, Packet};
+
+pub(crate) use crate::encoding::v4::encoder::{
+    check_remaining, write_bytes, write_length, write_string, write_u16, write_u8,
+};
+
+/// Encode a MQTT 5 [Packet] enum into a u8 slice.
+///
+/// ```
+/// # use mqttrust::encoding::v5::*;
+/// let properties = [Property::MessageExpiryInterval(60)];
+/// let packet = Publish {
+///    dup: false,
+///    qos: QoS::AtMostOnce,
+///    retain: false,
+///    topic_name: "test",
+///    payload: b"hello",
+///    pid: None,
+///    properties: Properties::new(&properties),
+/// }.into();
+///
+/// let mut buf = [0u8; 1024];
+/// let len = encode_slice(&packet, &mut buf).expect("failed encoding");
+/// assert_eq!(&buf[..len], &[0b00110000, 17,
+///                     0, 4, b't', b'e', b's', b't',
+///                     5, 0x02, 0, 0, 0, 60,
+///                     b'h', b'e', b'l', b'l', b'o']);
+/// ```
+///
+/// [Packet]: ../enum.Packet.html
+pub fn encode_slice(packet: &Packet, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut offset = 0;
+
+    match packet {
+        Packet::Connect(connect) => connect.to_buffer(buf, &mut offset),
+        Packet::Connack(connack) => connack.to_buffer(buf, &mut offset),
+        Packet::Publish(publish) => publish.to_buffer(buf, &mut offset),
+        Packet::Puback(ack) => ack.to_buffer(0b01000000, buf, &mut offset),
+        Packet::Pubrec(ack) => ack.to_buffer(0b01010000, buf, &mut offset),
+        Packet::Pubrel(ack) => ack.to_buffer(0b01100010, buf, &mut offset),
+        Packet::Pubcomp(ack) => ack.to_buffer(0b01110000, buf, &mut offset),
+        Packet::Subscribe(subscribe) => subscribe.to_buffer(buf, &mut offset),
+        Packet::Suback(suback) => suback.to_buffer(buf, &mut offset),
+        Packet::Unsubscribe(unsub) => unsub.to_buffer(buf, &mut offset),
+        Packet::Unsuback(unsuback) => unsuback.to_buffer(buf, &mut offset),
+        Packet::Pingreq => {
+            check_remaining(buf, &mut offset, 2)?;
+            let header: u8 = 0b11000000;
+            let length: u8 = 0;
+            write_u8(buf, &mut offset, header)?;
+            write_u8(buf, &mut offset, length)?;
+            Ok(2)
+        }
+        Packet::Pingresp => {
+            check_remaining(buf, &mut offset, 2)?;
+            let header: u8 = 0b11010000;
+            let length: u8 = 0;
+            write_u8(buf, &mut offset, header)?;
+            write_u8(buf, &mut offset, length)?;
+            Ok(2)
+        }
+        Packet::Disconnect(disconnect) => disconnect.to_buffer(buf, &mut offset),
+        Packet::Auth(auth) => auth.to_buffer(buf, &mut offset),
+    }
+}
+
+pub(crate) fn write_u32(buf: &mut [u8], offset: &mut usize, val: u32) -> Result<(), Error> {
+    write_u16(buf, offset, (val >> 16) as u16)?;
+    write_u16(buf, offset, (val & 0xFFFF) as u16)
+}
+
+/// https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901011
+pub(crate) fn write_variable_int(
+    buf: &mut [u8],
+    offset: &mut usize,
+    val: usize,
+) -> Result<(), Error> {
+    check_remaining(buf, offset, variable_int_len(val))?;
+    if val > 268_435_455 {
+        return Err(Error::InvalidLength);
+    }
+    let mut x = val;
+    loop {
+        let mut byte = (x % 128) as u8;
+        x /= 128;
+        if x > 0 {
+            byte |= 128;
+        }
+        write_u8(buf, offset, byte)?;
+        if x == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Number of bytes needed to encode `val` as a variable byte integer.
+pub(crate) fn variable_int_len(val: usize) -> usize {
+    match val {
+        0..=127 => 1,
+        128..=16383 => 2,
+        16384..=2097151 => 3,
+        _ => 4,
+    }
+}
@@ -0,0 +1,28 @@
+//! [MQTT 5.0] codec.
+//!
+//! Mirrors the layout of [`v4`](super::v4), but every packet carries the
+//! properties and reason codes introduced by MQTT 5.0. Primitive types such as
+//! [`Pid`], [`QoS`] and [`Error`] are shared with the v4 codec.
+//!
+//! [MQTT 5.0]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html
+
+pub mod connect;
+pub mod decoder;
+pub mod encoder;
+pub mod packet;
+pub mod property;
+pub mod publish;
+pub mod reason_code;
+pub mod subscribe;
+
+pub use super::v4::utils::{Error, Pid, QoS, QosPid};
+pub use {
+    connect::{Connack, Connect, LastWill},
+    decoder::decode_slice,
+    encoder::encode_slice,
+    packet::{Ack, Auth, Disconnect, Packet, PacketType},
+    property::{Properties, Property},
+    publish::Publish,
+    reason_code::ReasonCode,
+    subscribe::{RetainHandling, Suback, Subscribe, SubscribeTopic, Unsuback, Unsubscribe},
+};
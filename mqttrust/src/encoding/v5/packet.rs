@@ -0,0 +1,527 @@
+use super::{decoder::*, encoder::*, *};
+
+/// Maximum size of a fixed header: header byte(1) + remaining length(1-4)
+const FIXED_HEADER_LEN: usize = 5;
+const PID_LEN: usize = 2;
+
+/// Base enum for all MQTT 5 packet types.
+///
+/// This is the main type you'll be interacting with, as an output of [`decode_slice()`] and an input of
+/// [`encode_slice()`]. Most variants can be constructed directly without using methods.
+///
+/// ```
+/// # use mqttrust::encoding::v5::*;
+/// # use core::convert::TryFrom;
+/// // Simplest form
+/// let pkt = Packet::Connack(Connack { session_present: false,
+///                                     reason_code: ReasonCode::Success,
+///                                     properties: Properties::default() });
+/// // Using `Into` trait
+/// let publish = Publish { dup: false,
+///                         qos: QoS::AtMostOnce,
+///                         retain: false,
+///                         pid: None,
+///                         topic_name: "to/pic",
+///                         payload: b"payload",
+///                         properties: Properties::default() };
+/// let pkt: Packet = publish.into();
+/// // Acknowledgements
+/// let pkt = Packet::Puback(Ack::new(Pid::try_from(42).unwrap(), ReasonCode::Success));
+/// ```
+///
+/// [`encode_slice()`]: fn.encode_slice.html
+/// [`decode_slice()`]: fn.decode_slice.html
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet<'a> {
+    /// [MQTT 3.1](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901033)
+    Connect(Connect<'a>),
+    /// [MQTT 3.2](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901074)
+    Connack(Connack<'a>),
+    /// [MQTT 3.3](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901100)
+    Publish(Publish<'a>),
+    /// [MQTT 3.4](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901121)
+    Puback(Ack<'a>),
+    /// [MQTT 3.5](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901131)
+    Pubrec(Ack<'a>),
+    /// [MQTT 3.6](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901141)
+    Pubrel(Ack<'a>),
+    /// [MQTT 3.7](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901151)
+    Pubcomp(Ack<'a>),
+    /// [MQTT 3.8](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901161)
+    Subscribe(Subscribe<'a>),
+    /// [MQTT 3.9](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901171)
+    Suback(Suback<'a>),
+    /// [MQTT 3.10](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901179)
+    Unsubscribe(Unsubscribe<'a>),
+    /// [MQTT 3.11](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901187)
+    Unsuback(Unsuback<'a>),
+    /// [MQTT 3.12](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901195)
+    Pingreq,
+    /// [MQTT 3.13](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901200)
+    Pingresp,
+    /// [MQTT 3.14](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901205)
+    Disconnect(Disconnect<'a>),
+    /// [MQTT 3.15](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901217)
+    Auth(Auth<'a>),
+}
+
+impl<'a> Packet<'a> {
+    /// Return the packet type variant.
+    ///
+    /// This can be used for matching, categorising, debuging, etc. Most users will match directly
+    /// on `Packet` instead.
+    pub fn get_type(&self) -> PacketType {
+        match self {
+            Packet::Connect(_) => PacketType::Connect,
+            Packet::Connack(_) => PacketType::Connack,
+            Packet::Publish(_) => PacketType::Publish,
+            Packet::Puback(_) => PacketType::Puback,
+            Packet::Pubrec(_) => PacketType::Pubrec,
+            Packet::Pubrel(_) => PacketType::Pubrel,
+            Packet::Pubcomp(_) => PacketType::Pubcomp,
+            Packet::Subscribe(_) => PacketType::Subscribe,
+            Packet::Suback(_) => PacketType::Suback,
+            Packet::Unsubscribe(_) => PacketType::Unsubscribe,
+            Packet::Unsuback(_) => PacketType::Unsuback,
+            Packet::Pingreq => PacketType::Pingreq,
+            Packet::Pingresp => PacketType::Pingresp,
+            Packet::Disconnect(_) => PacketType::Disconnect,
+            Packet::Auth(_) => PacketType::Auth,
+        }
+    }
+
+    /// Upper bound of the encoded length of this packet.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        let variable_len = match self {
+            Packet::Connect(c) => c.len(),
+            Packet::Connack(c) => c.len(),
+            Packet::Publish(p) => p.len(),
+            Packet::Puback(a) | Packet::Pubrec(a) | Packet::Pubrel(a) | Packet::Pubcomp(a) => {
+                a.len()
+            }
+            Packet::Subscribe(s) => s.len(),
+            Packet::Suback(s) => s.len(),
+            Packet::Unsubscribe(u) => u.len(),
+            Packet::Unsuback(u) => u.len(),
+            Packet::Pingreq | Packet::Pingresp => 0,
+            Packet::Disconnect(d) => d.len(),
+            Packet::Auth(a) => a.len(),
+        };
+
+        FIXED_HEADER_LEN + variable_len
+    }
+}
+
+macro_rules! packet_from_borrowed {
+    ($($t:ident),+) => {
+        $(
+            impl<'a> From<$t<'a>> for Packet<'a> {
+                fn from(p: $t<'a>) -> Self {
+                    Packet::$t(p)
+                }
+            }
+        )+
+    }
+}
+
+packet_from_borrowed!(
+    Connect,
+    Connack,
+    Publish,
+    Subscribe,
+    Suback,
+    Unsubscribe,
+    Unsuback,
+    Disconnect,
+    Auth
+);
+
+/// Packet type variant, without the associated data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub enum PacketType {
+    Connect,
+    Connack,
+    Publish,
+    Puback,
+    Pubrec,
+    Pubrel,
+    Pubcomp,
+    Subscribe,
+    Suback,
+    Unsubscribe,
+    Unsuback,
+    Pingreq,
+    Pingresp,
+    Disconnect,
+    Auth,
+}
+
+/// Reason code and properties of a packet that may omit both.
+///
+/// [Disconnect] and [Auth] packets with a remaining length of 0 imply
+/// [`ReasonCode::Success`] and no properties.
+fn read_reason<'a>(
+    buf: &'a [u8],
+    offset: &mut usize,
+) -> Result<(ReasonCode, Properties<'a>), Error> {
+    if *offset == buf.len() {
+        return Ok((ReasonCode::Success, Properties::default()));
+    }
+    let reason_code = ReasonCode::from_u8(read_u8(buf, offset)?)?;
+    if *offset == buf.len() {
+        return Ok((reason_code, Properties::default()));
+    }
+    Ok((reason_code, Properties::from_buffer(buf, offset)?))
+}
+
+/// Length: reason_code(0/1) + properties(0/len)
+fn reason_len(reason_code: ReasonCode, properties: &Properties) -> usize {
+    let properties_len = properties.len();
+    match (reason_code, properties_len) {
+        // Empty properties are encoded as a single 0 length byte
+        (ReasonCode::Success, 1) => 0,
+        (_, 1) => 1,
+        (_, len) => 1 + len,
+    }
+}
+
+fn write_reason(
+    reason_code: ReasonCode,
+    properties: &Properties,
+    buf: &mut [u8],
+    offset: &mut usize,
+) -> Result<(), Error> {
+    match reason_len(reason_code, properties) {
+        0 => Ok(()),
+        1 => write_u8(buf, offset, reason_code.as_u8()),
+        _ => {
+            write_u8(buf, offset, reason_code.as_u8())?;
+            properties.to_buffer(buf, offset)
+        }
+    }
+}
+
+/// Acknowledgement of a publish flow, used by Puback, Pubrec, Pubrel and
+/// Pubcomp packets ([MQTT 3.4]).
+///
+/// [MQTT 3.4]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901121
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ack<'a> {
+    pub pid: Pid,
+    pub reason_code: ReasonCode,
+    pub properties: Properties<'a>,
+}
+
+impl<'a> Ack<'a> {
+    pub fn new(pid: Pid, reason_code: ReasonCode) -> Self {
+        Self {
+            pid,
+            reason_code,
+            properties: Properties::default(),
+        }
+    }
+
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let pid = read_pid(buf, offset)?;
+        let (reason_code, properties) = read_reason(buf, offset)?;
+        Ok(Ack {
+            pid,
+            reason_code,
+            properties,
+        })
+    }
+
+    /// Length: pid(2) + reason_code(0/1) + properties(0/len)
+    pub(crate) fn len(&self) -> usize {
+        PID_LEN + reason_len(self.reason_code, &self.properties)
+    }
+
+    pub(crate) fn to_buffer(
+        &self,
+        header: u8,
+        buf: &mut [u8],
+        offset: &mut usize,
+    ) -> Result<usize, Error> {
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, header)?;
+
+        let write_len = write_length(buf, offset, self.len())? + 1;
+        self.pid.to_buffer(buf, offset)?;
+        write_reason(self.reason_code, &self.properties, buf, offset)?;
+        Ok(write_len)
+    }
+}
+
+/// Disconnect packet ([MQTT 3.14]).
+///
+/// [MQTT 3.14]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901205
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disconnect<'a> {
+    pub reason_code: ReasonCode,
+    pub properties: Properties<'a>,
+}
+
+impl<'a> Disconnect<'a> {
+    pub fn new(reason_code: ReasonCode) -> Self {
+        Self {
+            reason_code,
+            properties: Properties::default(),
+        }
+    }
+
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let (reason_code, properties) = read_reason(buf, offset)?;
+        Ok(Disconnect {
+            reason_code,
+            properties,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        reason_len(self.reason_code, &self.properties)
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        let header: u8 = 0b11100000;
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, header)?;
+
+        let write_len = write_length(buf, offset, self.len())? + 1;
+        write_reason(self.reason_code, &self.properties, buf, offset)?;
+        Ok(write_len)
+    }
+}
+
+/// Auth packet ([MQTT 3.15]).
+///
+/// [MQTT 3.15]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901217
+#[derive(Debug, Clone, PartialEq)]
+pub struct Auth<'a> {
+    pub reason_code: ReasonCode,
+    pub properties: Properties<'a>,
+}
+
+impl<'a> Auth<'a> {
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let (reason_code, properties) = read_reason(buf, offset)?;
+        Ok(Auth {
+            reason_code,
+            properties,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        reason_len(self.reason_code, &self.properties)
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        let header: u8 = 0b11110000;
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, header)?;
+
+        let write_len = write_length(buf, offset, self.len())? + 1;
+        write_reason(self.reason_code, &self.properties, buf, offset)?;
+        Ok(write_len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::convert::TryFrom;
+
+    fn round_trip(packet: Packet<'_>) {
+        let mut buf = [0u8; 512];
+        let len = encode_slice(&packet, &mut buf).unwrap();
+        assert!(len <= packet.len());
+        assert_eq!(decode_slice(&buf[..len]), Ok(Some(packet)));
+    }
+
+    #[test]
+    fn connect() {
+        let properties = [
+            Property::SessionExpiryInterval(3600),
+            Property::ReceiveMaximum(20),
+            Property::UserProperty("key", "value"),
+        ];
+        let will_properties = [Property::WillDelayInterval(10)];
+        round_trip(Packet::Connect(Connect {
+            keep_alive: 60,
+            client_id: "client",
+            clean_start: true,
+            last_will: Some(LastWill {
+                topic: "will/topic",
+                message: b"gone",
+                qos: QoS::AtLeastOnce,
+                retain: true,
+                properties: Properties::new(&will_properties),
+            }),
+            username: Some("user"),
+            password: Some(b"secret"),
+            properties: Properties::new(&properties),
+        }));
+    }
+
+    #[test]
+    fn connack() {
+        let properties = [
+            Property::AssignedClientIdentifier("assigned"),
+            Property::TopicAliasMaximum(10),
+        ];
+        round_trip(Packet::Connack(Connack {
+            session_present: true,
+            reason_code: ReasonCode::Success,
+            properties: Properties::new(&properties),
+        }));
+    }
+
+    #[test]
+    fn publish() {
+        let properties = [
+            Property::TopicAlias(3),
+            Property::CorrelationData(b"1234"),
+            Property::SubscriptionIdentifier(268_435_455),
+        ];
+        round_trip(Packet::Publish(Publish {
+            dup: true,
+            qos: QoS::ExactlyOnce,
+            pid: Some(Pid::try_from(7).unwrap()),
+            retain: false,
+            topic_name: "a/b",
+            payload: b"payload",
+            properties: Properties::new(&properties),
+        }));
+    }
+
+    #[test]
+    fn invalid_topics() {
+        // Wildcards are rejected when decoding, as they are when encoding
+        assert_eq!(
+            decode_slice(&[0x30, 5, 0, 2, b'a', b'+', 0]),
+            Err(Error::InvalidTopicName)
+        );
+        assert_eq!(
+            decode_slice(&[0x82, 8, 0, 1, 0, 0, 2, b'a', b'#', 0]),
+            Err(Error::InvalidTopicFilter)
+        );
+
+        // An empty topic name refers to a topic alias
+        let properties = [Property::TopicAlias(3)];
+        round_trip(Packet::Publish(Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            pid: None,
+            retain: false,
+            topic_name: "",
+            payload: b"payload",
+            properties: Properties::new(&properties),
+        }));
+    }
+
+    #[test]
+    fn acks() {
+        let pid = Pid::try_from(1234).unwrap();
+        let properties = [Property::ReasonString("because")];
+        round_trip(Packet::Puback(Ack::new(pid, ReasonCode::Success)));
+        round_trip(Packet::Pubrec(Ack::new(pid, ReasonCode::QuotaExceeded)));
+        round_trip(Packet::Pubrel(Ack {
+            pid,
+            reason_code: ReasonCode::PacketIdentifierNotFound,
+            properties: Properties::new(&properties),
+        }));
+        round_trip(Packet::Pubcomp(Ack {
+            pid,
+            reason_code: ReasonCode::Success,
+            properties: Properties::new(&properties),
+        }));
+    }
+
+    #[test]
+    fn subscribe() {
+        let topics = [
+            SubscribeTopic::new("a/+", QoS::AtLeastOnce),
+            SubscribeTopic {
+                topic_path: "b/#",
+                qos: QoS::ExactlyOnce,
+                no_local: true,
+                retain_as_published: true,
+                retain_handling: RetainHandling::DoNotSend,
+            },
+        ];
+        let properties = [Property::SubscriptionIdentifier(42)];
+        let subscribe = Subscribe::with_properties(&topics, Properties::new(&properties));
+
+        let mut buf = [0u8; 128];
+        let len = encode_slice(&subscribe.into(), &mut buf).unwrap();
+        match decode_slice(&buf[..len]).unwrap() {
+            Some(Packet::Subscribe(s)) => {
+                assert_eq!(s.pid(), Some(Pid::new()));
+                assert_eq!(s.properties, Properties::new(&properties));
+                assert!(s.topics().eq(topics.iter().cloned()));
+            }
+            p => panic!("Unexpected packet {:?}", p),
+        }
+    }
+
+    #[test]
+    fn suback() {
+        let pid = Pid::try_from(3).unwrap();
+        let codes = [
+            ReasonCode::GrantedQoS1,
+            ReasonCode::Success,
+            ReasonCode::NotAuthorized,
+        ];
+
+        let mut buf = [0u8; 128];
+        let len = encode_slice(&Suback::new(pid, &codes).into(), &mut buf).unwrap();
+        match decode_slice(&buf[..len]).unwrap() {
+            Some(Packet::Suback(s)) => {
+                assert_eq!(s.pid, pid);
+                assert!(s.reason_codes().eq(codes.iter().cloned()));
+            }
+            p => panic!("Unexpected packet {:?}", p),
+        }
+
+        let len = encode_slice(&Unsuback::new(pid, &codes[..1]).into(), &mut buf).unwrap();
+        match decode_slice(&buf[..len]).unwrap() {
+            Some(Packet::Unsuback(s)) => {
+                assert_eq!(s.pid, pid);
+                assert!(s.reason_codes().eq(codes[..1].iter().cloned()));
+            }
+            p => panic!("Unexpected packet {:?}", p),
+        }
+    }
+
+    #[test]
+    fn unsubscribe() {
+        let topics = ["a/b", "c/d"];
+        let mut buf = [0u8; 128];
+        let len = encode_slice(&Unsubscribe::new(&topics).into(), &mut buf).unwrap();
+        match decode_slice(&buf[..len]).unwrap() {
+            Some(Packet::Unsubscribe(u)) => {
+                assert!(u.topics().eq(topics.iter().cloned()));
+            }
+            p => panic!("Unexpected packet {:?}", p),
+        }
+    }
+
+    #[test]
+    fn disconnect_and_auth() {
+        let properties = [
+            Property::AuthenticationMethod("SCRAM-SHA-1"),
+            Property::AuthenticationData(b"data"),
+        ];
+        round_trip(Packet::Disconnect(Disconnect::new(ReasonCode::Success)));
+        round_trip(Packet::Disconnect(Disconnect::new(
+            ReasonCode::DisconnectWithWillMessage,
+        )));
+        round_trip(Packet::Auth(Auth {
+            reason_code: ReasonCode::ContinueAuthentication,
+            properties: Properties::new(&properties),
+        }));
+        round_trip(Packet::Pingreq);
+        round_trip(Packet::Pingresp);
+    }
+}
@@ -0,0 +1,259 @@
+use core::marker::PhantomData;
+
+use super::{decoder::*, encoder::*, *};
+use crate::encoding::v4::subscribe::{FromBuffer, LazyList, List};
+
+/// MQTT 5 property ([MQTT 2.2.2]).
+///
+/// [MQTT 2.2.2]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901027
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Property<'a> {
+    PayloadFormatIndicator(u8),
+    MessageExpiryInterval(u32),
+    ContentType(&'a str),
+    ResponseTopic(&'a str),
+    CorrelationData(&'a [u8]),
+    SubscriptionIdentifier(u32),
+    SessionExpiryInterval(u32),
+    AssignedClientIdentifier(&'a str),
+    ServerKeepAlive(u16),
+    AuthenticationMethod(&'a str),
+    AuthenticationData(&'a [u8]),
+    RequestProblemInformation(u8),
+    WillDelayInterval(u32),
+    RequestResponseInformation(u8),
+    ResponseInformation(&'a str),
+    ServerReference(&'a str),
+    ReasonString(&'a str),
+    ReceiveMaximum(u16),
+    TopicAliasMaximum(u16),
+    TopicAlias(u16),
+    MaximumQoS(u8),
+    RetainAvailable(u8),
+    UserProperty(&'a str, &'a str),
+    MaximumPacketSize(u32),
+    WildcardSubscriptionAvailable(u8),
+    SubscriptionIdentifierAvailable(u8),
+    SharedSubscriptionAvailable(u8),
+}
+
+impl<'a> Property<'a> {
+    /// Property identifier.
+    pub fn id(&self) -> u8 {
+        match self {
+            Property::PayloadFormatIndicator(_) => 0x01,
+            Property::MessageExpiryInterval(_) => 0x02,
+            Property::ContentType(_) => 0x03,
+            Property::ResponseTopic(_) => 0x08,
+            Property::CorrelationData(_) => 0x09,
+            Property::SubscriptionIdentifier(_) => 0x0B,
+            Property::SessionExpiryInterval(_) => 0x11,
+            Property::AssignedClientIdentifier(_) => 0x12,
+            Property::ServerKeepAlive(_) => 0x13,
+            Property::AuthenticationMethod(_) => 0x15,
+            Property::AuthenticationData(_) => 0x16,
+            Property::RequestProblemInformation(_) => 0x17,
+            Property::WillDelayInterval(_) => 0x18,
+            Property::RequestResponseInformation(_) => 0x19,
+            Property::ResponseInformation(_) => 0x1A,
+            Property::ServerReference(_) => 0x1C,
+            Property::ReasonString(_) => 0x1F,
+            Property::ReceiveMaximum(_) => 0x21,
+            Property::TopicAliasMaximum(_) => 0x22,
+            Property::TopicAlias(_) => 0x23,
+            Property::MaximumQoS(_) => 0x24,
+            Property::RetainAvailable(_) => 0x25,
+            Property::UserProperty(_, _) => 0x26,
+            Property::MaximumPacketSize(_) => 0x27,
+            Property::WildcardSubscriptionAvailable(_) => 0x28,
+            Property::SubscriptionIdentifierAvailable(_) => 0x29,
+            Property::SharedSubscriptionAvailable(_) => 0x2A,
+        }
+    }
+
+    /// Length: identifier(1) + value
+    pub(crate) fn len(&self) -> usize {
+        1 + match self {
+            Property::PayloadFormatIndicator(_)
+            | Property::RequestProblemInformation(_)
+            | Property::RequestResponseInformation(_)
+            | Property::MaximumQoS(_)
+            | Property::RetainAvailable(_)
+            | Property::WildcardSubscriptionAvailable(_)
+            | Property::SubscriptionIdentifierAvailable(_)
+            | Property::SharedSubscriptionAvailable(_) => 1,
+            Property::ServerKeepAlive(_)
+            | Property::ReceiveMaximum(_)
+            | Property::TopicAliasMaximum(_)
+            | Property::TopicAlias(_) => 2,
+            Property::MessageExpiryInterval(_)
+            | Property::SessionExpiryInterval(_)
+            | Property::WillDelayInterval(_)
+            | Property::MaximumPacketSize(_) => 4,
+            Property::SubscriptionIdentifier(id) => variable_int_len(*id as usize),
+            Property::ContentType(s)
+            | Property::ResponseTopic(s)
+            | Property::AssignedClientIdentifier(s)
+            | Property::AuthenticationMethod(s)
+            | Property::ResponseInformation(s)
+            | Property::ServerReference(s)
+            | Property::ReasonString(s) => 2 + s.len(),
+            Property::CorrelationData(b) | Property::AuthenticationData(b) => 2 + b.len(),
+            Property::UserProperty(k, v) => 4 + k.len() + v.len(),
+        }
+    }
+
+    pub(crate) fn to_buffer(self, buf: &mut [u8], offset: &mut usize) -> Result<(), Error> {
+        write_u8(buf, offset, self.id())?;
+        match self {
+            Property::PayloadFormatIndicator(v)
+            | Property::RequestProblemInformation(v)
+            | Property::RequestResponseInformation(v)
+            | Property::MaximumQoS(v)
+            | Property::RetainAvailable(v)
+            | Property::WildcardSubscriptionAvailable(v)
+            | Property::SubscriptionIdentifierAvailable(v)
+            | Property::SharedSubscriptionAvailable(v) => write_u8(buf, offset, v),
+            Property::ServerKeepAlive(v)
+            | Property::ReceiveMaximum(v)
+            | Property::TopicAliasMaximum(v)
+            | Property::TopicAlias(v) => write_u16(buf, offset, v),
+            Property::MessageExpiryInterval(v)
+            | Property::SessionExpiryInterval(v)
+            | Property::WillDelayInterval(v)
+            | Property::MaximumPacketSize(v) => write_u32(buf, offset, v),
+            Property::SubscriptionIdentifier(v) => write_variable_int(buf, offset, v as usize),
+            Property::ContentType(s)
+            | Property::ResponseTopic(s)
+            | Property::AssignedClientIdentifier(s)
+            | Property::AuthenticationMethod(s)
+            | Property::ResponseInformation(s)
+            | Property::ServerReference(s)
+            | Property::ReasonString(s) => write_string(buf, offset, s),
+            Property::CorrelationData(b) | Property::AuthenticationData(b) => {
+                write_bytes(buf, offset, b)
+            }
+            Property::UserProperty(k, v) => {
+                write_string(buf, offset, k)?;
+                write_string(buf, offset, v)
+            }
+        }
+    }
+}
+
+impl<'a> FromBuffer<'a> for Property<'a> {
+    type Item = Self;
+
+    fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self::Item, Error> {
+        Ok(match read_u8(buf, offset)? {
+            0x01 => Property::PayloadFormatIndicator(read_u8(buf, offset)?),
+            0x02 => Property::MessageExpiryInterval(read_u32(buf, offset)?),
+            0x03 => Property::ContentType(read_str(buf, offset)?),
+            0x08 => Property::ResponseTopic(read_str(buf, offset)?),
+            0x09 => Property::CorrelationData(read_bytes(buf, offset)?),
+            0x0B => Property::SubscriptionIdentifier(read_variable_int(buf, offset)?),
+            0x11 => Property::SessionExpiryInterval(read_u32(buf, offset)?),
+            0x12 => Property::AssignedClientIdentifier(read_str(buf, offset)?),
+            0x13 => Property::ServerKeepAlive(read_u16(buf, offset)?),
+            0x15 => Property::AuthenticationMethod(read_str(buf, offset)?),
+            0x16 => Property::AuthenticationData(read_bytes(buf, offset)?),
+            0x17 => Property::RequestProblemInformation(read_u8(buf, offset)?),
+            0x18 => Property::WillDelayInterval(read_u32(buf, offset)?),
+            0x19 => Property::RequestResponseInformation(read_u8(buf, offset)?),
+            0x1A => Property::ResponseInformation(read_str(buf, offset)?),
+            0x1C => Property::ServerReference(read_str(buf, offset)?),
+            0x1F => Property::ReasonString(read_str(buf, offset)?),
+            0x21 => Property::ReceiveMaximum(read_u16(buf, offset)?),
+            0x22 => Property::TopicAliasMaximum(read_u16(buf, offset)?),
+            0x23 => Property::TopicAlias(read_u16(buf, offset)?),
+            0x24 => Property::MaximumQoS(read_u8(buf, offset)?),
+            0x25 => Property::RetainAvailable(read_u8(buf, offset)?),
+            0x26 => Property::UserProperty(read_str(buf, offset)?, read_str(buf, offset)?),
+            0x27 => Property::MaximumPacketSize(read_u32(buf, offset)?),
+            0x28 => Property::WildcardSubscriptionAvailable(read_u8(buf, offset)?),
+            0x29 => Property::SubscriptionIdentifierAvailable(read_u8(buf, offset)?),
+            0x2A => Property::SharedSubscriptionAvailable(read_u8(buf, offset)?),
+            id => return Err(Error::InvalidProperty(id)),
+        })
+    }
+}
+
+/// Properties of a MQTT 5 packet.
+///
+/// Either borrowed from a slice of [`Property`] when encoding, or lazily
+/// iterated from the packet bytes when decoding. Decoded properties are
+/// validated up front, so iterating them never stops short.
+#[derive(Debug, Clone)]
+pub struct Properties<'a>(List<'a, Property<'a>>);
+
+impl<'a> Properties<'a> {
+    pub fn new(properties: &'a [Property<'a>]) -> Self {
+        Properties(List::Owned(properties))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Property<'_>> {
+        (&self.0).into_iter()
+    }
+
+    /// Length of the encoded properties, excluding the property length itself.
+    fn data_len(&self) -> usize {
+        match &self.0 {
+            List::Owned(properties) => properties.iter().map(Property::len).sum(),
+            List::Lazy(data) => data.0.len(),
+        }
+    }
+
+    /// Length: property length(1-4) + properties
+    pub(crate) fn len(&self) -> usize {
+        let data_len = self.data_len();
+        variable_int_len(data_len) + data_len
+    }
+
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let len = read_variable_int(buf, offset)? as usize;
+        let end = *offset + len;
+        if end > buf.len() {
+            return Err(Error::InvalidLength);
+        }
+        let data = &buf[*offset..end];
+
+        // Validate all properties, such that lazy iteration is infallible
+        let mut pos = 0;
+        while pos < data.len() {
+            Property::from_buffer(data, &mut pos)?;
+        }
+
+        *offset = end;
+        Ok(Properties(List::Lazy(LazyList(data, PhantomData))))
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<(), Error> {
+        let data_len = self.data_len();
+        write_variable_int(buf, offset, data_len)?;
+        check_remaining(buf, offset, data_len)?;
+        match &self.0 {
+            List::Owned(properties) => {
+                for property in properties.iter() {
+                    property.to_buffer(buf, offset)?;
+                }
+            }
+            List::Lazy(data) => {
+                buf[*offset..*offset + data_len].copy_from_slice(data.0);
+                *offset += data_len;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Default for Properties<'a> {
+    fn default() -> Self {
+        Properties::new(&[])
+    }
+}
+
+impl<'a> PartialEq for Properties<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
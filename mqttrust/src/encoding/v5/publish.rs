@@ -0,0 +1,103 @@
+use super::{decoder::*, encoder::*, *};
+use crate::topic::validate_topic_name;
+
+/// Publish packet ([MQTT 3.3]).
+///
+/// [MQTT 3.3]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901100
+#[derive(Debug, Clone, PartialEq)]
+pub struct Publish<'a> {
+    pub dup: bool,
+    pub qos: QoS,
+    pub pid: Option<Pid>,
+    pub retain: bool,
+    pub topic_name: &'a str,
+    pub payload: &'a [u8],
+    pub properties: Properties<'a>,
+}
+
+impl<'a> Publish<'a> {
+    pub(crate) fn from_buffer(
+        header: &Header,
+        buf: &'a [u8],
+        offset: &mut usize,
+    ) -> Result<Self, Error> {
+        let topic_name = read_str(buf, offset)?;
+        // An empty topic name refers to a topic alias
+        if !topic_name.is_empty() {
+            validate_topic_name(topic_name)?;
+        }
+
+        let (qos, pid) = match header.qos {
+            QoS::AtMostOnce => (QoS::AtMostOnce, None),
+            QoS::AtLeastOnce => (QoS::AtLeastOnce, Some(read_pid(buf, offset)?)),
+            QoS::ExactlyOnce => (QoS::ExactlyOnce, Some(read_pid(buf, offset)?)),
+        };
+
+        let properties = Properties::from_buffer(buf, offset)?;
+
+        let payload = &buf[*offset..];
+        *offset = buf.len();
+
+        Ok(Publish {
+            dup: header.dup,
+            qos,
+            pid,
+            retain: header.retain,
+            topic_name,
+            payload,
+            properties,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        // Length: topic (2+len) + pid (0/2) + properties + payload (len)
+        2 + self.topic_name.len()
+            + match self.qos {
+                QoS::AtMostOnce => 0,
+                _ => 2,
+            }
+            + self.properties.len()
+            + self.payload.len()
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        // An empty topic name refers to a topic alias
+        if !self.topic_name.is_empty() {
+            validate_topic_name(self.topic_name)?;
+        }
+
+        // Header
+        let mut header: u8 = match self.qos {
+            QoS::AtMostOnce => 0b00110000,
+            QoS::AtLeastOnce => 0b00110010,
+            QoS::ExactlyOnce => 0b00110100,
+        };
+        if self.dup {
+            header |= 0b00001000_u8;
+        };
+        if self.retain {
+            header |= 0b00000001_u8;
+        };
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, header)?;
+
+        let length = self.len();
+        let write_len = write_length(buf, offset, length)? + 1;
+
+        // Topic
+        write_string(buf, offset, self.topic_name)?;
+
+        // Pid might be overwritten later on
+        if self.qos != QoS::AtMostOnce {
+            write_u16(buf, offset, self.pid.map(Pid::get).unwrap_or(0))?;
+        }
+
+        self.properties.to_buffer(buf, offset)?;
+
+        // Payload
+        buf[*offset..*offset + self.payload.len()].copy_from_slice(self.payload);
+        *offset += self.payload.len();
+
+        Ok(write_len)
+    }
+}
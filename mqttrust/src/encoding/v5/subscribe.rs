@@ -0,0 +1,361 @@
+use super::{decoder::*, encoder::*, *};
+use crate::encoding::v4::subscribe::{read_list, FromBuffer, List};
+use crate::topic::validate_topic_filter;
+
+/// Retain handling subscription option ([MQTT 3.8.3.1]).
+///
+/// [MQTT 3.8.3.1]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901169
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub enum RetainHandling {
+    /// Send retained messages at the time of the subscribe.
+    SendAtSubscribe,
+    /// Send retained messages at subscribe only if the subscription does not currently exist.
+    SendAtNewSubscribe,
+    /// Do not send retained messages at the time of the subscribe.
+    DoNotSend,
+}
+
+impl RetainHandling {
+    pub(crate) fn as_u8(&self) -> u8 {
+        match *self {
+            RetainHandling::SendAtSubscribe => 0,
+            RetainHandling::SendAtNewSubscribe => 1,
+            RetainHandling::DoNotSend => 2,
+        }
+    }
+
+    pub(crate) fn from_u8(byte: u8) -> Result<RetainHandling, Error> {
+        match byte {
+            0 => Ok(RetainHandling::SendAtSubscribe),
+            1 => Ok(RetainHandling::SendAtNewSubscribe),
+            2 => Ok(RetainHandling::DoNotSend),
+            _ => Err(Error::InvalidHeader),
+        }
+    }
+}
+
+/// Subscribe topic with its subscription options.
+///
+/// [Subscribe] packets contain a `Vec` of those.
+///
+/// [Subscribe]: struct.Subscribe.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscribeTopic<'a> {
+    pub topic_path: &'a str,
+    pub qos: QoS,
+    pub no_local: bool,
+    pub retain_as_published: bool,
+    pub retain_handling: RetainHandling,
+}
+
+impl<'a> SubscribeTopic<'a> {
+    /// Subscribe topic with default subscription options.
+    pub fn new(topic_path: &'a str, qos: QoS) -> Self {
+        Self {
+            topic_path,
+            qos,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: RetainHandling::SendAtSubscribe,
+        }
+    }
+
+    fn options(&self) -> u8 {
+        let mut options = self.qos.as_u8();
+        if self.no_local {
+            options |= 0b0000_0100;
+        }
+        if self.retain_as_published {
+            options |= 0b0000_1000;
+        }
+        options | self.retain_handling.as_u8() << 4
+    }
+}
+
+impl<'a> FromBuffer<'a> for SubscribeTopic<'a> {
+    type Item = Self;
+
+    fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self::Item, Error> {
+        let topic_path = read_str(buf, offset)?;
+        let options = read_u8(buf, offset)?;
+        if options & 0b1100_0000 != 0 {
+            return Err(Error::InvalidHeader);
+        }
+        Ok(SubscribeTopic {
+            topic_path,
+            qos: QoS::from_u8(options & 0b11)?,
+            no_local: options & 0b0000_0100 != 0,
+            retain_as_published: options & 0b0000_1000 != 0,
+            retain_handling: RetainHandling::from_u8((options >> 4) & 0b11)?,
+        })
+    }
+}
+
+/// Subscribe packet ([MQTT 3.8]).
+///
+/// [MQTT 3.8]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901161
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subscribe<'a> {
+    pid: Option<Pid>,
+    pub properties: Properties<'a>,
+    topics: List<'a, SubscribeTopic<'a>>,
+}
+
+/// Suback packet ([MQTT 3.9]).
+///
+/// [MQTT 3.9]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901171
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suback<'a> {
+    pub pid: Pid,
+    pub properties: Properties<'a>,
+    pub reason_codes: List<'a, ReasonCode>,
+}
+
+/// Unsubscribe packet ([MQTT 3.10]).
+///
+/// [MQTT 3.10]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901179
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unsubscribe<'a> {
+    pub pid: Option<Pid>,
+    pub properties: Properties<'a>,
+    pub topics: List<'a, &'a str>,
+}
+
+/// Unsuback packet ([MQTT 3.11]).
+///
+/// [MQTT 3.11]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901187
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unsuback<'a> {
+    pub pid: Pid,
+    pub properties: Properties<'a>,
+    pub reason_codes: List<'a, ReasonCode>,
+}
+
+impl<'a> Subscribe<'a> {
+    pub fn new(topics: &'a [SubscribeTopic<'a>]) -> Self {
+        Self::with_properties(topics, Properties::default())
+    }
+
+    pub fn with_properties(topics: &'a [SubscribeTopic<'a>], properties: Properties<'a>) -> Self {
+        Self {
+            pid: None,
+            properties,
+            topics: List::Owned(topics),
+        }
+    }
+
+    pub fn topics(&self) -> impl Iterator<Item = SubscribeTopic<'_>> {
+        self.topics.into_iter()
+    }
+
+    pub fn pid(&self) -> Option<Pid> {
+        self.pid
+    }
+
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let pid = read_pid(buf, offset)?;
+        let properties = Properties::from_buffer(buf, offset)?;
+
+        let subscribe = Subscribe {
+            pid: Some(pid),
+            properties,
+            topics: read_list(buf, offset)?,
+        };
+        for topic in subscribe.topics() {
+            validate_topic_filter(topic.topic_path)?;
+        }
+        Ok(subscribe)
+    }
+
+    /// Length: pid(2) + properties + topic.for_each(2+len + options(1))
+    pub(crate) fn len(&self) -> usize {
+        let mut length = 2 + self.properties.len();
+        for topic in self.topics() {
+            length += topic.topic_path.len() + 2 + 1;
+        }
+        length
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        for topic in self.topics() {
+            validate_topic_filter(topic.topic_path)?;
+        }
+
+        let header: u8 = 0b10000010;
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, header)?;
+
+        let write_len = write_length(buf, offset, self.len())? + 1;
+
+        // Pid
+        self.pid.unwrap_or_default().to_buffer(buf, offset)?;
+
+        self.properties.to_buffer(buf, offset)?;
+
+        // Topics
+        for topic in self.topics() {
+            write_string(buf, offset, topic.topic_path)?;
+            write_u8(buf, offset, topic.options())?;
+        }
+
+        Ok(write_len)
+    }
+}
+
+impl<'a> Unsubscribe<'a> {
+    pub fn new(topics: &'a [&'a str]) -> Self {
+        Self::with_properties(topics, Properties::default())
+    }
+
+    pub fn with_properties(topics: &'a [&'a str], properties: Properties<'a>) -> Self {
+        Self {
+            pid: None,
+            properties,
+            topics: List::Owned(topics),
+        }
+    }
+
+    pub fn topics(&self) -> impl Iterator<Item = &str> {
+        self.topics.into_iter()
+    }
+
+    pub fn pid(&self) -> Option<Pid> {
+        self.pid
+    }
+
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let pid = read_pid(buf, offset)?;
+        let properties = Properties::from_buffer(buf, offset)?;
+
+        let unsubscribe = Unsubscribe {
+            pid: Some(pid),
+            properties,
+            topics: read_list(buf, offset)?,
+        };
+        for topic in unsubscribe.topics() {
+            validate_topic_filter(topic)?;
+        }
+        Ok(unsubscribe)
+    }
+
+    /// Length: pid(2) + properties + topic.for_each(2+len)
+    pub(crate) fn len(&self) -> usize {
+        let mut length = 2 + self.properties.len();
+        for topic in self.topics() {
+            length += 2 + topic.len();
+        }
+        length
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        for topic in self.topics() {
+            validate_topic_filter(topic)?;
+        }
+
+        let header: u8 = 0b10100010;
+
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, header)?;
+
+        let write_len = write_length(buf, offset, self.len())? + 1;
+
+        // Pid
+        self.pid.unwrap_or_default().to_buffer(buf, offset)?;
+
+        self.properties.to_buffer(buf, offset)?;
+
+        for topic in self.topics() {
+            write_string(buf, offset, topic)?;
+        }
+        Ok(write_len)
+    }
+}
+
+impl<'a> Suback<'a> {
+    pub fn new(pid: Pid, reason_codes: &'a [ReasonCode]) -> Self {
+        Self {
+            pid,
+            properties: Properties::default(),
+            reason_codes: List::Owned(reason_codes),
+        }
+    }
+
+    pub fn reason_codes(&self) -> impl Iterator<Item = ReasonCode> + '_ {
+        self.reason_codes.into_iter()
+    }
+
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let pid = read_pid(buf, offset)?;
+        let properties = Properties::from_buffer(buf, offset)?;
+
+        Ok(Suback {
+            pid,
+            properties,
+            reason_codes: read_list(buf, offset)?,
+        })
+    }
+
+    /// Length: pid(2) + properties + reason_codes.len()
+    pub(crate) fn len(&self) -> usize {
+        2 + self.properties.len() + self.reason_codes.len()
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        let header: u8 = 0b10010000;
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, header)?;
+
+        let write_len = write_length(buf, offset, self.len())? + 1;
+        self.pid.to_buffer(buf, offset)?;
+        self.properties.to_buffer(buf, offset)?;
+        for rc in self.reason_codes() {
+            write_u8(buf, offset, rc.as_u8())?;
+        }
+        Ok(write_len)
+    }
+}
+
+impl<'a> Unsuback<'a> {
+    pub fn new(pid: Pid, reason_codes: &'a [ReasonCode]) -> Self {
+        Self {
+            pid,
+            properties: Properties::default(),
+            reason_codes: List::Owned(reason_codes),
+        }
+    }
+
+    pub fn reason_codes(&self) -> impl Iterator<Item = ReasonCode> + '_ {
+        self.reason_codes.into_iter()
+    }
+
+    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+        let pid = read_pid(buf, offset)?;
+        let properties = Properties::from_buffer(buf, offset)?;
+
+        Ok(Unsuback {
+            pid,
+            properties,
+            reason_codes: read_list(buf, offset)?,
+        })
+    }
+
+    /// Length: pid(2) + properties + reason_codes.len()
+    pub(crate) fn len(&self) -> usize {
+        2 + self.properties.len() + self.reason_codes.len()
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        let header: u8 = 0b10110000;
+        check_remaining(buf, offset, 1)?;
+        write_u8(buf, offset, header)?;
+
+        let write_len = write_length(buf, offset, self.len())? + 1;
+        self.pid.to_buffer(buf, offset)?;
+        self.properties.to_buffer(buf, offset)?;
+        for rc in self.reason_codes() {
+            write_u8(buf, offset, rc.as_u8())?;
+        }
+        Ok(write_len)
+    }
+}
@@ -4,6 +4,7 @@
 pub(crate) mod fmt;
 
 pub mod encoding;
+pub mod topic;
 
 pub use encoding::v4::{
     subscribe::SubscribeTopic, utils::QoS, Packet, Publish, Subscribe, Unsubscribe,
@@ -18,6 +19,33 @@ pub enum MqttError {
     Borrow,
     /// Needed resource is unavailable
     Unavailable,
+    /// Topic name or filter doesn't follow the MQTT topic rules
+    InvalidTopic,
+    /// Packet exceeds the maximum size the client can send
+    PacketTooLarge,
+}
+
+/// Identifies a publish until its delivery is reported.
+///
+/// Tokens are sequence numbers, assigned to publishes in the order they are
+/// sent by a client.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub struct DeliveryToken(u32);
+
+impl DeliveryToken {
+    pub fn new(seq: u32) -> Self {
+        DeliveryToken(seq)
+    }
+
+    pub fn get(self) -> u32 {
+        self.0
+    }
+
+    /// Token of the subsequent publish
+    pub fn next(self) -> Self {
+        DeliveryToken(self.0.wrapping_add(1))
+    }
 }
 
 pub trait Mqtt {
@@ -48,3 +76,28 @@ pub trait Mqtt {
         self.send(packet)
     }
 }
+
+/// Extends [`Mqtt`] with publishes returning a [`DeliveryToken`], which
+/// their delivery is reported with.
+pub trait MqttDelivery: Mqtt {
+    /// Sends a publish, returning the token its delivery is reported with.
+    fn send_publish(&self, publish: Publish<'_>) -> Result<DeliveryToken, MqttError>;
+
+    /// Same as [`Mqtt::publish`], returning the token its delivery is
+    /// reported with.
+    fn publish_tracked(
+        &self,
+        topic_name: &str,
+        payload: &[u8],
+        qos: QoS,
+    ) -> Result<DeliveryToken, MqttError> {
+        self.send_publish(Publish {
+            dup: false,
+            qos,
+            pid: None,
+            retain: false,
+            topic_name,
+            payload,
+        })
+    }
+}
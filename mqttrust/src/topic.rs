@@ -0,0 +1,174 @@
+//! Matching of topic names against topic filters ([MQTT 4.7]).
+//!
+//! [MQTT 4.7]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718106
+
+use crate::encoding::v4::Error;
+
+/// Checks that a topic name is valid to publish to.
+///
+/// A topic name is non-empty, at most 65535 bytes long, and contains neither
+/// wildcards nor the null character.
+///
+/// ```
+/// # use mqttrust::{encoding::v4::Error, topic::validate_topic_name};
+/// assert_eq!(validate_topic_name("sensors/kitchen"), Ok(()));
+/// assert_eq!(validate_topic_name("sensors/+"), Err(Error::InvalidTopicName));
+/// ```
+pub fn validate_topic_name(topic_name: &str) -> Result<(), Error> {
+    if is_valid_string(topic_name) && !topic_name.contains(is_wildcard) {
+        Ok(())
+    } else {
+        Err(Error::InvalidTopicName)
+    }
+}
+
+/// Checks that a topic filter is valid to subscribe to.
+///
+/// A topic filter is non-empty, at most 65535 bytes long, and doesn't contain
+/// the null character. A `+` wildcard must occupy an entire level, and a `#`
+/// wildcard must occupy the last level.
+///
+/// ```
+/// # use mqttrust::{encoding::v4::Error, topic::validate_topic_filter};
+/// assert_eq!(validate_topic_filter("sensors/+/temperature"), Ok(()));
+/// assert_eq!(validate_topic_filter("sensors/#/temperature"), Err(Error::InvalidTopicFilter));
+/// ```
+pub fn validate_topic_filter(topic_filter: &str) -> Result<(), Error> {
+    if !is_valid_string(topic_filter) {
+        return Err(Error::InvalidTopicFilter);
+    }
+
+    let mut levels = topic_filter.split('/').peekable();
+    while let Some(level) = levels.next() {
+        match level {
+            "+" => {}
+            "#" if levels.peek().is_none() => {}
+            level if level.contains(is_wildcard) => return Err(Error::InvalidTopicFilter),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks whether a topic name matches a topic filter.
+///
+/// A `+` level in the filter matches exactly one level of the name, and a
+/// trailing `#` level matches the parent level and any number of child
+/// levels. Topic names starting with `$` are only matched by filters which
+/// don't start with a wildcard. Invalid topic names and filters never match.
+///
+/// ```
+/// # use mqttrust::topic::matches;
+/// assert!(matches("sensors/kitchen/temperature", "sensors/+/temperature"));
+/// assert!(matches("sensors", "sensors/#"));
+/// assert!(!matches("sensors/kitchen/humidity", "sensors/+/temperature"));
+/// assert!(!matches("$SYS/uptime", "#"));
+/// ```
+pub fn matches(topic_name: &str, topic_filter: &str) -> bool {
+    if validate_topic_name(topic_name).is_err() || validate_topic_filter(topic_filter).is_err() {
+        return false;
+    }
+    if topic_name.starts_with('$') && topic_filter.starts_with(is_wildcard) {
+        return false;
+    }
+
+    let mut names = topic_name.split('/');
+    let mut filters = topic_filter.split('/');
+    loop {
+        match (filters.next(), names.next()) {
+            (Some("#"), _) => return filters.next().is_none(),
+            (Some("+"), Some(_)) => {}
+            (Some(filter), Some(name)) if filter == name => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+fn is_wildcard(c: char) -> bool {
+    c == '+' || c == '#'
+}
+
+fn is_valid_string(topic: &str) -> bool {
+    !topic.is_empty() && topic.len() <= u16::MAX as usize && !topic.contains('\0')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_levels() {
+        assert!(matches("a/b/c", "a/b/c"));
+        assert!(!matches("a/b/c", "a/b"));
+        assert!(!matches("a/b", "a/b/c"));
+        // Empty levels are distinct levels
+        assert!(matches("/a", "/a"));
+        assert!(!matches("/a", "a"));
+        assert!(!matches("a/", "a"));
+    }
+
+    #[test]
+    fn single_level_wildcard() {
+        assert!(matches("a/b/c", "a/+/c"));
+        assert!(matches("a//c", "a/+/c"));
+        assert!(matches("a/b", "+/+"));
+        assert!(matches("/a", "+/+"));
+        assert!(!matches("a/b/c", "a/+"));
+        assert!(!matches("a", "a/+"));
+    }
+
+    #[test]
+    fn multi_level_wildcard() {
+        assert!(matches("a", "#"));
+        assert!(matches("a/b/c", "#"));
+        assert!(matches("a", "a/#"));
+        assert!(matches("a/b/c", "a/#"));
+        assert!(matches("a/b/c", "a/+/#"));
+        assert!(!matches("b/c", "a/#"));
+        // Only valid as the last level
+        assert!(!matches("a/b/c", "a/#/c"));
+    }
+
+    #[test]
+    fn dollar_topics() {
+        assert!(!matches("$SYS/uptime", "#"));
+        assert!(!matches("$SYS/uptime", "+/uptime"));
+        assert!(matches("$SYS/uptime", "$SYS/#"));
+        assert!(matches("$SYS/uptime", "$SYS/+"));
+        assert!(matches("a/$b", "a/+"));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(!matches("a/b", "a/b+"));
+        assert!(!matches("a/b#", "a/b#"));
+        assert!(!matches("a/+", "a/+"));
+    }
+
+    #[test]
+    fn topic_names() {
+        assert_eq!(validate_topic_name("a"), Ok(()));
+        assert_eq!(validate_topic_name("/"), Ok(()));
+        assert_eq!(validate_topic_name("$SYS/a b"), Ok(()));
+        assert_eq!(validate_topic_name(""), Err(Error::InvalidTopicName));
+        assert_eq!(validate_topic_name("a/+"), Err(Error::InvalidTopicName));
+        assert_eq!(validate_topic_name("a#"), Err(Error::InvalidTopicName));
+        assert_eq!(validate_topic_name("a\0b"), Err(Error::InvalidTopicName));
+    }
+
+    #[test]
+    fn topic_filters() {
+        for filter in ["a", "+", "#", "/", "+/#", "a/+/b", "a//#", "$SYS/#"] {
+            assert_eq!(validate_topic_filter(filter), Ok(()), "{}", filter);
+        }
+        for filter in ["", "a/#/b", "foo+", "a/b#", "##", "a/\0"] {
+            assert_eq!(
+                validate_topic_filter(filter),
+                Err(Error::InvalidTopicFilter),
+                "{}",
+                filter
+            );
+        }
+    }
+}
@@ -1,10 +1,15 @@
+use crate::max_payload::MAX_PAYLOAD_SIZE;
 use bbqueue::framed::FrameProducer;
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::ops::DerefMut;
 use mqttrust::{
-    encoding::v4::{encoder::encode_slice, Packet},
-    Mqtt, MqttError,
+    encoding::v4::{encoder::encode_slice, Error as EncodingError, Packet},
+    DeliveryToken, Mqtt, MqttDelivery, MqttError, Publish,
 };
+
+/// Size of the delivery token preceding each packet in the queue
+pub(crate) const TOKEN_LEN: usize = 4;
+
 /// MQTT Client
 ///
 /// This client is merely a convenience wrapper around a
@@ -12,6 +17,14 @@ use mqttrust::{
 /// types, and maintaining a common reference to a client id. Also it implements
 /// the [`Mqtt`] trait.
 ///
+/// Publishes sent through [`MqttDelivery`] return a [`DeliveryToken`], which
+/// the [Eventloop](crate::eventloop::EventLoop) reports back in a
+/// `Notification::Delivery` once the publish is acknowledged, failed or
+/// dropped. The token is queued along with the publish.
+///
+/// Packets serializing to more than `MAX_PAYLOAD_SIZE` bytes are rejected with
+/// `MqttError::PacketTooLarge`, as the eventloop can't send them.
+///
 /// **Lifetimes**:
 /// - `'a`: Lifetime of the queue for exchanging packets between the client and
 ///   [Eventloop](crate::eventloop::EventLoop). This must have the same lifetime as the corresponding
@@ -24,8 +37,9 @@ use mqttrust::{
 ///   The length is in bytes and it must be chosen long enough to contain serialized MQTT packets and
 ///   [FrameProducer](bbqueue::framed) header bytes.
 ///   For example a MQTT packet with 30 bytes payload and 20 bytes topic name takes 59 bytes to serialize
-///   into MQTT frame plus ~2 bytes (depending on grant length) for [FrameProducer](bbqueue::framed) header.
-///   For rough calculation `payload_len + topic_name + 15` can be used to determine
+///   into MQTT frame plus ~2 bytes (depending on grant length) for [FrameProducer](bbqueue::framed) header
+///   and 4 bytes for the delivery token.
+///   For rough calculation `payload_len + topic_name + 19` can be used to determine
 ///   how many bytes one packet consumes.
 ///   Packets are read out from queue only when [Eventloop::yield_event](crate::eventloop::EventLoop::yield_event) is called.
 ///   Therefore make sure that queue length is long enough to contain multiple packets if you want to call
@@ -33,6 +47,8 @@ use mqttrust::{
 pub struct Client<'a, 'b, const L: usize> {
     client_id: &'b str,
     producer: Option<RefCell<FrameProducer<'a, L>>>,
+    /// Token of the next publish
+    next_token: Cell<DeliveryToken>,
 }
 
 impl<'a, 'b, const L: usize> Client<'a, 'b, L> {
@@ -40,6 +56,7 @@ impl<'a, 'b, const L: usize> Client<'a, 'b, L> {
         Self {
             client_id,
             producer: Some(RefCell::new(producer)),
+            next_token: Cell::new(DeliveryToken::default()),
         }
     }
 
@@ -47,29 +64,49 @@ impl<'a, 'b, const L: usize> Client<'a, 'b, L> {
     ///
     /// This can be called before dropping `Client` to get back original `FrameProducer`.
     pub fn release_queue(&mut self) -> Option<FrameProducer<'a, L>> {
-        match self.producer.take() {
-            Some(prod) => Some(prod.into_inner()),
-            None => None,
-        }
+        self.producer.take().map(RefCell::into_inner)
     }
 }
 
 impl<'a, 'b, const L: usize> Mqtt for Client<'a, 'b, L> {
     fn client_id(&self) -> &str {
-        &self.client_id
+        self.client_id
     }
 
     fn send(&self, packet: Packet<'_>) -> Result<(), MqttError> {
         match &self.producer {
             Some(producer) => {
                 let mut prod = producer.try_borrow_mut().map_err(|_| MqttError::Borrow)?;
-                let max_size = packet.len();
+                let max_size = TOKEN_LEN + packet.len();
                 let mut grant = prod.grant(max_size).map_err(|_| MqttError::Full)?;
-                let len = encode_slice(&packet, grant.deref_mut()).map_err(|_| MqttError::Full)?;
-                grant.commit(len);
+                let (token, buf) = grant.deref_mut().split_at_mut(TOKEN_LEN);
+                token.copy_from_slice(&self.next_token.get().get().to_le_bytes());
+                let len = encode_slice(&packet, buf).map_err(|e| match e {
+                    EncodingError::InvalidTopicName | EncodingError::InvalidTopicFilter => {
+                        MqttError::InvalidTopic
+                    }
+                    _ => MqttError::Full,
+                })?;
+                // The eventloop sends a packet out of its transmit buffer,
+                // which is sized for the largest incoming packet
+                if len > MAX_PAYLOAD_SIZE {
+                    return Err(MqttError::PacketTooLarge);
+                }
+                grant.commit(TOKEN_LEN + len);
+                if let Packet::Publish(_) = packet {
+                    self.next_token.set(self.next_token.get().next());
+                }
                 Ok(())
             }
             None => Err(MqttError::Unavailable),
         }
     }
 }
+
+impl<'a, 'b, const L: usize> MqttDelivery for Client<'a, 'b, L> {
+    fn send_publish(&self, publish: Publish<'_>) -> Result<DeliveryToken, MqttError> {
+        let token = self.next_token.get();
+        self.send(Packet::Publish(publish))?;
+        Ok(token)
+    }
+}
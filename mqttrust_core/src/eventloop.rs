@@ -1,23 +1,62 @@
+use crate::client::TOKEN_LEN;
 use crate::max_payload::MAX_PAYLOAD_SIZE;
-use crate::options::Broker;
-use crate::packet::SerializedPacket;
-use crate::state::{MqttConnectionStatus, MqttState};
-use crate::{EventError, MqttOptions, NetworkError, Notification};
+use crate::options::{
+    AddressFamily, Broker, ProtocolVersion, ReconnectPolicy, MAX_USER_PROPERTIES,
+};
+use crate::packet::{PublishHead, SerializedPacket, V5Parts};
+use crate::payload::PayloadSource;
+use crate::state::{MqttConnectionStatus, MqttState, SessionLimits, StartTime, StateError};
+use crate::subscriptions::{MAX_SUBSCRIPTIONS, MAX_TOPIC_FILTER_LEN};
+use crate::{AckHandle, EventError, MqttOptions, NetworkError, Notification, STREAM_CHUNK_SIZE};
 use bbqueue::framed::FrameConsumer;
-use core::convert::Infallible;
+use core::convert::{Infallible, TryFrom};
 use core::ops::DerefMut;
 use core::ops::RangeTo;
 use embedded_nal::{AddrType, Dns, SocketAddr, TcpClientStack};
-use fugit::ExtU32;
+use fugit::{ExtU32, TimerDurationU32, TimerInstantU32};
 use heapless::{String, Vec};
-use mqttrust::encoding::v4::{decode_slice, encode_slice, Connect, Packet, Protocol};
-
-pub struct EventLoop<'a, 'b, S, O, const TIMER_HZ: u32, const L: usize>
-where
+use mqttrust::encoding::v4::{
+    decode_slice, decoder::Header, encode_slice, Connect, Error as EncodingError, Packet,
+    PacketType, Pid, Protocol, QoS, Subscribe, SubscribeTopic,
+};
+use mqttrust::encoding::{v4, v5};
+use mqttrust::topic::validate_topic_name;
+use mqttrust::DeliveryToken;
+
+/// Size of a subscribe to all topic filters of a full registry
+const RESUBSCRIBE_LEN: usize = 5 + 2 + MAX_SUBSCRIPTIONS * (2 + MAX_TOPIC_FILTER_LEN + 1);
+
+/// Size of the buffer control packets are encoded into
+const CONTROL_PACKET_LEN: usize = 64;
+
+/// Size of the buffer keeping the bytes of outgoing packets which the socket
+/// didn't accept right away. Holds a full packet, along with the properties
+/// added to requests for MQTT 5.
+const TX_BUF_LEN: usize = MAX_PAYLOAD_SIZE + CONTROL_PACKET_LEN;
+
+/// MQTT Eventloop
+///
+/// **Generics**:
+/// - `L`: Length of the queue for exchanging packets with the [Client](crate::Client).
+/// - `INFLIGHT`: Maximum number of outgoing and incoming QoS 1, 2 publishes in
+///   flight. Must be a power of two.
+/// - `RETRANSMIT_LEN`: Size in bytes of the buffer storing outgoing QoS 1, 2
+///   publishes for retransmission, which is shared by all of them. Larger
+///   publishes are discarded and reported as `DeliveryStatus::Dropped`.
+pub struct EventLoop<
+    'a,
+    'b,
+    S,
+    O,
+    const TIMER_HZ: u32,
+    const L: usize,
+    const INFLIGHT: usize = 2,
+    const RETRANSMIT_LEN: usize = 3072,
+> where
     O: fugit_timer::Timer<TIMER_HZ>,
 {
     /// Current state of the connection
-    pub(crate) state: MqttState<TIMER_HZ>,
+    pub(crate) state: MqttState<TIMER_HZ, INFLIGHT, RETRANSMIT_LEN>,
     /// Last outgoing packet time
     pub(crate) last_outgoing_timer: O,
     /// Options of the current mqtt connection
@@ -25,6 +64,37 @@ where
     /// Request stream
     pub(crate) requests: Option<FrameConsumer<'a, L>>,
     network_handle: NetworkHandle<S>,
+    /// Delay of the next connection attempt
+    backoff: Backoff<TIMER_HZ>,
+    /// Connection transition to be notified by the next `yield_event`
+    transition: Option<Notification>,
+    /// Graceful disconnect in progress
+    shutdown: Option<Shutdown<TIMER_HZ>>,
+    /// Streamed publish being written to the socket
+    stream_write: Option<StreamWrite>,
+    /// Packets of a resumed session yet to be sent again
+    replay: Replay<INFLIGHT>,
+}
+
+/// Progress of a graceful disconnect
+#[derive(Debug)]
+struct Shutdown<const TIMER_HZ: u32> {
+    deadline: TimerInstantU32<TIMER_HZ>,
+    /// Whether the DISCONNECT was passed to the socket
+    disconnect_sent: bool,
+}
+
+/// Progress of writing a streamed publish, of which the payload is written one
+/// chunk per call of `EventLoop::publish_stream` or
+/// `EventLoop::retry_publish_stream`
+#[derive(Debug)]
+struct StreamWrite {
+    pid: Option<Pid>,
+    /// Whether this is a retransmission of the inflight streamed publish
+    retry: bool,
+    /// Payload offset of the next chunk
+    offset: usize,
+    payload_len: usize,
 }
 
 impl<'a, 'b, S, O, const TIMER_HZ: u32, const L: usize> EventLoop<'a, 'b, S, O, TIMER_HZ, L>
@@ -35,6 +105,30 @@ where
         requests: FrameConsumer<'a, L>,
         outgoing_timer: O,
         options: MqttOptions<'b>,
+    ) -> Self {
+        Self::with_inflight(requests, outgoing_timer, options)
+    }
+}
+
+impl<
+        'a,
+        'b,
+        S,
+        O,
+        const TIMER_HZ: u32,
+        const L: usize,
+        const INFLIGHT: usize,
+        const RETRANSMIT_LEN: usize,
+    > EventLoop<'a, 'b, S, O, TIMER_HZ, L, INFLIGHT, RETRANSMIT_LEN>
+where
+    O: fugit_timer::Timer<TIMER_HZ>,
+{
+    /// Creates an eventloop with the `INFLIGHT` and `RETRANSMIT_LEN` of its
+    /// type, rather than the defaults used by `new`.
+    pub fn with_inflight(
+        requests: FrameConsumer<'a, L>,
+        outgoing_timer: O,
+        options: MqttOptions<'b>,
     ) -> Self {
         Self {
             state: MqttState::new(),
@@ -42,6 +136,11 @@ where
             options,
             requests: Some(requests),
             network_handle: NetworkHandle::new(),
+            backoff: Backoff::new(),
+            transition: None,
+            shutdown: None,
+            stream_write: None,
+            replay: Replay::new(),
         }
     }
 
@@ -52,12 +151,63 @@ where
         self.requests.take()
     }
 
+    /// State of the connection to the broker
+    pub fn connection_state(&self) -> MqttConnectionStatus {
+        self.state.connection_status
+    }
+
+    /// Limits announced by the broker for the current MQTT 5 connection
+    pub fn session_limits(&self) -> &SessionLimits {
+        &self.state.session_limits
+    }
+
+    /// Client identifier of the connection, which is the one assigned by the
+    /// broker if any
+    pub fn client_id(&self) -> &str {
+        match &self.state.assigned_client_id {
+            Some(client_id) => client_id.as_str(),
+            None => self.options.client_id(),
+        }
+    }
+
+    /// Topic filters subscribed to, which are subscribed to again whenever
+    /// the broker doesn't keep the session. Refer to `MAX_SUBSCRIPTIONS` for
+    /// how many are kept.
+    pub fn subscriptions(&self) -> impl Iterator<Item = SubscribeTopic<'_>> {
+        self.state.subscriptions.iter()
+    }
+
+    /// Keep alive interval, unless overridden by the broker
+    fn keep_alive_ms(&self) -> u32 {
+        match self.state.session_limits.server_keep_alive {
+            Some(secs) => secs as u32 * 1000,
+            None => self.options.keep_alive_ms(),
+        }
+    }
+
+    /// Connects to the broker, returning `true` once a new connection is
+    /// established. After connecting failed or the connection was lost, this
+    /// returns `WouldBlock` until the delay of the `ReconnectPolicy` passed.
+    ///
+    /// This never blocks on the network: it returns `WouldBlock` while the
+    /// broker address is being resolved, the socket is being connected, or
+    /// the broker has yet to acknowledge the MQTT connection.
     pub fn connect<N: Dns + TcpClientStack<TcpSocket = S> + ?Sized>(
         &mut self,
         network: &mut N,
     ) -> nb::Result<bool, EventError> {
-        // connect to the broker
-        match self.network_handle.is_connected(network) {
+        let now = self.last_outgoing_timer.now();
+        if !self.backoff.is_due(&now) {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        // connect to the broker, unless the socket is still being connected
+        let connected = if self.network_handle.pending_connect.is_some() {
+            Err(NetworkError::SocketClosed)
+        } else {
+            self.network_handle.is_connected(network)
+        };
+        match connected {
             Ok(false) => {
                 // Socket is present, but not connected. Usually this implies
                 // that the socket is closed for writes. Disconnect to close &
@@ -70,15 +220,27 @@ where
                     MqttConnectionStatus::Connected
                 ) {
                     warn!("Socket cleanup!");
-                    self.disconnect(network);
+                    self.close_on_error(network);
+                    self.transition = Some(Notification::Disconnected {
+                        reason: EventError::Network(NetworkError::SocketClosed),
+                    });
                     return Err(EventError::Network(NetworkError::SocketClosed).into());
                 }
             }
             Err(_) => {
-                // We have no socket present at all
-                self.network_handle
-                    .connect(network, self.options.broker())
-                    .map_err(EventError::Network)?;
+                // We have no socket present at all, or it is being connected
+                match self.network_handle.connect(
+                    network,
+                    self.options.broker(),
+                    self.options.address_family(),
+                ) {
+                    Ok(()) => {}
+                    Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+                    Err(nb::Error::Other(e)) => {
+                        self.close_on_error(network);
+                        return Err(EventError::Network(e).into());
+                    }
+                }
                 debug!("Network connected!");
 
                 self.state.connection_status = MqttConnectionStatus::Disconnected;
@@ -95,7 +257,7 @@ where
                     EventError::Network(_) | EventError::MqttState(_) | EventError::Timeout
                 ) {
                     debug!("Disconnecting!");
-                    self.disconnect(network);
+                    self.close_on_error(network);
                 }
                 e
             })
@@ -103,7 +265,13 @@ where
     }
 
     fn should_handle_request(&mut self) -> bool {
-        let qos_space = self.state.outgoing_pub.len() < self.state.outgoing_pub.capacity();
+        let inflight_max = self
+            .state
+            .outgoing_pub
+            .capacity()
+            .min(self.state.session_limits.receive_maximum as usize);
+        let inflight = self.state.outgoing_pub.len() + self.state.outgoing_stream.iter().count();
+        let qos_space = inflight < inflight_max;
 
         // TODO:
         // let qos_0 = if let Some(_) = self.requests.read() {
@@ -124,19 +292,55 @@ where
     ) -> nb::Result<Notification, EventError> {
         let now = self.last_outgoing_timer.now();
 
+        // Report the delivery of earlier publishes
+        if let Some(delivery) = self.state.deliveries.pop_front() {
+            return Ok(Notification::Delivery(delivery));
+        }
+
+        // Bytes the socket didn't accept earlier go before any new packets,
+        // which also wait for a streamed publish to be written completely
+        let tx_idle = match self.network_handle.flush(network) {
+            Ok(()) => self.stream_write.is_none(),
+            Err(nb::Error::WouldBlock) => false,
+            Err(e) => return Err(e),
+        };
+
+        // Packets of a resumed session go before any requests
+        if tx_idle && self.resend_next(network)? {
+            return Err(nb::Error::WouldBlock);
+        }
+
         // Handle a request
-        if self.should_handle_request() {
+        if tx_idle && self.should_handle_request() {
             match &mut self.requests {
                 Some(requests) => {
                     if let Some(mut grant) = requests.read() {
-                        let mut packet = SerializedPacket(grant.deref_mut());
-                        match self.state.handle_outgoing_request(&mut packet, &now) {
+                        // Each packet is preceded by the delivery token the
+                        // client returned for it
+                        let (token, packet) = grant.deref_mut().split_at_mut(TOKEN_LEN);
+                        let token = DeliveryToken::new(u32::from_le_bytes([
+                            token[0], token[1], token[2], token[3],
+                        ]));
+                        let mut packet = SerializedPacket(packet);
+                        match self.state.handle_outgoing_request(&mut packet, token, &now) {
                             Ok(()) => {
-                                self.network_handle.send(network, packet.to_inner())?;
+                                self.network_handle.send_request(
+                                    network,
+                                    packet.to_inner(),
+                                    self.state.protocol,
+                                )?;
+                                grant.release();
+                                return Err(nb::Error::WouldBlock);
+                            }
+                            Err(StateError::MaxMessagesInflight) => {}
+                            Err(StateError::PacketTooLarge | StateError::InflightTooLarge) => {
+                                error!("Request exceeds the maximum packet size, discarding!");
+                                self.state
+                                    .handle_dropped_request(&packet, token)
+                                    .map_err(EventError::from)?;
                                 grant.release();
                                 return Err(nb::Error::WouldBlock);
                             }
-                            Err(crate::state::StateError::MaxMessagesInflight) => {}
                             Err(e) => return Err(nb::Error::Other(e.into())),
                         }
                     }
@@ -145,11 +349,15 @@ where
             }
         }
 
-        if self
-            .state
-            .last_ping_entry()
-            .or_insert(now)
-            .has_elapsed(&now, self.options.keep_alive_ms().millis())
+        // A keep alive of zero disables the keepalive mechanism
+        let keep_alive_ms = self.keep_alive_ms();
+        if tx_idle
+            && keep_alive_ms > 0
+            && self
+                .state
+                .last_ping_entry()
+                .or_insert(now)
+                .has_elapsed(&now, keep_alive_ms.millis())
         {
             // Handle keepalive ping
             let packet = self
@@ -161,6 +369,39 @@ where
             return Err(nb::Error::WouldBlock);
         }
 
+        // By comparing the current time, select a pending non-zero QoS publish
+        // request staying longer than the retry interval, and handle its
+        // retrial. Others which are due follow on the next calls. Checked
+        // ahead of reading the socket, such that retries also happen on a
+        // link on which the broker stays silent.
+        if tx_idle {
+            if let Some(pid) = self.state.next_retry(now, self.options.retry_policy()) {
+                warn!("Retrying PID {:?}", pid);
+                let protocol = self.state.protocol;
+                let packet = self.state.stored_publish(pid).map_err(EventError::from)?;
+                self.network_handle
+                    .send_request(network, packet, protocol)?;
+                return Err(nb::Error::WouldBlock);
+            }
+        }
+
+        // Replies to incoming packets can't interrupt a streamed publish
+        // being written
+        if self.stream_write.is_some() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        // The payload of a streamed publish has to be read again by the user
+        if let Some(notification) = self.state.stream_retry(now, self.options.retry_policy()) {
+            return Ok(notification);
+        }
+
+        // Incoming packets are read once the socket accepted the earlier
+        // bytes, which leaves room for their replies
+        if !tx_idle {
+            return Err(nb::Error::WouldBlock);
+        }
+
         // Handle an incoming packet
         let (notification, packet) = self
             .network_handle
@@ -171,29 +412,33 @@ where
         // Handle `ack` of newly received incoming packet, if relevant
         if let Some(packet) = packet {
             self.network_handle.send_packet(network, &packet)?;
-        }
-
-        // By comparing the current time, select pending non-zero QoS publish
-        // requests staying longer than the retry interval, and handle their
-        // retrial.
-        for (pid, inflight) in self.state.retries(now, 10.secs()) {
-            warn!("Retrying PID {:?}", pid);
-            // Update inflight's timestamp for later retrials
-            inflight.last_touch_entry().insert(now);
-            let packet = inflight.packet(*pid).map_err(EventError::from)?;
-            self.network_handle.send(network, &packet)?;
+            return notification.ok_or(nb::Error::WouldBlock);
         }
 
         notification.ok_or(nb::Error::WouldBlock)
     }
 
-    /// Yields notification from events. All the error raised while processing
-    /// event is reported as an `Ok` value of `Notification::Abort`.
+    /// Yields notification from events. An error raised while processing
+    /// events closes the connection, and is reported as an `Ok` value of
+    /// `Notification::Disconnected` if the connection was established, or of
+    /// `Notification::Abort` otherwise.
     #[must_use = "Eventloop should be iterated over a loop to make progress"]
     pub fn yield_event<N: TcpClientStack<TcpSocket = S> + ?Sized>(
         &mut self,
         network: &mut N,
     ) -> nb::Result<Notification, Infallible> {
+        if let Some(notification) = self.transition.take() {
+            return Ok(notification);
+        }
+
+        if let Some(shutdown) = &self.shutdown {
+            let now = self.last_outgoing_timer.now();
+            let expired = now >= shutdown.deadline;
+            if shutdown.disconnect_sent || expired || !self.has_outstanding_requests() {
+                return self.close_gracefully(network, expired);
+            }
+        }
+
         if self.network_handle.socket.is_none() {
             return Ok(Notification::Abort(EventError::Network(
                 NetworkError::NoSocket,
@@ -204,19 +449,374 @@ where
             nb::Error::WouldBlock => Err(nb::Error::WouldBlock),
             nb::Error::Other(e) => {
                 debug!("Disconnecting from an event error");
-                self.disconnect(network);
-                Ok(Notification::Abort(e))
+                let connected = self.state.connection_status == MqttConnectionStatus::Connected;
+                self.close_on_error(network);
+                if connected {
+                    Ok(Notification::Disconnected { reason: e })
+                } else {
+                    Ok(Notification::Abort(e))
+                }
             }
         })
     }
 
+    /// Sends a DISCONNECT and closes the connection once the socket accepted
+    /// it. If the deadline of the graceful disconnect passes before, or
+    /// writing fails, the connection is closed regardless, which is notified
+    /// as `Notification::Disconnected`.
+    fn close_gracefully<N: TcpClientStack<TcpSocket = S> + ?Sized>(
+        &mut self,
+        network: &mut N,
+        expired: bool,
+    ) -> nb::Result<Notification, Infallible> {
+        let written = if self.state.connection_status == MqttConnectionStatus::Connected {
+            self.send_disconnect(network)
+        } else {
+            Ok(())
+        };
+
+        let reason = match written {
+            Ok(()) => {
+                self.shutdown = None;
+                self.disconnect(network);
+                return Ok(Notification::Closed);
+            }
+            Err(nb::Error::WouldBlock) if !expired => return Err(nb::Error::WouldBlock),
+            Err(nb::Error::WouldBlock) => EventError::Timeout,
+            Err(nb::Error::Other(e)) => e,
+        };
+        warn!("Failed to send Disconnect");
+        self.shutdown = None;
+        self.close_on_error(network);
+        Ok(Notification::Disconnected { reason })
+    }
+
+    /// Writes a DISCONNECT following the bytes the socket didn't accept
+    /// earlier, which can't interrupt a streamed publish being written.
+    /// Returns `WouldBlock` until all of them are written.
+    fn send_disconnect<N: TcpClientStack<TcpSocket = S> + ?Sized>(
+        &mut self,
+        network: &mut N,
+    ) -> nb::Result<(), EventError> {
+        self.network_handle.flush(network)?;
+        if self.stream_write.is_some() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        if let Some(shutdown) = &mut self.shutdown {
+            if !shutdown.disconnect_sent {
+                debug!("Sending Disconnect");
+                shutdown.disconnect_sent = true;
+                self.network_handle
+                    .send_packet(network, &Packet::Disconnect)?;
+            }
+        }
+        self.network_handle.flush(network)
+    }
+
+    /// Publishes a payload of known length, which is read from `source` in
+    /// chunks straight onto the socket, rather than being queued by a
+    /// `Client`. Every call writes the next chunk, returning `WouldBlock`
+    /// until the full publish is written, so this has to be called again with
+    /// the same `source` until then. Meanwhile, `yield_event` holds off
+    /// sending other packets.
+    ///
+    /// Returns the packet identifier of a QoS 1 or 2 publish, which is
+    /// completed by the corresponding `Notification::Puback` or
+    /// `Notification::Pubcomp`. Only one of those can be inflight at a time,
+    /// otherwise this returns `WouldBlock`. When a retransmission is due,
+    /// `yield_event` notifies `Notification::RetryStreamedPublish`, upon which
+    /// the payload is read again by `retry_publish_stream`.
+    pub fn publish_stream<N, P>(
+        &mut self,
+        network: &mut N,
+        topic_name: &str,
+        qos: QoS,
+        retain: bool,
+        source: &mut P,
+    ) -> nb::Result<Option<Pid>, EventError>
+    where
+        N: TcpClientStack<TcpSocket = S> + ?Sized,
+        P: PayloadSource,
+    {
+        match &self.stream_write {
+            Some(write) if !write.retry => return self.write_stream_chunk(network, source),
+            Some(_) => return Err(nb::Error::WouldBlock),
+            None => {}
+        }
+
+        if self.state.connection_status != MqttConnectionStatus::Connected {
+            return Err(nb::Error::Other(StateError::InvalidState.into()));
+        }
+        validate_topic_name(topic_name).map_err(|e| nb::Error::Other(e.into()))?;
+        // Packets of a resumed session go first
+        if !self.replay.is_empty() {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.flush_before_stream(network)?;
+
+        let now = self.last_outgoing_timer.now();
+        let pid = match self
+            .state
+            .handle_outgoing_stream(topic_name, qos, retain, &now)
+        {
+            Ok(pid) => pid,
+            Err(StateError::MaxMessagesInflight) => return Err(nb::Error::WouldBlock),
+            Err(e) => return Err(nb::Error::Other(e.into())),
+        };
+
+        self.start_stream(network, topic_name, qos, pid, false, retain, source)
+            .map_err(|e| {
+                if pid.is_some()
+                    && matches!(
+                        e,
+                        nb::Error::Other(EventError::MqttState(StateError::PacketTooLarge))
+                    )
+                {
+                    self.state.outgoing_stream = None;
+                }
+                e
+            })
+    }
+
+    /// Retransmits the inflight streamed publish, reading its payload from
+    /// `source` again. Like `publish_stream`, every call writes the next
+    /// chunk, returning `WouldBlock` until the full publish is written.
+    pub fn retry_publish_stream<N, P>(
+        &mut self,
+        network: &mut N,
+        source: &mut P,
+    ) -> nb::Result<(), EventError>
+    where
+        N: TcpClientStack<TcpSocket = S> + ?Sized,
+        P: PayloadSource,
+    {
+        match &self.stream_write {
+            Some(write) if write.retry => {
+                return self.write_stream_chunk(network, source).map(drop)
+            }
+            Some(_) => return Err(nb::Error::WouldBlock),
+            None => {}
+        }
+
+        self.flush_before_stream(network)?;
+        let stream = self
+            .state
+            .outgoing_stream
+            .as_ref()
+            .ok_or(EventError::MqttState(StateError::InvalidState))?;
+        let topic_name = stream.topic_name.clone();
+        let (qos, pid, retain) = (stream.qos, stream.pid, stream.retain);
+        self.start_stream(network, &topic_name, qos, Some(pid), true, retain, source)
+            .map(drop)
+    }
+
+    /// Acknowledges an incoming publish delivered with an `AckHandle`, once
+    /// processed by the application. Its ack is sent right away, unless held
+    /// back by the ack of a publish received before it. Returns `WouldBlock`
+    /// while the socket has yet to accept earlier packets.
+    pub fn ack<N: TcpClientStack<TcpSocket = S> + ?Sized>(
+        &mut self,
+        network: &mut N,
+        handle: AckHandle,
+    ) -> nb::Result<(), EventError> {
+        self.network_handle.flush(network)?;
+        if self.stream_write.is_some() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        for packet in self.state.handle_outgoing_ack(handle) {
+            self.network_handle.send_packet(network, &packet)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the bytes the socket didn't accept earlier, which go before a
+    /// streamed publish.
+    fn flush_before_stream<N: TcpClientStack<TcpSocket = S> + ?Sized>(
+        &mut self,
+        network: &mut N,
+    ) -> nb::Result<(), EventError> {
+        self.network_handle
+            .flush(network)
+            .map_err(|e| e.map(|e| self.connection_lost(network, e)))
+    }
+
+    /// Writes the headers of a streamed publish, followed by the first chunk
+    /// of its payload.
+    #[allow(clippy::too_many_arguments)]
+    fn start_stream<N, P>(
+        &mut self,
+        network: &mut N,
+        topic_name: &str,
+        qos: QoS,
+        pid: Option<Pid>,
+        dup: bool,
+        retain: bool,
+        source: &mut P,
+    ) -> nb::Result<Option<Pid>, EventError>
+    where
+        N: TcpClientStack<TcpSocket = S> + ?Sized,
+        P: PayloadSource,
+    {
+        let head = PublishHead::new(
+            topic_name,
+            qos,
+            pid,
+            dup,
+            retain,
+            source.payload_len(),
+            self.state.protocol,
+        )
+        .map_err(|e| nb::Error::Other(e.into()))?;
+        if let Some(maximum) = self.state.session_limits.maximum_packet_size {
+            if head.len() > maximum as usize {
+                return Err(nb::Error::Other(StateError::PacketTooLarge.into()));
+            }
+        }
+        // Headers have to fit the transmit buffer
+        if head.len() - head.payload_len > TX_BUF_LEN {
+            return Err(nb::Error::Other(StateError::PacketTooLarge.into()));
+        }
+
+        let written = self
+            .network_handle
+            .send(network, &head.header)
+            .and_then(|()| {
+                self.network_handle
+                    .send(network, head.topic_name.as_bytes())
+            })
+            .and_then(|()| self.network_handle.send(network, &head.trailer));
+        if let Err(e) = written {
+            return Err(nb::Error::Other(self.connection_lost(network, e)));
+        }
+
+        self.stream_write = Some(StreamWrite {
+            pid,
+            retry: dup,
+            offset: 0,
+            payload_len: head.payload_len,
+        });
+        self.write_stream_chunk(network, source)
+    }
+
+    /// Writes the next chunk of the payload of the streamed publish, once the
+    /// socket accepted the earlier ones. Returns its packet identifier once the
+    /// full publish is written, and `WouldBlock` until then.
+    fn write_stream_chunk<N, P>(
+        &mut self,
+        network: &mut N,
+        source: &mut P,
+    ) -> nb::Result<Option<Pid>, EventError>
+    where
+        N: TcpClientStack<TcpSocket = S> + ?Sized,
+        P: PayloadSource,
+    {
+        self.flush_before_stream(network)?;
+
+        let (offset, payload_len) = match &self.stream_write {
+            Some(write) => (write.offset, write.payload_len),
+            None => return Err(nb::Error::Other(StateError::InvalidState.into())),
+        };
+        let mut written = 0;
+        if offset < payload_len {
+            let mut buf = [0u8; STREAM_CHUNK_SIZE];
+            let chunk = (payload_len - offset).min(buf.len());
+            let read = match source.read(offset, &mut buf[..chunk]) {
+                Ok(read) if read > 0 => read.min(chunk),
+                _ => {
+                    error!("Streamed payload ended prematurely!");
+                    let e = self.connection_lost(network, EventError::PayloadSource);
+                    return Err(nb::Error::Other(e));
+                }
+            };
+            if let Err(e) = self.network_handle.send(network, &buf[..read]) {
+                return Err(nb::Error::Other(self.connection_lost(network, e)));
+            }
+            written = read;
+        }
+
+        match self.stream_write.as_mut() {
+            Some(write) if offset + written < payload_len => {
+                write.offset += written;
+                Err(nb::Error::WouldBlock)
+            }
+            _ => Ok(self.stream_write.take().and_then(|write| write.pid)),
+        }
+    }
+
+    /// Closes the connection after an error outside of `yield_event`, which
+    /// notifies it as `Notification::Disconnected` if it was established.
+    /// Returns the error back.
+    fn connection_lost<N: TcpClientStack<TcpSocket = S> + ?Sized>(
+        &mut self,
+        network: &mut N,
+        e: EventError,
+    ) -> EventError {
+        if self.state.connection_status == MqttConnectionStatus::Connected {
+            self.transition = Some(Notification::Disconnected { reason: e.clone() });
+        }
+        self.close_on_error(network);
+        e
+    }
+
+    /// Disconnects gracefully from the broker, such that it doesn't publish
+    /// the last will. Queued requests keep being sent and outstanding acks
+    /// awaited by `yield_event` until there are none left, or until `timeout`
+    /// passed. A DISCONNECT is then sent and the connection closed once it's
+    /// written, which is notified by `Notification::Closed`. If it isn't
+    /// written before `timeout` passed, the connection is closed regardless,
+    /// which is notified by `Notification::Disconnected` instead.
+    pub fn disconnect_gracefully(&mut self, timeout: TimerDurationU32<TIMER_HZ>) {
+        let now = self.last_outgoing_timer.now();
+        self.shutdown = Some(Shutdown {
+            deadline: now + timeout,
+            disconnect_sent: false,
+        });
+    }
+
+    /// Whether requests are queued, or outgoing publishes and their delivery
+    /// reports are pending
+    fn has_outstanding_requests(&mut self) -> bool {
+        let queued = self
+            .requests
+            .as_mut()
+            .is_some_and(|requests| requests.read().is_some());
+        queued
+            || !self.state.outgoing_pub.is_empty()
+            || !self.state.outgoing_rel.is_empty()
+            || self.state.outgoing_stream.is_some()
+            || self.stream_write.is_some()
+            || !self.state.deliveries.is_empty()
+            || !self.network_handle.tx_buf.is_empty()
+    }
+
+    /// Closes the connection right away, without sending a DISCONNECT, such
+    /// that the broker publishes the last will.
     pub fn disconnect<N: TcpClientStack<TcpSocket = S> + ?Sized>(&mut self, network: &mut N) {
         self.state.connection_status = MqttConnectionStatus::Disconnected;
+        self.network_handle.tx_buf.clear();
+        self.network_handle.pending_connect = None;
+        self.stream_write = None;
+        self.replay = Replay::new();
         if let Some(socket) = self.network_handle.socket.take() {
             network.close(socket).ok();
         }
     }
 
+    /// Closes the connection after an error, delaying the next connection
+    /// attempt according to the `ReconnectPolicy`.
+    fn close_on_error<N: TcpClientStack<TcpSocket = S> + ?Sized>(&mut self, network: &mut N) {
+        self.disconnect(network);
+
+        let now = self.last_outgoing_timer.now();
+        self.backoff.fail(&now, self.options.reconnect_policy());
+        debug!(
+            "Reconnecting in {:?} ms, after {:?} failures",
+            self.backoff.delay_ms, self.backoff.failures
+        );
+    }
+
     fn mqtt_connect<N: TcpClientStack<TcpSocket = S> + ?Sized>(
         &mut self,
         network: &mut N,
@@ -229,26 +829,80 @@ where
                 self.state.last_ping_entry().insert(now);
 
                 self.state.await_pingresp = false;
+                self.state.protocol = self.options.protocol();
+                self.state.session_limits = SessionLimits::default();
                 self.network_handle.rx_buf.init();
+                self.network_handle.rx_buf.stream_publishes = self.options.stream_large_publishes();
+                self.state.manual_acks = self.options.manual_acks();
 
                 let (username, password) = self.options.credentials();
-
-                let connect = Packet::Connect(Connect {
-                    protocol: Protocol::MQTT311,
-                    keep_alive: (self.options.keep_alive_ms() / 1000) as u16,
-                    client_id: self.options.client_id(),
-                    clean_session: self.options.clean_session(),
-                    last_will: self.options.last_will(),
-                    username,
-                    password,
-                });
+                let keep_alive = (self.options.keep_alive_ms() / 1000) as u16;
+                let client_id = match &self.state.assigned_client_id {
+                    Some(client_id) => client_id.as_str(),
+                    None => self.options.client_id(),
+                };
 
                 // mqtt connection with timeout
-                self.network_handle.send_packet(network, &connect)?;
+                match self.state.protocol {
+                    ProtocolVersion::MQTT311 => {
+                        let connect = Packet::Connect(Connect {
+                            protocol: Protocol::MQTT311,
+                            keep_alive,
+                            client_id,
+                            clean_session: self.options.clean_session(),
+                            last_will: self.options.last_will(),
+                            username,
+                            password,
+                        });
+                        self.network_handle
+                            .send_connect(network, |buf| encode_slice(&connect, buf))?;
+                    }
+                    ProtocolVersion::MQTT5 => {
+                        let mut properties: Vec<v5::Property, { 3 + MAX_USER_PROPERTIES }> =
+                            Vec::new();
+                        if let Some(secs) = self.options.session_expiry_interval() {
+                            properties
+                                .push(v5::Property::SessionExpiryInterval(secs))
+                                .ok();
+                        }
+                        // Only as many incoming publishes as can be awaiting their ack
+                        properties
+                            .push(v5::Property::ReceiveMaximum(self.state.receive_maximum()))
+                            .ok();
+                        // Larger publishes are still received when streamed
+                        if !self.options.stream_large_publishes() {
+                            properties
+                                .push(v5::Property::MaximumPacketSize(MAX_PAYLOAD_SIZE as u32))
+                                .ok();
+                        }
+                        for (key, value) in self.options.user_properties() {
+                            properties.push(v5::Property::UserProperty(key, value)).ok();
+                        }
+
+                        let connect = v5::Packet::Connect(v5::Connect {
+                            keep_alive,
+                            client_id,
+                            clean_start: self.options.clean_session(),
+                            last_will: self.options.last_will().map(|last_will| v5::LastWill {
+                                topic: last_will.topic,
+                                message: last_will.message,
+                                qos: last_will.qos,
+                                retain: last_will.retain,
+                                properties: v5::Properties::default(),
+                            }),
+                            username,
+                            password,
+                            properties: v5::Properties::new(&properties),
+                        });
+                        self.network_handle
+                            .send_connect(network, |buf| v5::encode_slice(&connect, buf))?;
+                    }
+                }
                 self.state.handle_outgoing_connect();
                 Err(nb::Error::WouldBlock)
             }
             MqttConnectionStatus::Handshake => {
+                self.network_handle.flush(network)?;
                 let now = self.last_outgoing_timer.now();
 
                 if self
@@ -260,7 +914,8 @@ where
                     return Err(nb::Error::Other(EventError::Timeout));
                 }
 
-                self.network_handle
+                let connected = self
+                    .network_handle
                     .receive(network)
                     .map_err(|e| e.map(EventError::Network))?
                     .decode(&mut self.state)
@@ -269,52 +924,194 @@ where
                             return Err(nb::Error::WouldBlock);
                         }
                         Ok(n.map(|n| n == Notification::ConnAck).unwrap_or(false))
-                    })
+                    })?;
+
+                if connected {
+                    self.replay_session();
+                    self.backoff.reset();
+                    self.transition = Some(Notification::Connected {
+                        session_present: self.state.session_present,
+                    });
+                }
+                Ok(connected)
             }
         }
     }
+
+    /// Subscribes to the topic filters of the registry again, which the
+    /// broker dropped along with the session.
+    fn resubscribe<N: TcpClientStack<TcpSocket = S> + ?Sized>(
+        &mut self,
+        network: &mut N,
+    ) -> Result<(), EventError> {
+        let protocol = self.state.protocol;
+        let (pid, topics) = match self.state.resubscribe() {
+            Some(resubscribe) => resubscribe,
+            None => return Ok(()),
+        };
+
+        let mut buf = [0u8; RESUBSCRIBE_LEN];
+        let len = encode_slice(&Packet::Subscribe(Subscribe::new(&topics)), &mut buf)?;
+        let mut packet = SerializedPacket(&mut buf[..len]);
+        packet.set_pid(pid)?;
+        debug!("Resubscribing to {:?} topic filters", topics.len());
+        self.network_handle
+            .send_request(network, packet.to_inner(), protocol)?;
+        Ok(())
+    }
+
+    /// Queues the pubrels and stored publishes of a resumed session to be
+    /// sent again, in the order they were originally sent. Pubrels go first,
+    /// as their publishes were sent before any of the publishes still
+    /// awaiting acks. Without a session, the topic filters are subscribed to
+    /// again instead.
+    fn replay_session(&mut self) {
+        let now = self.last_outgoing_timer.now();
+        let mut replay = Replay {
+            pubrels: self
+                .state
+                .outgoing_rel
+                .iter()
+                .map(|(pid, _)| *pid)
+                .collect(),
+            publishes: self.state.replay(now),
+            resubscribe: !self.state.session_present,
+        };
+        replay.pubrels.reverse();
+        replay.publishes.reverse();
+        self.replay = replay;
+    }
+
+    /// Sends the next packet queued by `replay_session`, returning whether
+    /// there was any left.
+    fn resend_next<N: TcpClientStack<TcpSocket = S> + ?Sized>(
+        &mut self,
+        network: &mut N,
+    ) -> Result<bool, EventError> {
+        if let Some(pid) = self.replay.pubrels.pop() {
+            let pid = Pid::try_from(pid).map_err(|_| StateError::InvalidState)?;
+            debug!("Resending Pubrel({:?})", pid);
+            self.network_handle
+                .send_packet(network, &Packet::Pubrel(pid))?;
+        } else if let Some(pid) = self.replay.publishes.pop() {
+            debug!("Resending PID {:?}", pid);
+            let protocol = self.state.protocol;
+            let packet = self.state.stored_publish(pid)?;
+            self.network_handle
+                .send_request(network, packet, protocol)?;
+        } else if core::mem::take(&mut self.replay.resubscribe) {
+            self.resubscribe(network)?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+/// Packets of a resumed session to be sent again, one per call of
+/// `yield_event` before any requests. Packet identifiers are popped off the
+/// back.
+struct Replay<const INFLIGHT: usize> {
+    pubrels: Vec<u16, INFLIGHT>,
+    publishes: Vec<u16, INFLIGHT>,
+    /// Subscribe to the topic filters of the registry again
+    resubscribe: bool,
+}
+
+impl<const INFLIGHT: usize> Replay<INFLIGHT> {
+    fn new() -> Self {
+        Self {
+            pubrels: Vec::new(),
+            publishes: Vec::new(),
+            resubscribe: false,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pubrels.is_empty() && self.publishes.is_empty() && !self.resubscribe
+    }
+}
+
+/// Consecutive failures to connect, delaying the next attempt
+struct Backoff<const TIMER_HZ: u32> {
+    failures: u32,
+    since: StartTime<TIMER_HZ>,
+    delay_ms: u32,
+}
+
+impl<const TIMER_HZ: u32> Backoff<TIMER_HZ> {
+    fn new() -> Self {
+        Self {
+            failures: 0,
+            since: StartTime::default(),
+            delay_ms: 0,
+        }
+    }
+
+    /// Whether the next connection attempt is due
+    fn is_due(&self, now: &TimerInstantU32<TIMER_HZ>) -> bool {
+        self.failures == 0 || self.since.has_elapsed(now, self.delay_ms.millis())
+    }
+
+    fn fail(&mut self, now: &TimerInstantU32<TIMER_HZ>, policy: &ReconnectPolicy) {
+        self.failures = self.failures.saturating_add(1);
+        self.delay_ms = policy.delay_ms(self.failures, now.ticks() ^ self.failures);
+        self.since.insert(*now);
+    }
+
+    fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// Stage of connecting the socket to the broker, which is resumed on the next
+/// poll while the network stack is busy.
+#[derive(Debug, Clone, PartialEq)]
+enum PendingConnect {
+    /// Resolving the address of the broker, of the given type
+    Resolving(AddrType),
+    /// Connecting the socket to the resolved address
+    Connecting(SocketAddr),
 }
 
 struct NetworkHandle<S> {
     /// Network socket
     socket: Option<S>,
-    tx_buf: heapless::Vec<u8, 64>,
+    /// Connection of the socket in progress
+    pending_connect: Option<PendingConnect>,
+    /// Address type to connect with when the pending connection fails
+    fallback: Option<AddrType>,
+    tx_buf: TxBuffer,
     rx_buf: PacketBuffer,
 }
 
 impl<S> NetworkHandle<S> {
+    /// Resolves the address of the broker. IP address brokers are connected
+    /// to as they are, without a reverse lookup.
     fn lookup_host<N: Dns + TcpClientStack<TcpSocket = S> + ?Sized>(
         network: &mut N,
         broker: Broker,
         port: u16,
-    ) -> Result<(String<256>, SocketAddr), NetworkError> {
-        match broker {
-            Broker::Hostname(h) => {
-                let socket_addr = SocketAddr::new(
-                    network.get_host_by_name(h, AddrType::IPv4).map_err(|_e| {
-                        info!("Failed to resolve IP!");
-                        NetworkError::DnsLookupFailed
-                    })?,
-                    port,
-                );
-                Ok((String::from(h), socket_addr))
-            }
-            Broker::IpAddr(ip) => {
-                let socket_addr = SocketAddr::new(ip, port);
-                let domain = network.get_host_by_address(ip).map_err(|_e| {
-                    info!("Failed to resolve hostname!");
+        addr_type: AddrType,
+    ) -> nb::Result<SocketAddr, NetworkError> {
+        let ip = match broker {
+            Broker::Hostname(h) => network.get_host_by_name(h, addr_type).map_err(|e| {
+                e.map(|_e| {
+                    info!("Failed to resolve IP!");
                     NetworkError::DnsLookupFailed
-                })?;
-
-                Ok((domain, socket_addr))
-            }
-        }
+                })
+            })?,
+            Broker::IpAddr(ip) => ip,
+        };
+        Ok(SocketAddr::new(ip, port))
     }
 
     fn new() -> Self {
         Self {
             socket: None,
-            tx_buf: heapless::Vec::new(),
+            pending_connect: None,
+            fallback: None,
+            tx_buf: TxBuffer::new(),
             rx_buf: PacketBuffer::new(),
         }
     }
@@ -333,71 +1130,193 @@ impl<S> NetworkHandle<S> {
         }
     }
 
+    /// Connects the socket to the broker, opening it first if there is none.
+    /// Returns `WouldBlock` while the address of the broker is being resolved
+    /// or the socket is being connected, resuming that stage on the next call.
+    ///
+    /// Unless `family` is a single address family, a failed attempt is
+    /// retried once with the other family.
     fn connect<N: Dns + TcpClientStack<TcpSocket = S> + ?Sized>(
         &mut self,
         network: &mut N,
         broker: (Broker, u16),
-    ) -> Result<(), NetworkError> {
-        let socket = match self.socket.as_mut() {
-            None => {
-                let socket = network.socket().map_err(|_e| NetworkError::SocketOpen)?;
-                self.socket.get_or_insert(socket)
-            }
-            Some(socket) => socket,
-        };
+        family: AddressFamily,
+    ) -> nb::Result<(), NetworkError> {
+        if self.socket.is_none() {
+            let socket = network.socket().map_err(|_e| NetworkError::SocketOpen)?;
+            self.socket = Some(socket);
+            self.pending_connect = None;
+        }
 
-        let (broker, port) = broker;
-        let (_hostname, socket_addr) = NetworkHandle::<S>::lookup_host(network, broker, port)?;
+        let pending = self
+            .pending_connect
+            .get_or_insert_with(|| PendingConnect::Resolving(family.addr_type()));
+        if let PendingConnect::Resolving(addr_type) = pending.clone() {
+            // Fallback attempts never resolve the address type of the first one
+            let first_attempt = addr_type == family.addr_type();
+            let (broker, port) = broker;
+            let is_hostname = matches!(broker, Broker::Hostname(_));
+            match NetworkHandle::<S>::lookup_host(network, broker, port, addr_type) {
+                Ok(socket_addr) => {
+                    if first_attempt {
+                        // IP address brokers have no other address to fall back to
+                        self.fallback = if is_hostname {
+                            family.fallback(Some(&socket_addr.ip()))
+                        } else {
+                            None
+                        };
+                    }
+                    self.pending_connect = Some(PendingConnect::Connecting(socket_addr));
+                }
+                Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+                Err(nb::Error::Other(e)) => {
+                    if first_attempt {
+                        self.fallback = family.fallback(None);
+                    }
+                    return self.fall_back(network, e);
+                }
+            }
+        }
 
-        nb::block!(network.connect(socket, socket_addr)).map_err(|_| {
-            if let Some(socket) = self.socket.take() {
-                network.close(socket).ok();
+        if let (Some(socket), Some(PendingConnect::Connecting(socket_addr))) =
+            (self.socket.as_mut(), self.pending_connect.clone())
+        {
+            match network.connect(socket, socket_addr) {
+                Ok(()) => {}
+                Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+                Err(nb::Error::Other(_)) => {
+                    if let Some(socket) = self.socket.take() {
+                        network.close(socket).ok();
+                    }
+                    return self.fall_back(network, NetworkError::SocketConnect);
+                }
             }
-            NetworkError::SocketConnect
-        })
+        }
+
+        self.pending_connect = None;
+        Ok(())
+    }
+
+    /// Resolves the broker again with the fallback address type on a new
+    /// socket, returning `WouldBlock`. Fails the connection with `error` if
+    /// there is no fallback left.
+    fn fall_back<N: TcpClientStack<TcpSocket = S> + ?Sized>(
+        &mut self,
+        network: &mut N,
+        error: NetworkError,
+    ) -> nb::Result<(), NetworkError> {
+        match self.fallback.take() {
+            Some(addr_type) => {
+                debug!("Falling back to other address family!");
+                if self.socket.is_none() {
+                    let socket = network.socket().map_err(|_e| NetworkError::SocketOpen)?;
+                    self.socket = Some(socket);
+                }
+                self.pending_connect = Some(PendingConnect::Resolving(addr_type));
+                Err(nb::Error::WouldBlock)
+            }
+            None => {
+                self.pending_connect = None;
+                if let Some(socket) = self.socket.take() {
+                    network.close(socket).ok();
+                }
+                Err(nb::Error::Other(error))
+            }
+        }
     }
 
-    pub fn send_packet<'d, N: TcpClientStack<TcpSocket = S> + ?Sized>(
+    pub fn send_packet<N: TcpClientStack<TcpSocket = S> + ?Sized>(
         &mut self,
         network: &mut N,
         pkt: &Packet,
-    ) -> Result<usize, EventError> {
-        self.tx_buf.clear();
-        self.tx_buf
-            .resize_default(self.tx_buf.capacity())
-            .unwrap_or_else(|()| unreachable!("Input length equals to the current capacity."));
+    ) -> Result<(), EventError> {
+        self.send_encoded(network, |buf| encode_slice(pkt, buf))
+    }
 
-        let size = encode_slice(&pkt, self.tx_buf.as_mut()).map_err(EventError::Encoding)?;
+    /// Sends a connect packet, which may well exceed the size of control
+    /// packets with its credentials and last will. It is encoded into the
+    /// receive buffer instead, which isn't used before the connack arrives.
+    fn send_connect<N, F>(&mut self, network: &mut N, encode: F) -> Result<(), EventError>
+    where
+        N: TcpClientStack<TcpSocket = S> + ?Sized,
+        F: FnOnce(&mut [u8]) -> Result<usize, v4::Error>,
+    {
+        self.rx_buf.init();
+        let size = encode(self.rx_buf.buffer.as_mut()).map_err(EventError::Encoding)?;
 
         let socket = self
             .socket
             .as_mut()
             .ok_or(EventError::Network(NetworkError::NoSocket))?;
+        let result = self
+            .tx_buf
+            .write(network, socket, &self.rx_buf.buffer[..size]);
 
-        let length = nb::block!(network.send(socket, &self.tx_buf[..size])).map_err(|_| {
-            error!("[send] NetworkError::Write");
-            EventError::Network(NetworkError::Write)
-        })?;
+        self.rx_buf.init();
+        result
+    }
 
-        Ok(length)
+    fn send_encoded<N, F>(&mut self, network: &mut N, encode: F) -> Result<(), EventError>
+    where
+        N: TcpClientStack<TcpSocket = S> + ?Sized,
+        F: FnOnce(&mut [u8]) -> Result<usize, v4::Error>,
+    {
+        let mut buf = [0u8; CONTROL_PACKET_LEN];
+        let size = encode(&mut buf).map_err(EventError::Encoding)?;
+        self.send(network, &buf[..size])
     }
 
-    pub fn send<'d, N: TcpClientStack<TcpSocket = S> + ?Sized>(
+    pub fn send<N: TcpClientStack<TcpSocket = S> + ?Sized>(
         &mut self,
         network: &mut N,
         pkt: &[u8],
-    ) -> Result<usize, EventError> {
+    ) -> Result<(), EventError> {
         let socket = self
             .socket
             .as_mut()
             .ok_or(EventError::Network(NetworkError::NoSocket))?;
 
-        let length = nb::block!(network.send(socket, &pkt)).map_err(|_| {
-            error!("[send] NetworkError::Write");
-            EventError::Network(NetworkError::Write)
-        })?;
+        self.tx_buf.write(network, socket, pkt)
+    }
+
+    /// Writes the bytes the socket didn't accept earlier. Returns
+    /// `WouldBlock` while some are left.
+    fn flush<N: TcpClientStack<TcpSocket = S> + ?Sized>(
+        &mut self,
+        network: &mut N,
+    ) -> nb::Result<(), EventError> {
+        if self.tx_buf.is_empty() {
+            return Ok(());
+        }
+
+        let socket = self
+            .socket
+            .as_mut()
+            .ok_or(EventError::Network(NetworkError::NoSocket))?;
+        self.tx_buf.flush(network, socket)
+    }
 
-        Ok(length)
+    /// Sends a serialized request, adding the empty property length of MQTT 5
+    /// if required by the protocol.
+    fn send_request<N: TcpClientStack<TcpSocket = S> + ?Sized>(
+        &mut self,
+        network: &mut N,
+        pkt: &[u8],
+        protocol: ProtocolVersion,
+    ) -> Result<(), EventError> {
+        match protocol {
+            ProtocolVersion::MQTT311 => self.send(network, pkt),
+            ProtocolVersion::MQTT5 => {
+                let parts = V5Parts::new(pkt)?;
+                self.send(network, &parts.header)?;
+                self.send(network, parts.variable_header)?;
+                self.send(network, &V5Parts::PROPERTIES)?;
+                if !parts.payload.is_empty() {
+                    self.send(network, parts.payload)?;
+                }
+                Ok(())
+            }
+        }
     }
 
     fn receive<N: TcpClientStack<TcpSocket = S> + ?Sized>(
@@ -406,12 +1325,99 @@ impl<S> NetworkHandle<S> {
     ) -> nb::Result<PacketDecoder<'_>, NetworkError> {
         let socket = self.socket.as_mut().ok_or(NetworkError::NoSocket)?;
 
-        self.rx_buf.receive(socket, network)?;
+        match self.rx_buf.receive(socket, network) {
+            // Decode packets already in the buffer, e.g. the rest of a
+            // streamed publish, or packets behind a publish held back, even if
+            // no new bytes arrived
+            Err(nb::Error::WouldBlock) if self.rx_buf.range.end > 0 => {}
+            result => result?,
+        }
 
         Ok(PacketDecoder::new(&mut self.rx_buf))
     }
 }
 
+/// Bytes of outgoing packets which the socket didn't accept right away, which
+/// are written before any others
+struct TxBuffer {
+    pending: Vec<u8, TX_BUF_LEN>,
+}
+
+impl TxBuffer {
+    fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Writes as many pending bytes as the socket accepts. Returns
+    /// `WouldBlock` while some are left.
+    fn flush<N, S>(&mut self, network: &mut N, socket: &mut S) -> nb::Result<(), EventError>
+    where
+        N: TcpClientStack<TcpSocket = S> + ?Sized,
+    {
+        match network.send(socket, &self.pending) {
+            Ok(written) => {
+                let len = self.pending.len();
+                self.pending.copy_within(written.., 0);
+                self.pending.truncate(len - written);
+            }
+            Err(nb::Error::WouldBlock) => {}
+            Err(nb::Error::Other(_)) => {
+                error!("[send] NetworkError::Write");
+                return Err(nb::Error::Other(EventError::Network(NetworkError::Write)));
+            }
+        }
+
+        if self.pending.is_empty() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Writes `bytes` following the pending ones, without blocking. Bytes the
+    /// socket doesn't accept right away become pending. Packets are only
+    /// written once the pending bytes are flushed, so a full packet always
+    /// fits.
+    fn write<N, S>(
+        &mut self,
+        network: &mut N,
+        socket: &mut S,
+        bytes: &[u8],
+    ) -> Result<(), EventError>
+    where
+        N: TcpClientStack<TcpSocket = S> + ?Sized,
+    {
+        let mut written = 0;
+        if self.pending.is_empty() {
+            written = match network.send(socket, bytes) {
+                Ok(written) => written,
+                Err(nb::Error::WouldBlock) => 0,
+                Err(nb::Error::Other(_)) => {
+                    error!("[send] NetworkError::Write");
+                    return Err(EventError::Network(NetworkError::Write));
+                }
+            };
+        }
+
+        self.pending
+            .extend_from_slice(&bytes[written..])
+            .map_err(|_| {
+                error!("Transmit buffer full!");
+                EventError::BufferSize
+            })
+    }
+}
+
 /// A placeholder that keeps a buffer and constructs a packet incrementally.
 /// Given that underlying `TcpClientStack` throws `WouldBlock` in a non-blocking
 /// manner, its packet construction won't block either.
@@ -419,19 +1425,88 @@ impl<S> NetworkHandle<S> {
 struct PacketBuffer {
     range: RangeTo<usize>,
     buffer: Vec<u8, { MAX_PAYLOAD_SIZE }>,
+    /// Incoming packet exceeding the buffer, which is being discarded or
+    /// streamed
+    oversized: Option<Oversized>,
+    /// Stream the payload of oversized publishes rather than discarding them
+    stream_publishes: bool,
+}
+
+/// An incoming packet too large for the `PacketBuffer`. Its bytes are drained
+/// off the socket as they arrive.
+#[derive(Debug)]
+struct Oversized {
+    /// Total size of the packet
+    size: usize,
+    /// Bytes of the packet yet to be drained
+    remaining: usize,
+    /// Variable header, if the packet is a publish
+    publish: Option<OversizedPublish>,
+    /// Payload offset of the next chunk, if the publish is being streamed
+    stream: Option<usize>,
+}
+
+#[derive(Debug)]
+struct OversizedPublish {
+    topic_name: String<256>,
+    /// Whether the topic exceeded `topic_name` or the buffer, and got
+    /// truncated
+    truncated: bool,
+    qospid: (QoS, Option<Pid>),
+    /// Offset of the payload within the packet, if the variable header fit
+    /// into the buffer
+    payload_offset: Option<usize>,
+    /// Offset of the packet identifier within the packet, if it didn't fit
+    /// into the buffer and is read while draining
+    pid_offset: Option<usize>,
+    pid_bytes: [u8; 2],
+}
+
+impl OversizedPublish {
+    /// Picks the bytes of a packet identifier yet to be read out of `chunk`,
+    /// which starts at `offset` within the packet.
+    fn read_pid(&mut self, offset: usize, chunk: &[u8]) {
+        if let Some(pid_offset) = self.pid_offset {
+            for (i, byte) in self.pid_bytes.iter_mut().enumerate() {
+                if let Some(b) = (pid_offset + i)
+                    .checked_sub(offset)
+                    .and_then(|pos| chunk.get(pos))
+                {
+                    *byte = *b;
+                }
+            }
+        }
+    }
+
+    /// QoS and packet identifier, once the publish is drained
+    fn qospid(&self) -> Result<(QoS, Option<Pid>), EncodingError> {
+        match self.pid_offset {
+            Some(_) => {
+                let pid = Pid::try_from(u16::from_be_bytes(self.pid_bytes))?;
+                Ok((self.qospid.0, Some(pid)))
+            }
+            None => Ok(self.qospid),
+        }
+    }
 }
 
 impl PacketBuffer {
     fn new() -> Self {
         let range = ..0;
         let buffer = Vec::new();
-        let mut buf = Self { range, buffer };
+        let mut buf = Self {
+            range,
+            buffer,
+            oversized: None,
+            stream_publishes: false,
+        };
         buf.init();
         buf
     }
 
     /// Fills the buffer with all 0s
     fn init(&mut self) {
+        self.oversized = None;
         self.range.end = 0;
         self.buffer.clear();
         self.buffer
@@ -448,7 +1523,13 @@ impl PacketBuffer {
     /// After decoding a packet, overwrite the used bytes by shifting the buffer
     /// by its length. Assumes the length fits within the buffer's capacity.
     fn rotate(&mut self, length: usize) {
-        self.buffer.copy_within(length.., 0);
+        self.remove(0, length);
+    }
+
+    /// Overwrites the bytes of a packet at `offset` by shifting the rest of
+    /// the buffer over them.
+    fn remove(&mut self, offset: usize, length: usize) {
+        self.buffer.copy_within(offset + length.., offset);
         self.range.end -= length;
         self.buffer.truncate(self.buffer.capacity() - length);
         self.buffer
@@ -456,6 +1537,231 @@ impl PacketBuffer {
             .unwrap_or_else(|()| unreachable!("Length equals to the current capacity."));
     }
 
+    /// Detects an incoming packet exceeding the buffer from its fixed header.
+    /// For publishes, waits for the rest of the variable header as well.
+    fn detect_oversized(
+        &self,
+        protocol: ProtocolVersion,
+    ) -> Result<Option<Oversized>, EncodingError> {
+        let buf = &self.buffer[self.range];
+
+        let (header_len, size) = match packet_size(buf)? {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+        if size <= self.buffer.capacity() {
+            return Ok(None);
+        }
+
+        let header = Header::new(buf[0])?;
+        let publish = match header.typ {
+            PacketType::Publish => {
+                match Self::publish_header(buf, header_len, header.qos, protocol)? {
+                    Some(publish) => Some(publish),
+                    // Wait for the rest of the variable header, unless it can't
+                    // ever fit into the buffer
+                    None if buf.len() < self.buffer.capacity() => return Ok(None),
+                    None => Self::partial_publish_header(buf, header_len, header.qos)?,
+                }
+            }
+            _ => None,
+        };
+
+        Ok(Some(Oversized {
+            size,
+            remaining: size,
+            publish,
+            stream: None,
+        }))
+    }
+
+    /// Reads the variable header of a publish starting at `offset`, if
+    /// received.
+    fn publish_header(
+        buf: &[u8],
+        mut offset: usize,
+        qos: QoS,
+        protocol: ProtocolVersion,
+    ) -> Result<Option<OversizedPublish>, EncodingError> {
+        let topic_len = match buf.get(offset..offset + 2) {
+            Some(len) => ((len[0] as usize) << 8) | len[1] as usize,
+            None => return Ok(None),
+        };
+        offset += 2;
+        let topic = match buf.get(offset..offset + topic_len) {
+            Some(topic) => core::str::from_utf8(topic).map_err(|_| EncodingError::InvalidString)?,
+            None => return Ok(None),
+        };
+        offset += topic_len;
+        let pid = match qos {
+            QoS::AtMostOnce => None,
+            QoS::AtLeastOnce | QoS::ExactlyOnce => match buf.get(offset..offset + 2) {
+                Some(pid) => {
+                    offset += 2;
+                    Some(Pid::try_from(((pid[0] as u16) << 8) | pid[1] as u16)?)
+                }
+                None => return Ok(None),
+            },
+        };
+
+        // Skip the MQTT 5 properties
+        if protocol == ProtocolVersion::MQTT5 {
+            let mut properties_len = 0;
+            for pos in 0..4 {
+                let byte = match buf.get(offset) {
+                    Some(byte) => byte,
+                    None => return Ok(None),
+                };
+                offset += 1;
+                properties_len |= (*byte as usize & 0x7F) << (pos * 7);
+                if byte & 0x80 == 0 {
+                    break;
+                } else if pos == 3 {
+                    return Err(EncodingError::InvalidLength);
+                }
+            }
+            if buf.len() < offset + properties_len {
+                return Ok(None);
+            }
+            offset += properties_len;
+        }
+
+        let (topic_name, truncated) = Self::truncate_topic(topic);
+        Ok(Some(OversizedPublish {
+            topic_name,
+            truncated,
+            qospid: (qos, pid),
+            payload_offset: Some(offset),
+            pid_offset: None,
+            pid_bytes: [0; 2],
+        }))
+    }
+
+    /// Reads as much of the variable header of a publish starting at `offset`
+    /// as fits into the buffer. The topic is truncated to the received part,
+    /// and the packet identifier is read while draining the publish.
+    fn partial_publish_header(
+        buf: &[u8],
+        mut offset: usize,
+        qos: QoS,
+    ) -> Result<Option<OversizedPublish>, EncodingError> {
+        let topic_len = match buf.get(offset..offset + 2) {
+            Some(len) => ((len[0] as usize) << 8) | len[1] as usize,
+            None => return Ok(None),
+        };
+        offset += 2;
+        let received = buf.get(offset..).unwrap_or_default();
+        let topic = &received[..topic_len.min(received.len())];
+        // A multi-byte character may be cut off at the end of the buffer
+        let topic = match core::str::from_utf8(topic) {
+            Ok(topic) => topic,
+            Err(e) if e.error_len().is_none() => core::str::from_utf8(&topic[..e.valid_up_to()])
+                .unwrap_or_else(|_| unreachable!("Prefix is valid UTF-8.")),
+            Err(_) => return Err(EncodingError::InvalidString),
+        };
+
+        let (topic_name, truncated) = Self::truncate_topic(topic);
+        Ok(Some(OversizedPublish {
+            topic_name,
+            truncated: truncated || topic.len() < topic_len,
+            qospid: (qos, None),
+            payload_offset: None,
+            pid_offset: match qos {
+                QoS::AtMostOnce => None,
+                QoS::AtLeastOnce | QoS::ExactlyOnce => Some(offset + topic_len),
+            },
+            pid_bytes: [0; 2],
+        }))
+    }
+
+    /// Truncates topics exceeding the notification, returning whether it got
+    /// truncated
+    fn truncate_topic(topic: &str) -> (String<256>, bool) {
+        let mut topic_name = String::new();
+        for c in topic.chars() {
+            if topic_name.push(c).is_err() {
+                return (topic_name, true);
+            }
+        }
+        (topic_name, false)
+    }
+
+    /// Starts streaming the payload of an oversized publish, skipping past its
+    /// variable header.
+    fn start_stream(&mut self, mut oversized: Oversized) -> Option<Notification> {
+        let publish = oversized.publish.as_ref()?;
+        let payload_offset = publish.payload_offset?;
+        let notification = Notification::PublishStream {
+            topic_name: publish.topic_name.clone(),
+            qos: publish.qospid.0,
+            pid: publish.qospid.1,
+            payload_len: oversized.size - payload_offset,
+        };
+
+        oversized.remaining -= payload_offset;
+        oversized.stream = Some(0);
+        self.rotate(payload_offset);
+        self.oversized = Some(oversized);
+        Some(notification)
+    }
+
+    /// Takes the next payload chunk of a streamed publish off the buffer,
+    /// along with its QoS and packet identifier if it is the last one.
+    #[allow(clippy::type_complexity)]
+    fn next_chunk(
+        &mut self,
+    ) -> Option<(
+        usize,
+        Vec<u8, STREAM_CHUNK_SIZE>,
+        Option<(QoS, Option<Pid>)>,
+    )> {
+        let oversized = self.oversized.as_mut()?;
+        let offset = oversized.stream?;
+        let length = oversized
+            .remaining
+            .min(self.range.end)
+            .min(STREAM_CHUNK_SIZE);
+        if length == 0 && oversized.remaining > 0 {
+            return None;
+        }
+
+        oversized.remaining -= length;
+        oversized.stream = Some(offset + length);
+        let last = oversized.remaining == 0;
+        let payload = Vec::from_slice(&self.buffer[..length])
+            .unwrap_or_else(|()| unreachable!("Length is at most the chunk size."));
+        self.rotate(length);
+
+        let qospid = if last {
+            self.oversized
+                .take()
+                .and_then(|oversized| oversized.publish)
+                .map(|publish| publish.qospid)
+        } else {
+            None
+        };
+        Some((offset, payload, qospid))
+    }
+
+    /// Drains the bytes of an oversized packet. Returns the packet once all of
+    /// its bytes are drained.
+    fn drain_oversized(&mut self) -> Option<Oversized> {
+        let oversized = self.oversized.as_mut()?;
+        let length = oversized.remaining.min(self.range.end);
+        if let Some(publish) = oversized.publish.as_mut() {
+            publish.read_pid(oversized.size - oversized.remaining, &self.buffer[..length]);
+        }
+        oversized.remaining -= length;
+        let drained = oversized.remaining == 0;
+        self.rotate(length);
+
+        if drained {
+            self.oversized.take()
+        } else {
+            None
+        }
+    }
+
     /// Receives bytes from a network socket in non-blocking mode. If incoming
     /// bytes found, the range gets extended covering them.
     fn receive<N, S>(&mut self, socket: &mut S, network: &mut N) -> nb::Result<(), NetworkError>
@@ -463,6 +1769,11 @@ impl PacketBuffer {
         N: TcpClientStack<TcpSocket = S> + ?Sized,
     {
         let buffer = self.buffer();
+        // A buffer filled up with publishes held back is read from once they
+        // are handled
+        if buffer.is_empty() {
+            return Err(nb::Error::WouldBlock);
+        }
         let len = network.receive(socket, buffer).map_err(|e| {
             if matches!(e, nb::Error::WouldBlock) {
                 nb::Error::WouldBlock
@@ -476,6 +1787,23 @@ impl PacketBuffer {
     }
 }
 
+/// Reads the size of the packet at the start of `buf` from its fixed header,
+/// along with the length of the fixed header, if received.
+fn packet_size(buf: &[u8]) -> Result<Option<(usize, usize)>, EncodingError> {
+    let mut remaining_len = 0;
+    for pos in 0..4 {
+        let byte = match buf.get(pos + 1) {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        remaining_len |= (*byte as usize & 0x7F) << (pos * 7);
+        if byte & 0x80 == 0 {
+            return Ok(Some((pos + 2, pos + 2 + remaining_len)));
+        }
+    }
+    Err(EncodingError::InvalidHeader)
+}
+
 /// Provides contextual information for decoding packets. If an incoming packet
 /// is well-formed and has a packet type the underlying state expects, returns a
 /// notification. On an error, cleans up its buffer state.
@@ -524,24 +1852,187 @@ impl<'a> PacketDecoder<'a> {
             .into()
     }
 
-    fn decode<const TIMER_HZ: u32>(
+    fn decode<const TIMER_HZ: u32, const INFLIGHT: usize, const RETRANSMIT_LEN: usize>(
         mut self,
-        state: &mut MqttState<TIMER_HZ>,
+        state: &mut MqttState<TIMER_HZ, INFLIGHT, RETRANSMIT_LEN>,
     ) -> nb::Result<(Option<Notification>, Option<Packet<'static>>), EventError> {
+        if self.packet_buffer.oversized.is_none() {
+            match self.packet_buffer.detect_oversized(state.protocol) {
+                Ok(None) => {}
+                Ok(Some(oversized))
+                    if self.packet_buffer.stream_publishes
+                        && oversized.publish.as_ref().is_some_and(|publish| {
+                            // Duplicate publishes, publishes without room, and
+                            // publishes of which the topic got truncated, are
+                            // drained instead
+                            publish.payload_offset.is_some()
+                                && !publish.truncated
+                                && state.filter_incoming_publish(publish.qospid).is_none()
+                                && !state.is_incoming_publish_blocked(publish.qospid)
+                        }) =>
+                {
+                    return self
+                        .packet_buffer
+                        .start_stream(oversized)
+                        .map(|notification| (Some(notification), None))
+                        .ok_or(nb::Error::WouldBlock);
+                }
+                Ok(Some(oversized)) => {
+                    warn!(
+                        "Incoming packet of {:?} bytes exceeds the buffer, discarding!",
+                        oversized.size
+                    );
+                    self.packet_buffer.oversized = Some(oversized);
+                }
+                Err(e) => {
+                    self.is_err.replace(true);
+                    error!("Packet decode error!");
+                    return Err(EventError::Encoding(e).into());
+                }
+            }
+        }
+
+        if matches!(
+            self.packet_buffer.oversized,
+            Some(Oversized {
+                stream: Some(_),
+                ..
+            })
+        ) {
+            return match self.packet_buffer.next_chunk() {
+                Some((offset, payload, last)) => state
+                    .handle_incoming_publish_chunk(offset, payload, last)
+                    .map_err(EventError::from)
+                    .map_err(nb::Error::from),
+                None => Err(nb::Error::WouldBlock),
+            };
+        }
+
+        if self.packet_buffer.oversized.is_some() {
+            return match self.packet_buffer.drain_oversized() {
+                Some(Oversized {
+                    size,
+                    publish: Some(publish),
+                    ..
+                }) => {
+                    let qospid = publish.qospid().map_err(|e| {
+                        self.is_err.replace(true);
+                        error!("Packet decode error!");
+                        EventError::Encoding(e)
+                    })?;
+                    // Held back, it would have filled up the buffer, leaving
+                    // no room to read the packets behind it. It is discarded
+                    // without an ack instead, for the broker to send it again.
+                    if state.is_incoming_publish_blocked(qospid) {
+                        warn!(
+                            "No room for incoming publish of {:?} bytes, discarding!",
+                            size
+                        );
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    state
+                        .handle_incoming_oversized_publish(publish.topic_name, qospid, size)
+                        .map_err(EventError::from)
+                        .map_err(nb::Error::from)
+                }
+                Some(_) => {
+                    error!("Discarded oversized packet!");
+                    Err(nb::Error::WouldBlock)
+                }
+                None => Err(nb::Error::WouldBlock),
+            };
+        }
+
         let buffer = self.packet_buffer.buffer[self.packet_buffer.range].as_ref();
-        match decode_slice(buffer) {
+
+        // A publish without room is left in the buffer until one frees up,
+        // while the packets behind it are still handled
+        let qospid = match buffer.first().map(|byte| Header::new(*byte)) {
+            Some(Ok(header))
+                if header.typ == PacketType::Publish && header.qos != QoS::AtMostOnce =>
+            {
+                match state.protocol {
+                    ProtocolVersion::MQTT311 => match decode_slice(buffer) {
+                        Ok(Some(Packet::Publish(publish))) => Some((publish.qos, publish.pid)),
+                        _ => None,
+                    },
+                    ProtocolVersion::MQTT5 => match v5::decode_slice(buffer) {
+                        Ok(Some(v5::Packet::Publish(publish))) => Some((publish.qos, publish.pid)),
+                        _ => None,
+                    },
+                }
+            }
+            _ => None,
+        };
+        if qospid.is_some_and(|qospid| state.is_incoming_publish_blocked(qospid)) {
+            return self.decode_behind(state);
+        }
+
+        let result = match state.protocol {
+            ProtocolVersion::MQTT311 => decode_slice(buffer)
+                .map(|packet| packet.map(|packet| state.handle_incoming_packet(packet))),
+            ProtocolVersion::MQTT5 => v5::decode_slice(buffer)
+                .map(|packet| packet.map(|packet| state.handle_incoming_packet_v5(packet))),
+        };
+
+        match result {
             Err(e) => {
                 self.is_err.replace(true);
                 error!("Packet decode error!");
 
                 Err(EventError::Encoding(e).into())
             }
-            Ok(Some(packet)) => {
+            Ok(Some(result)) => {
                 self.is_err.replace(false);
-                state
-                    .handle_incoming_packet(packet)
-                    .map_err(EventError::from)
-                    .map_err(nb::Error::from)
+                result.map_err(EventError::from).map_err(nb::Error::from)
+            }
+            Ok(None) => Err(nb::Error::WouldBlock),
+        }
+    }
+
+    /// Handles the first complete packet behind the publishes held back at
+    /// the start of the buffer, unless it is a publish as well. Publishes are
+    /// left in the buffer, in the order they were received.
+    fn decode_behind<const TIMER_HZ: u32, const INFLIGHT: usize, const RETRANSMIT_LEN: usize>(
+        mut self,
+        state: &mut MqttState<TIMER_HZ, INFLIGHT, RETRANSMIT_LEN>,
+    ) -> nb::Result<(Option<Notification>, Option<Packet<'static>>), EventError> {
+        let buffer = self.packet_buffer.buffer[self.packet_buffer.range].as_ref();
+
+        let mut offset = 0;
+        let (offset, size) = loop {
+            let size = match packet_size(&buffer[offset..]) {
+                Ok(Some((_, size))) if offset + size <= buffer.len() => size,
+                Ok(_) => return Err(nb::Error::WouldBlock),
+                Err(e) => {
+                    self.is_err.replace(true);
+                    return Err(EventError::Encoding(e).into());
+                }
+            };
+            match Header::new(buffer[offset]) {
+                Ok(header) if header.typ == PacketType::Publish => offset += size,
+                _ => break (offset, size),
+            }
+        };
+
+        let packet = &buffer[offset..offset + size];
+        let result = match state.protocol {
+            ProtocolVersion::MQTT311 => decode_slice(packet)
+                .map(|packet| packet.map(|packet| state.handle_incoming_packet(packet))),
+            ProtocolVersion::MQTT5 => v5::decode_slice(packet)
+                .map(|packet| packet.map(|packet| state.handle_incoming_packet_v5(packet))),
+        };
+
+        match result {
+            Err(e) => {
+                self.is_err.replace(true);
+                error!("Packet decode error!");
+
+                Err(EventError::Encoding(e).into())
+            }
+            Ok(Some(result)) => {
+                self.packet_buffer.remove(offset, size);
+                result.map_err(EventError::from).map_err(nb::Error::from)
             }
             Ok(None) => Err(nb::Error::WouldBlock),
         }
@@ -566,11 +2057,18 @@ impl<'a> Drop for PacketDecoder<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::options::RetryPolicy;
     use crate::state::{BoxedPublish, Inflight, StartTime};
+    use crate::IterPayload;
+    use crate::{Client, Delivery, DeliveryStatus};
     use bbqueue::BBBuffer;
     use fugit::TimerInstantU32;
     use heapless::pool::singleton::Pool;
-    use mqttrust::encoding::v4::{Connack, ConnectReturnCode, Error as EncodingError, Pid};
+    use mqttrust::encoding::v4::{
+        Connack, ConnectReturnCode, Error as EncodingError, LastWill, Pid, Suback,
+        SubscribeReturnCodes,
+    };
+    use mqttrust::{Mqtt, MqttDelivery, MqttError};
     use mqttrust::{Publish, QoS};
 
     #[derive(Debug)]
@@ -601,6 +2099,46 @@ mod tests {
     struct MockNetwork {
         pub should_fail_read: bool,
         pub should_fail_write: bool,
+        pub session_present: bool,
+        pub sent: std::vec::Vec<u8>,
+        /// Maximum number of bytes accepted by a single send
+        pub send_limit: Option<usize>,
+        /// Number of host lookups returning `WouldBlock` before resolving
+        pub dns_delay: usize,
+        /// Number of socket connects returning `WouldBlock` before connecting
+        pub connect_delay: usize,
+        /// Whether connects to IPv6 addresses succeed
+        pub ipv6_reachable: bool,
+        /// Address the socket was last connected to
+        pub remote: Option<embedded_nal::SocketAddr>,
+        /// Bytes received in place of a connack, followed by `WouldBlock`
+        /// once they are read
+        pub incoming: Option<std::vec::Vec<u8>>,
+    }
+
+    impl Default for MockNetwork {
+        fn default() -> Self {
+            Self {
+                should_fail_read: false,
+                should_fail_write: false,
+                session_present: false,
+                sent: std::vec::Vec::new(),
+                send_limit: None,
+                dns_delay: 0,
+                connect_delay: 0,
+                ipv6_reachable: true,
+                remote: None,
+                incoming: None,
+            }
+        }
+    }
+
+    fn build_mqttstate() -> MqttState<1000> {
+        let state = MqttState::new();
+        const LEN: usize = 1024 * 10;
+        static mut PUBLISH_MEM: [u8; LEN] = [0u8; LEN];
+        BoxedPublish::grow(unsafe { &mut *core::ptr::addr_of_mut!(PUBLISH_MEM) });
+        state
     }
 
     impl Dns for MockNetwork {
@@ -609,9 +2147,16 @@ mod tests {
         fn get_host_by_name(
             &mut self,
             _hostname: &str,
-            _addr_type: embedded_nal::AddrType,
+            addr_type: embedded_nal::AddrType,
         ) -> nb::Result<embedded_nal::IpAddr, Self::Error> {
-            unimplemented!()
+            if self.dns_delay > 0 {
+                self.dns_delay -= 1;
+                return Err(nb::Error::WouldBlock);
+            }
+            match addr_type {
+                AddrType::IPv6 => Ok(embedded_nal::Ipv6Addr::localhost().into()),
+                _ => Ok(embedded_nal::Ipv4Addr::localhost().into()),
+            }
         }
         fn get_host_by_address(
             &mut self,
@@ -632,8 +2177,16 @@ mod tests {
         fn connect(
             &mut self,
             _socket: &mut Self::TcpSocket,
-            _remote: embedded_nal::SocketAddr,
+            remote: embedded_nal::SocketAddr,
         ) -> nb::Result<(), Self::Error> {
+            if self.connect_delay > 0 {
+                self.connect_delay -= 1;
+                return Err(nb::Error::WouldBlock);
+            }
+            if remote.is_ipv6() && !self.ipv6_reachable {
+                return Err(nb::Error::Other(()));
+            }
+            self.remote = Some(remote);
             Ok(())
         }
 
@@ -649,7 +2202,12 @@ mod tests {
             if self.should_fail_write {
                 Err(nb::Error::Other(()))
             } else {
-                Ok(buffer.len())
+                let len = buffer.len().min(self.send_limit.unwrap_or(usize::MAX));
+                if len == 0 && !buffer.is_empty() {
+                    return Err(nb::Error::WouldBlock);
+                }
+                self.sent.extend_from_slice(&buffer[..len]);
+                Ok(len)
             }
         }
 
@@ -660,9 +2218,17 @@ mod tests {
         ) -> nb::Result<usize, Self::Error> {
             if self.should_fail_read {
                 Err(nb::Error::Other(()))
+            } else if let Some(incoming) = &mut self.incoming {
+                let len = incoming.len().min(buffer.len());
+                if len == 0 {
+                    return Err(nb::Error::WouldBlock);
+                }
+                buffer[..len].copy_from_slice(&incoming[..len]);
+                incoming.drain(..len);
+                Ok(len)
             } else {
                 let connack = Packet::Connack(Connack {
-                    session_present: false,
+                    session_present: self.session_present,
                     code: ConnectReturnCode::Accepted,
                 });
                 let size = encode_slice(&connack, buffer).unwrap();
@@ -677,10 +2243,7 @@ mod tests {
 
     #[test]
     fn success_receive_multiple_packets() {
-        let mut state = MqttState::<1000>::new();
-        const LEN: usize = 1024 * 10;
-        static mut PUBLISH_MEM: [u8; LEN] = [0u8; LEN];
-        BoxedPublish::grow(unsafe { &mut PUBLISH_MEM });
+        let mut state = build_mqttstate();
 
         let mut rx_buf = PacketBuffer::new();
         let connack = Connack {
@@ -726,10 +2289,7 @@ mod tests {
 
     #[test]
     fn failure_receive_multiple_packets() {
-        let mut state = MqttState::<1000>::new();
-        const LEN: usize = 1024 * 10;
-        static mut PUBLISH_MEM: [u8; LEN] = [0u8; LEN];
-        BoxedPublish::grow(unsafe { &mut PUBLISH_MEM });
+        let mut state = build_mqttstate();
 
         let mut rx_buf = PacketBuffer::new();
         let connack_malformed = Connack {
@@ -771,43 +2331,388 @@ mod tests {
     }
 
     #[test]
-    fn retry_behaviour() {
-        static mut Q: BBBuffer<{ 1024 * 10 }> = BBBuffer::new();
-
+    fn hold_back_publish_without_room() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let buf = &mut [0u8; 128];
+        let mut incoming = std::vec::Vec::new();
+        let pid = Pid::try_from(3).unwrap();
+        for packet in [
+            Packet::from(Publish {
+                dup: false,
+                qos: QoS::ExactlyOnce,
+                pid: Some(pid),
+                retain: false,
+                topic_name: "test/a",
+                payload: b"a",
+            }),
+            Packet::from(Publish {
+                dup: false,
+                qos: QoS::AtMostOnce,
+                pid: None,
+                retain: false,
+                topic_name: "test/b",
+                payload: b"b",
+            }),
+            Packet::Pubrel(Pid::try_from(1).unwrap()),
+        ] {
+            let len = encode_slice(&packet, buf).unwrap();
+            incoming.extend_from_slice(&buf[..len]);
+        }
         let mut network = MockNetwork {
-            should_fail_read: false,
-            should_fail_write: false,
+            incoming: Some(incoming),
+            ..Default::default()
         };
 
-        let (_p, c) = unsafe { Q.try_split_framed().unwrap() };
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
         let mut event = EventLoop::new(
             c,
             ClockMock { ticks: 0 },
             MqttOptions::new("client", Broker::Hostname(""), 8883),
         );
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.state.incoming_pub.insert(1).unwrap();
+        event.state.incoming_pub.insert(2).unwrap();
+        event.network_handle.socket = Some(());
 
-        let now = StartTime::new(TimerInstantU32::from_ticks(0));
+        // The publishes are held back, while the pubrel behind them is
+        // handled, which frees up room
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(
+            decode_slice(&network.sent).unwrap(),
+            Some(Packet::Pubcomp(Pid::try_from(1).unwrap()))
+        );
 
-        let topic = "hello/world";
-        let payload = &[1, 2, 3];
+        // The publishes follow in the order they were received
+        let sent = network.sent.len();
+        match event.yield_event(&mut network) {
+            Ok(Notification::Publish(publish)) => assert_eq!(publish.topic_name, "test/a"),
+            n => panic!("Unexpected notification {:?}", n),
+        }
+        assert_eq!(
+            decode_slice(&network.sent[sent..]).unwrap(),
+            Some(Packet::Pubrec(pid))
+        );
+        match event.yield_event(&mut network) {
+            Ok(Notification::Publish(publish)) => assert_eq!(publish.topic_name, "test/b"),
+            n => panic!("Unexpected notification {:?}", n),
+        }
+        assert_eq!(event.network_handle.rx_buf.range.end, 0);
+    }
 
-        let publish = Publish {
+    #[test]
+    fn receive_v5_packets() {
+        let mut state = build_mqttstate();
+
+        let mut rx_buf = PacketBuffer::new();
+        let properties = [
+            v5::Property::ReceiveMaximum(1),
+            v5::Property::ServerKeepAlive(0),
+        ];
+        let connack = v5::Packet::Connack(v5::Connack {
+            session_present: false,
+            reason_code: v5::ReasonCode::Success,
+            properties: v5::Properties::new(&properties),
+        });
+        let publish = v5::Packet::Publish(v5::Publish {
+            dup: false,
             qos: QoS::AtLeastOnce,
             pid: Some(Pid::new()),
-            payload,
-            dup: false,
             retain: false,
-            topic_name: topic,
-        };
+            topic_name: "test/topic",
+            payload: &[0xff; 16],
+            properties: v5::Properties::default(),
+        });
 
-        let mut rx_buf = PacketBuffer::new();
-        let publish_len = encode_slice(&Packet::from(publish.clone()), rx_buf.buffer()).unwrap();
+        let connack_len = v5::encode_slice(&connack, rx_buf.buffer()).unwrap();
+        rx_buf.range.end += connack_len;
+        let publish_len = v5::encode_slice(&publish, rx_buf.buffer()).unwrap();
         rx_buf.range.end += publish_len;
 
-        event
-            .state
+        state.protocol = ProtocolVersion::MQTT5;
+        state.connection_status = MqttConnectionStatus::Handshake;
+        let (n, p) = PacketDecoder::new(&mut rx_buf).decode(&mut state).unwrap();
+        assert_eq!(n, Some(Notification::ConnAck));
+        assert_eq!(p, None);
+        assert_eq!(state.session_limits.receive_maximum, 1);
+        assert_eq!(state.session_limits.server_keep_alive, Some(0));
+
+        let (n, p) = PacketDecoder::new(&mut rx_buf).decode(&mut state).unwrap();
+        match n {
+            Some(Notification::Publish(p)) => assert_eq!(&p.payload, &[0xff; 16]),
+            _ => panic!(),
+        };
+        assert_eq!(p, Some(Packet::Puback(Pid::default())));
+        assert_eq!(rx_buf.range.end, 0);
+    }
+
+    #[test]
+    fn discard_oversized_publish() {
+        let mut state = MqttState::<1000>::new();
+        state.connection_status = MqttConnectionStatus::Connected;
+        let mut rx_buf = PacketBuffer::new();
+
+        let payload = [0xAB; MAX_PAYLOAD_SIZE];
+        let publish = Publish {
+            dup: false,
+            qos: QoS::ExactlyOnce,
+            pid: Some(Pid::try_from(7).unwrap()),
+            retain: true,
+            topic_name: "big/retained",
+            payload: &payload,
+        };
+        let mut packet = [0u8; MAX_PAYLOAD_SIZE + 32];
+        let len = encode_slice(&Packet::from(publish), &mut packet).unwrap();
+        let pingresp_len = encode_slice(&Packet::Pingresp, &mut packet[len..]).unwrap();
+        let packet = &packet[..len + pingresp_len];
+
+        // Feed the packet in chunks, as received from the socket
+        let mut received = 0;
+        let mut notification = None;
+        while received < len {
+            let chunk = rx_buf.buffer().len().min(packet.len() - received).min(1000);
+            rx_buf.buffer()[..chunk].copy_from_slice(&packet[received..received + chunk]);
+            rx_buf.range.end += chunk;
+            received += chunk;
+
+            match PacketDecoder::new(&mut rx_buf).decode(&mut state) {
+                Ok(n) => notification = Some(n),
+                Err(nb::Error::WouldBlock) => {}
+                Err(e) => panic!("Unexpected error {:?}", e),
+            }
+        }
+
+        let (n, p) = notification.unwrap();
+        assert_eq!(
+            n,
+            Some(Notification::OversizedPublish {
+                topic_name: String::from("big/retained"),
+                size: len,
+            })
+        );
+        assert_eq!(p, Some(Packet::Pubrec(Pid::try_from(7).unwrap())));
+        assert!(state.incoming_pub.contains(&7));
+
+        // Packets following the oversized one are decoded as usual
+        rx_buf.buffer()[..packet.len() - received].copy_from_slice(&packet[received..]);
+        rx_buf.range.end += packet.len() - received;
+        state.await_pingresp = true;
+        assert_eq!(
+            PacketDecoder::new(&mut rx_buf).decode(&mut state),
+            Ok((None, None))
+        );
+        assert!(!state.await_pingresp);
+        assert_eq!(rx_buf.range.end, 0);
+    }
+
+    #[test]
+    fn discard_publish_with_topic_exceeding_buffer() {
+        let mut state = MqttState::<1000>::new();
+        state.connection_status = MqttConnectionStatus::Connected;
+        let mut rx_buf = PacketBuffer::new();
+        rx_buf.stream_publishes = true;
+
+        let topic_name = "t".repeat(MAX_PAYLOAD_SIZE + 99);
+        let pid = Pid::try_from(0x1234).unwrap();
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pid: Some(pid),
+            retain: false,
+            topic_name: &topic_name,
+            payload: b"data",
+        };
+        let mut packet = std::vec![0u8; MAX_PAYLOAD_SIZE + 512];
+        let len = encode_slice(&Packet::from(publish), &mut packet).unwrap();
+
+        // Small chunks split the packet identifier across reads
+        let mut received = 0;
+        let mut notifications = std::vec::Vec::new();
+        while received < len {
+            let chunk = rx_buf.buffer().len().min(len - received).min(7);
+            rx_buf.buffer()[..chunk].copy_from_slice(&packet[received..received + chunk]);
+            rx_buf.range.end += chunk;
+            received += chunk;
+
+            match PacketDecoder::new(&mut rx_buf).decode(&mut state) {
+                Ok(n) => notifications.push(n),
+                Err(nb::Error::WouldBlock) => {}
+                Err(e) => panic!("Unexpected error {:?}", e),
+            }
+        }
+
+        // The publish is acked, even though its variable header didn't fit
+        assert_eq!(
+            notifications,
+            [(
+                Some(Notification::OversizedPublish {
+                    topic_name: String::from(&topic_name[..256]),
+                    size: len,
+                }),
+                Some(Packet::Puback(pid))
+            )]
+        );
+        assert_eq!(rx_buf.range.end, 0);
+    }
+
+    #[test]
+    fn stream_oversized_publish() {
+        let mut state = MqttState::<1000>::new();
+        state.connection_status = MqttConnectionStatus::Connected;
+        let mut rx_buf = PacketBuffer::new();
+        rx_buf.stream_publishes = true;
+
+        let payload: std::vec::Vec<u8> = (0..MAX_PAYLOAD_SIZE * 2).map(|i| i as u8).collect();
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pid: Some(Pid::try_from(9).unwrap()),
+            retain: false,
+            topic_name: "firmware/chunk",
+            payload: &payload,
+        };
+        let mut packet = std::vec![0u8; MAX_PAYLOAD_SIZE * 2 + 32];
+        let len = encode_slice(&Packet::from(publish), &mut packet).unwrap();
+        let packet = &packet[..len];
+
+        // Feed the packet in chunks, as received from the socket, decoding
+        // until the buffered bytes are consumed
+        let mut received = 0;
+        let mut notifications = std::vec::Vec::new();
+        let mut ack = None;
+        while received < len {
+            let chunk = rx_buf.buffer().len().min(packet.len() - received).min(1000);
+            rx_buf.buffer()[..chunk].copy_from_slice(&packet[received..received + chunk]);
+            rx_buf.range.end += chunk;
+            received += chunk;
+
+            loop {
+                match PacketDecoder::new(&mut rx_buf).decode(&mut state) {
+                    Ok((n, p)) => {
+                        notifications.push(n.unwrap());
+                        if p.is_some() {
+                            ack = p;
+                        }
+                    }
+                    Err(nb::Error::WouldBlock) => break,
+                    Err(e) => panic!("Unexpected error {:?}", e),
+                }
+            }
+        }
+
+        let mut notifications = notifications.into_iter();
+        assert_eq!(
+            notifications.next(),
+            Some(Notification::PublishStream {
+                topic_name: String::from("firmware/chunk"),
+                qos: QoS::AtLeastOnce,
+                pid: Some(Pid::try_from(9).unwrap()),
+                payload_len: payload.len(),
+            })
+        );
+
+        let mut streamed = std::vec::Vec::new();
+        for notification in notifications {
+            match notification {
+                Notification::PublishChunk {
+                    offset, payload, ..
+                } => {
+                    assert_eq!(offset, streamed.len());
+                    assert!(!payload.is_empty());
+                    streamed.extend_from_slice(&payload);
+                }
+                n => panic!("Unexpected notification {:?}", n),
+            }
+        }
+        assert_eq!(streamed, payload);
+        assert_eq!(ack, Some(Packet::Puback(Pid::try_from(9).unwrap())));
+        assert_eq!(rx_buf.range.end, 0);
+        assert!(rx_buf.oversized.is_none());
+    }
+
+    #[test]
+    fn stream_publish_with_long_topic() {
+        let mut state = MqttState::<1000>::new();
+        state.connection_status = MqttConnectionStatus::Connected;
+        let mut rx_buf = PacketBuffer::new();
+        rx_buf.stream_publishes = true;
+
+        let topic_name = "t".repeat(300);
+        let payload = [0u8; MAX_PAYLOAD_SIZE];
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            pid: None,
+            retain: false,
+            topic_name: &topic_name,
+            payload: &payload,
+        };
+        let mut packet = std::vec![0u8; MAX_PAYLOAD_SIZE + 512];
+        let len = encode_slice(&Packet::from(publish), &mut packet).unwrap();
+
+        let mut received = 0;
+        let mut notifications = std::vec::Vec::new();
+        while received < len {
+            let chunk = rx_buf.buffer().len().min(len - received);
+            rx_buf.buffer()[..chunk].copy_from_slice(&packet[received..received + chunk]);
+            rx_buf.range.end += chunk;
+            received += chunk;
+
+            while let Ok((n, _)) = PacketDecoder::new(&mut rx_buf).decode(&mut state) {
+                notifications.extend(n);
+            }
+        }
+
+        // Streaming would truncate the topic, so the publish is discarded
+        assert_eq!(
+            notifications,
+            [Notification::OversizedPublish {
+                topic_name: String::from(&topic_name[..256]),
+                size: len,
+            }]
+        );
+    }
+
+    #[test]
+    fn retry_behaviour() {
+        static mut Q: BBBuffer<{ 1024 * 10 }> = BBBuffer::new();
+
+        let mut network = MockNetwork::default();
+
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883),
+        );
+
+        let now = StartTime::new(TimerInstantU32::from_ticks(0));
+
+        let topic = "hello/world";
+        let payload = &[1, 2, 3];
+
+        let publish = Publish {
+            qos: QoS::AtLeastOnce,
+            pid: Some(Pid::new()),
+            payload,
+            dup: false,
+            retain: false,
+            topic_name: topic,
+        };
+
+        let mut rx_buf = PacketBuffer::new();
+        let publish_len = encode_slice(&Packet::from(publish.clone()), rx_buf.buffer()).unwrap();
+        rx_buf.range.end += publish_len;
+
+        event
+            .state
+            .retransmit
+            .push(2, &rx_buf.buffer[..rx_buf.range.end])
+            .unwrap();
+        event
+            .state
             .outgoing_pub
-            .insert(2, Inflight::new(now, &rx_buf.buffer[..rx_buf.range.end]))
+            .insert(2, Inflight::new(now, DeliveryToken::default()))
             .unwrap();
 
         event.state.connection_status = MqttConnectionStatus::Handshake;
@@ -815,4 +2720,833 @@ mod tests {
 
         event.connect(&mut network).unwrap();
     }
+
+    #[test]
+    fn connect_with_large_credentials() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork::default();
+
+        let token = [b'x'; 2048];
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883)
+                .set_credentials("username", &token)
+                .set_last_will(LastWill {
+                    topic: "client/status",
+                    message: &[0; 512],
+                    qos: QoS::AtLeastOnce,
+                    retain: true,
+                }),
+        );
+        event.network_handle.socket = Some(());
+
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        match decode_slice(&network.sent).unwrap() {
+            Some(Packet::Connect(connect)) => {
+                assert_eq!(connect.username, Some("username"));
+                assert_eq!(connect.password, Some(&token[..]));
+                assert_eq!(connect.last_will.unwrap().message.len(), 512);
+            }
+            p => panic!("Unexpected packet {:?}", p),
+        }
+        assert_eq!(event.connect(&mut network), Ok(true));
+    }
+
+    #[test]
+    fn connect_v5_maximum_packet_size() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork::default();
+
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 1883)
+                .set_protocol(ProtocolVersion::MQTT5),
+        );
+        event.network_handle.socket = Some(());
+
+        let has_maximum_packet_size = |sent: &[u8]| match v5::decode_slice(sent).unwrap() {
+            Some(v5::Packet::Connect(connect)) => connect
+                .properties
+                .iter()
+                .any(|property| matches!(property, v5::Property::MaximumPacketSize(_))),
+            p => panic!("Unexpected packet {:?}", p),
+        };
+
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert!(has_maximum_packet_size(&network.sent));
+
+        // Streamed publishes may exceed the receive buffer
+        event.disconnect(&mut network);
+        event.options = event.options.clone().set_stream_large_publishes(true);
+        event.network_handle.socket = Some(());
+        network.sent.clear();
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert!(!has_maximum_packet_size(&network.sent));
+    }
+
+    #[test]
+    fn partial_writes() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork {
+            send_limit: Some(0),
+            ..Default::default()
+        };
+
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883),
+        );
+        event.network_handle.socket = Some(());
+
+        // Nothing is accepted by the socket, so the connect is kept
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert!(network.sent.is_empty());
+
+        // Writing resumes where the socket stopped accepting bytes
+        network.send_limit = Some(8);
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(network.sent.len(), 8);
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(network.sent.len(), 16);
+        assert_eq!(event.connect(&mut network), Ok(true));
+        match decode_slice(&network.sent).unwrap() {
+            Some(Packet::Connect(connect)) => assert_eq!(connect.client_id, "client"),
+            p => panic!("Unexpected packet {:?}", p),
+        }
+    }
+
+    #[test]
+    fn pending_writes_hold_back_packets() {
+        static mut Q: BBBuffer<{ 4 * 1024 * 3 }> = BBBuffer::new();
+
+        let mut network = MockNetwork {
+            send_limit: Some(0),
+            ..Default::default()
+        };
+
+        let (p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let client = Client::new(p, "client");
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883),
+        );
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.network_handle.socket = Some(());
+
+        let payload = [0xab; 4000];
+        client.publish("a", &payload, QoS::AtMostOnce).unwrap();
+        client.publish("b", &payload, QoS::AtMostOnce).unwrap();
+
+        // A full packet is kept, while the next one waits in the queue
+        let len = 3 + 3 + payload.len();
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(event.network_handle.tx_buf.pending.len(), len);
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(event.network_handle.tx_buf.pending.len(), len);
+        assert!(network.sent.is_empty());
+
+        network.send_limit = None;
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(network.sent.len(), 2 * len);
+        assert!(event.network_handle.tx_buf.is_empty());
+    }
+
+    #[test]
+    fn reconnect_backoff() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork {
+            should_fail_read: true,
+            ..Default::default()
+        };
+
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname("broker"), 1883),
+        );
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.network_handle.socket = Some(());
+
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::Disconnected {
+                reason: EventError::Network(NetworkError::Read)
+            })
+        );
+        assert_eq!(event.connection_state(), MqttConnectionStatus::Disconnected);
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::Abort(EventError::Network(
+                NetworkError::NoSocket
+            )))
+        );
+
+        // Waits 1 second before reconnecting
+        event.last_outgoing_timer.ticks = 999;
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        event.last_outgoing_timer.ticks = 1_000;
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(
+            event.connect(&mut network),
+            Err(nb::Error::Other(EventError::Network(NetworkError::Read)))
+        );
+        assert!(event.network_handle.socket.is_none());
+
+        // The delay doubles with every failed attempt
+        event.last_outgoing_timer.ticks = 2_999;
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        event.last_outgoing_timer.ticks = 3_000;
+        network.should_fail_read = false;
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(event.connect(&mut network), Ok(true));
+        assert_eq!(event.connection_state(), MqttConnectionStatus::Connected);
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::Connected {
+                session_present: false
+            })
+        );
+        assert_eq!(event.backoff.failures, 0);
+    }
+
+    #[test]
+    fn non_blocking_connect() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork {
+            dns_delay: 2,
+            connect_delay: 1,
+            ..Default::default()
+        };
+
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname("broker"), 1883),
+        );
+
+        // Resolving the broker
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(
+            event.network_handle.pending_connect,
+            Some(PendingConnect::Resolving(AddrType::IPv4))
+        );
+
+        // Connecting the socket
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert!(matches!(
+            event.network_handle.pending_connect,
+            Some(PendingConnect::Connecting(_))
+        ));
+        assert!(network.sent.is_empty());
+
+        // Awaiting the connack
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(event.network_handle.pending_connect, None);
+        assert_eq!(event.connection_state(), MqttConnectionStatus::Handshake);
+        assert!(!network.sent.is_empty());
+
+        assert_eq!(event.connect(&mut network), Ok(true));
+        assert_eq!(event.backoff.failures, 0);
+    }
+
+    #[test]
+    fn connect_to_ip_address() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork::default();
+
+        let ip = embedded_nal::IpAddr::from(embedded_nal::Ipv4Addr::new(10, 0, 0, 1));
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", ip.into(), 8883).set_address_family(AddressFamily::Either),
+        );
+
+        // Connects without a reverse lookup, which the mock doesn't implement
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(network.remote, Some(SocketAddr::new(ip, 8883)));
+        assert_eq!(event.network_handle.fallback, None);
+        assert_eq!(event.connect(&mut network), Ok(true));
+    }
+
+    #[test]
+    fn address_family_fallback() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork {
+            ipv6_reachable: false,
+            ..Default::default()
+        };
+
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname("broker"), 1883)
+                .set_address_family(AddressFamily::PreferIPv6),
+        );
+
+        // IPv6 fails, and is retried with IPv4
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(
+            event.network_handle.pending_connect,
+            Some(PendingConnect::Resolving(AddrType::IPv4))
+        );
+        assert_eq!(event.connect(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(
+            network.remote,
+            Some(SocketAddr::new(
+                embedded_nal::Ipv4Addr::localhost().into(),
+                1883
+            ))
+        );
+        assert_eq!(event.connect(&mut network), Ok(true));
+        event.disconnect(&mut network);
+
+        // Only a single attempt with a single address family
+        event.options = event
+            .options
+            .clone()
+            .set_address_family(AddressFamily::IPv6);
+        assert_eq!(
+            event.connect(&mut network),
+            Err(nb::Error::Other(EventError::Network(
+                NetworkError::SocketConnect
+            )))
+        );
+        assert!(event.network_handle.socket.is_none());
+    }
+
+    #[test]
+    fn resume_session() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork {
+            session_present: true,
+            ..Default::default()
+        };
+
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883).set_clean_session(false),
+        );
+
+        let now = TimerInstantU32::from_ticks(0);
+        let buf = &mut [0u8; 64];
+        for qos in [QoS::ExactlyOnce, QoS::AtLeastOnce, QoS::ExactlyOnce] {
+            let publish = Packet::Publish(Publish {
+                qos,
+                pid: None,
+                payload: &[1, 2, 3],
+                dup: false,
+                retain: false,
+                topic_name: "hello/world",
+            });
+            let len = encode_slice(&publish, buf).unwrap();
+            let mut packet = SerializedPacket(&mut buf[..len]);
+            event
+                .state
+                .handle_outgoing_publish(&mut packet, DeliveryToken::default(), &now)
+                .unwrap();
+
+            // Release the first publish
+            if event.state.last_pid.get() == 2 {
+                event
+                    .state
+                    .handle_incoming_packet(Packet::Pubrec(Pid::try_from(2).unwrap()))
+                    .unwrap();
+            }
+        }
+
+        event.state.connection_status = MqttConnectionStatus::Handshake;
+        event.network_handle.socket = Some(());
+        assert_eq!(event.connect(&mut network), Ok(true));
+        assert!(network.sent.is_empty());
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::Connected {
+                session_present: true
+            })
+        );
+
+        // One packet per call, the pubrel first, followed by the stored
+        // publishes with DUP set
+        for len in [4, 4 + 20, 4 + 20 + 20] {
+            assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+            assert_eq!(network.sent.len(), len);
+        }
+        let mut sent = &network.sent[..];
+        for (typ, pid, dup) in [
+            (PacketType::Pubrel, 2, false),
+            (PacketType::Publish, 3, true),
+            (PacketType::Publish, 4, true),
+        ] {
+            let packet = decode_slice(sent).unwrap().unwrap();
+            let header = Header::new(sent[0]).unwrap();
+            assert_eq!(header.typ, typ);
+            assert_eq!(header.dup, dup);
+            match packet {
+                Packet::Pubrel(p) => assert_eq!(p.get(), pid),
+                Packet::Publish(p) => assert_eq!(p.pid.unwrap().get(), pid),
+                p => panic!("Unexpected packet {:?}", p),
+            }
+            sent = &sent[sent[1] as usize + 2..];
+        }
+        assert!(sent.is_empty());
+        assert_eq!(event.state.outgoing_pub.len(), 2);
+        assert_eq!(event.state.outgoing_rel.len(), 1);
+    }
+
+    #[test]
+    fn resubscribe_without_session() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork::default();
+
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883),
+        );
+
+        let now = TimerInstantU32::from_ticks(0);
+        let topics = [
+            SubscribeTopic {
+                topic_path: "a/+",
+                qos: QoS::AtLeastOnce,
+            },
+            SubscribeTopic {
+                topic_path: "b/#",
+                qos: QoS::AtMostOnce,
+            },
+        ];
+        let buf = &mut [0u8; 64];
+        let len = encode_slice(&Packet::Subscribe(Subscribe::new(&topics)), buf).unwrap();
+        event
+            .state
+            .handle_outgoing_request(
+                &mut SerializedPacket(&mut buf[..len]),
+                DeliveryToken::default(),
+                &now,
+            )
+            .unwrap();
+        let codes = [SubscribeReturnCodes::Success(QoS::AtLeastOnce); 2];
+        event
+            .state
+            .handle_incoming_packet(Packet::Suback(Suback::new(
+                Pid::try_from(2).unwrap(),
+                &codes,
+            )))
+            .unwrap();
+        assert!(event.subscriptions().eq(topics.iter().cloned()));
+
+        event.state.connection_status = MqttConnectionStatus::Handshake;
+        event.network_handle.socket = Some(());
+        assert_eq!(event.connect(&mut network), Ok(true));
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::Connected {
+                session_present: false
+            })
+        );
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+
+        match decode_slice(&network.sent).unwrap() {
+            Some(Packet::Subscribe(subscribe)) => {
+                assert_eq!(subscribe.pid(), Some(Pid::try_from(3).unwrap()));
+                assert!(subscribe.topics().eq(topics.iter().cloned()));
+            }
+            p => panic!("Unexpected packet {:?}", p),
+        }
+    }
+
+    #[test]
+    fn publish_stream() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork::default();
+
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883),
+        );
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.network_handle.socket = Some(());
+
+        let payload: std::vec::Vec<u8> = (0..300u16).map(|i| i as u8).collect();
+        let mut source = IterPayload::new(payload.len(), || (0..300u16).map(|i| i as u8));
+
+        assert_eq!(
+            event.publish_stream(&mut network, "logs/#", QoS::AtLeastOnce, false, &mut source),
+            Err(nb::Error::Other(EventError::Encoding(
+                EncodingError::InvalidTopicName
+            )))
+        );
+
+        // The payload is written one chunk per call
+        let mut publish = |event: &mut EventLoop<'_, '_, _, _, 1000, 1024>,
+                           network: &mut MockNetwork| {
+            event.publish_stream(network, "logs/upload", QoS::AtLeastOnce, false, &mut source)
+        };
+        assert_eq!(
+            publish(&mut event, &mut network),
+            Err(nb::Error::WouldBlock)
+        );
+        assert_eq!(network.sent.len(), 3 + 2 + 11 + 2 + STREAM_CHUNK_SIZE);
+
+        // Other packets wait until the publish is written
+        event.last_outgoing_timer.ticks = 60_000;
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(network.sent.len(), 3 + 2 + 11 + 2 + STREAM_CHUNK_SIZE);
+        event.last_outgoing_timer.ticks = 0;
+
+        let pid = publish(&mut event, &mut network).unwrap().unwrap();
+        match decode_slice(&network.sent).unwrap() {
+            Some(Packet::Publish(publish)) => {
+                assert_eq!(publish.pid, Some(pid));
+                assert_eq!(publish.topic_name, "logs/upload");
+                assert_eq!(publish.payload, payload.as_slice());
+                assert!(!publish.dup);
+            }
+            p => panic!("Unexpected packet {:?}", p),
+        }
+
+        // Only one streamed publish is inflight at a time
+        assert_eq!(
+            publish(&mut event, &mut network),
+            Err(nb::Error::WouldBlock)
+        );
+
+        // Retransmission reads the payload again
+        event.last_outgoing_timer.ticks = 10_000;
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::RetryStreamedPublish(pid))
+        );
+        network.sent.clear();
+        assert_eq!(
+            event.retry_publish_stream(&mut network, &mut source),
+            Err(nb::Error::WouldBlock)
+        );
+        assert_eq!(
+            event.retry_publish_stream(&mut network, &mut source),
+            Ok(())
+        );
+        match decode_slice(&network.sent).unwrap() {
+            Some(Packet::Publish(publish)) => {
+                assert_eq!(publish.pid, Some(pid));
+                assert_eq!(publish.payload, payload.as_slice());
+                assert!(publish.dup);
+            }
+            p => panic!("Unexpected packet {:?}", p),
+        }
+
+        assert_eq!(
+            event.state.handle_incoming_packet(Packet::Puback(pid)),
+            Ok((Some(Notification::Puback(pid)), None))
+        );
+        assert!(event.state.outgoing_stream.is_none());
+    }
+
+    #[test]
+    fn publish_stream_source_failure() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork::default();
+
+        let (_p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883),
+        );
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.network_handle.socket = Some(());
+
+        // The source ends before the announced length
+        let mut source = IterPayload::new(1000, || 0..10u8);
+        assert_eq!(
+            event.publish_stream(&mut network, "logs", QoS::AtMostOnce, false, &mut source),
+            Err(nb::Error::WouldBlock)
+        );
+        assert_eq!(
+            event.publish_stream(&mut network, "logs", QoS::AtMostOnce, false, &mut source),
+            Err(nb::Error::Other(EventError::PayloadSource))
+        );
+
+        // The connection is lost like on any other error
+        assert!(event.network_handle.socket.is_none());
+        assert_eq!(event.backoff.failures, 1);
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::Disconnected {
+                reason: EventError::PayloadSource
+            })
+        );
+    }
+
+    #[test]
+    fn disconnect_gracefully() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork::default();
+
+        let (p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let client = Client::new(p, "client");
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883),
+        );
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.network_handle.socket = Some(());
+
+        // Queued publishes are sent, and their acks awaited
+        let token = client.publish_tracked("a", b"1", QoS::AtLeastOnce).unwrap();
+        event.disconnect_gracefully(1000.millis());
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        let pid = match decode_slice(&network.sent).unwrap() {
+            Some(Packet::Publish(publish)) => publish.pid.unwrap(),
+            p => panic!("Unexpected packet {:?}", p),
+        };
+        event
+            .state
+            .handle_incoming_packet(Packet::Puback(pid))
+            .unwrap();
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::Delivery(Delivery {
+                token,
+                pid: Some(pid),
+                status: DeliveryStatus::Acknowledged,
+            }))
+        );
+
+        network.sent.clear();
+        assert_eq!(event.yield_event(&mut network), Ok(Notification::Closed));
+        assert_eq!(network.sent, [0xe0, 0x00]);
+        assert!(event.network_handle.socket.is_none());
+
+        // Unacked publishes are given up on once the timeout passed
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.network_handle.socket = Some(());
+        client.publish("a", b"2", QoS::AtLeastOnce).unwrap();
+        event.disconnect_gracefully(1000.millis());
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+
+        network.sent.clear();
+        event.last_outgoing_timer.ticks = 1_000;
+        assert_eq!(event.yield_event(&mut network), Ok(Notification::Closed));
+        assert_eq!(network.sent, [0xe0, 0x00]);
+
+        // The connection is closed once the socket accepted the DISCONNECT
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.network_handle.socket = Some(());
+        event.state.outgoing_pub.clear();
+        network.sent.clear();
+        network.send_limit = Some(0);
+        event.disconnect_gracefully(1000.millis());
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        assert!(event.network_handle.socket.is_some());
+        network.send_limit = Some(1);
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(event.yield_event(&mut network), Ok(Notification::Closed));
+        assert_eq!(network.sent, [0xe0, 0x00]);
+
+        // A DISCONNECT which isn't written before the timeout is a failure
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.network_handle.socket = Some(());
+        network.sent.clear();
+        network.send_limit = Some(0);
+        event.disconnect_gracefully(1000.millis());
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        event.last_outgoing_timer.ticks = 3_000;
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::Disconnected {
+                reason: EventError::Timeout
+            })
+        );
+        assert!(network.sent.is_empty());
+        assert!(event.network_handle.socket.is_none());
+    }
+
+    #[test]
+    fn delivery_tokens() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork::default();
+
+        let (p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let client = Client::new(p, "client");
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883),
+        );
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.network_handle.socket = Some(());
+
+        let first = client.publish_tracked("a", b"1", QoS::AtLeastOnce).unwrap();
+        let second = client.publish_tracked("b", b"2", QoS::AtLeastOnce).unwrap();
+        assert_ne!(first, second);
+
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+
+        let pid = match decode_slice(&network.sent[network.sent.len() - 8..]).unwrap() {
+            Some(Packet::Publish(publish)) => publish.pid.unwrap(),
+            p => panic!("Unexpected packet {:?}", p),
+        };
+        event
+            .state
+            .handle_incoming_packet(Packet::Puback(pid))
+            .unwrap();
+
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::Delivery(Delivery {
+                token: second,
+                pid: Some(pid),
+                status: DeliveryStatus::Acknowledged,
+            }))
+        );
+
+        // Tokens are queued along with the publishes, so an eventloop taking
+        // over the queue reports them alike
+        let c = event.release_queue().unwrap();
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883),
+        );
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.network_handle.socket = Some(());
+
+        let third = client.publish_tracked("c", b"3", QoS::AtLeastOnce).unwrap();
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        event
+            .state
+            .handle_incoming_packet(Packet::Puback(Pid::try_from(2).unwrap()))
+            .unwrap();
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::Delivery(Delivery {
+                token: third,
+                pid: Some(Pid::try_from(2).unwrap()),
+                status: DeliveryStatus::Acknowledged,
+            }))
+        );
+    }
+
+    #[test]
+    fn reject_large_publishes() {
+        static mut Q: BBBuffer<{ MAX_PAYLOAD_SIZE * 2 }> = BBBuffer::new();
+
+        let (p, mut c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let client = Client::new(p, "client");
+
+        let payload = [0u8; MAX_PAYLOAD_SIZE];
+        assert_eq!(
+            client.publish("a", &payload, QoS::AtMostOnce),
+            Err(MqttError::PacketTooLarge)
+        );
+        assert_eq!(
+            client.publish_tracked("a", &payload, QoS::AtLeastOnce),
+            Err(MqttError::PacketTooLarge)
+        );
+        assert!(c.read().is_none());
+
+        // Rejected publishes don't take up a token
+        assert_eq!(
+            client.publish_tracked("a", b"1", QoS::AtLeastOnce),
+            Ok(DeliveryToken::default())
+        );
+    }
+
+    #[test]
+    fn retry_on_silent_link() {
+        static mut Q: BBBuffer<1024> = BBBuffer::new();
+
+        let mut network = MockNetwork {
+            incoming: Some(std::vec::Vec::new()),
+            ..Default::default()
+        };
+
+        let (p, c) = unsafe { (*core::ptr::addr_of!(Q)).try_split_framed().unwrap() };
+        let client = Client::new(p, "client");
+        let mut event = EventLoop::new(
+            c,
+            ClockMock { ticks: 0 },
+            MqttOptions::new("client", Broker::Hostname(""), 8883).set_retry_policy(RetryPolicy {
+                initial_interval_ms: 1_000,
+                backoff_factor: 1,
+                max_interval_ms: 1_000,
+                jitter_ms: 0,
+                max_attempts: Some(2),
+            }),
+        );
+        event.state.connection_status = MqttConnectionStatus::Connected;
+        event.network_handle.socket = Some(());
+
+        let token = client.publish_tracked("a", b"1", QoS::AtLeastOnce).unwrap();
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        let pid = match decode_slice(&network.sent).unwrap() {
+            Some(Packet::Publish(publish)) => publish.pid.unwrap(),
+            p => panic!("Unexpected packet {:?}", p),
+        };
+
+        let sent = network.sent.len();
+        event.last_outgoing_timer.ticks = 999;
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(network.sent.len(), sent);
+
+        // The broker never answers, yet the publish is sent again
+        event.last_outgoing_timer.ticks = 1_000;
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        match decode_slice(&network.sent[sent..]).unwrap() {
+            Some(Packet::Publish(publish)) => {
+                assert!(publish.dup);
+                assert_eq!(publish.pid, Some(pid));
+            }
+            p => panic!("Unexpected packet {:?}", p),
+        }
+
+        // The second attempt was the last one
+        let sent = network.sent.len();
+        event.last_outgoing_timer.ticks = 2_000;
+        assert_eq!(event.yield_event(&mut network), Err(nb::Error::WouldBlock));
+        assert_eq!(network.sent.len(), sent);
+        assert_eq!(
+            event.yield_event(&mut network),
+            Ok(Notification::Delivery(Delivery {
+                token,
+                pid: Some(pid),
+                status: DeliveryStatus::Expired,
+            }))
+        );
+        assert!(event.state.outgoing_pub.is_empty());
+    }
 }
@@ -217,9 +217,12 @@ macro_rules! unwrap {
     }
 }
 
+// Only used by `unwrap!`
+#[allow(dead_code)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct NoneError;
 
+#[allow(dead_code)]
 pub trait Try {
     type Ok;
     type Error;
@@ -11,7 +11,11 @@ mod eventloop;
 mod max_payload;
 mod options;
 mod packet;
+mod payload;
+mod retransmit;
+mod router;
 mod state;
+mod subscriptions;
 
 pub use bbqueue;
 
@@ -20,10 +24,23 @@ use core::convert::TryFrom;
 pub use eventloop::EventLoop;
 use heapless::{String, Vec};
 use max_payload::MAX_PAYLOAD_SIZE;
-pub use mqttrust::encoding::v4::{Pid, Publish, QoS, QosPid, Suback};
+pub use mqttrust::encoding::v4::{Pid, Publish, QoS, QosPid, Suback, SubscribeReturnCodes};
 pub use mqttrust::*;
-pub use options::{Broker, MqttOptions};
+pub use options::{
+    AddressFamily, Broker, MqttOptions, ProtocolVersion, ReconnectPolicy, RetryPolicy,
+};
+pub use payload::{IterPayload, PayloadSource};
+pub use router::{Handler, Router};
 use state::StateError;
+pub use state::{MqttConnectionStatus, SessionLimits};
+pub use subscriptions::{MAX_SUBSCRIPTIONS, MAX_TOPIC_FILTER_LEN};
+
+/// Maximum payload length of a `Notification::PublishChunk`
+pub const STREAM_CHUNK_SIZE: usize = 256;
+
+/// Maximum number of return codes kept in a `Notification::Suback`, which has
+/// one per topic of the subscribe
+pub const MAX_SUBACK_RETURN_CODES: usize = 8;
 
 #[derive(Debug, PartialEq)]
 #[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
@@ -33,12 +50,58 @@ pub struct PublishNotification {
     pub retain: bool,
     pub topic_name: String<256>,
     pub payload: Vec<u8, MAX_PAYLOAD_SIZE>,
+    /// Handle to acknowledge a QoS 1 or 2 publish, when manual acks are
+    /// enabled in the `MqttOptions`. Take it to pass it to `EventLoop::ack`.
+    pub ack: Option<AckHandle>,
+}
+
+/// Pending acknowledgement of an incoming QoS 1 or 2 publish, which is sent by
+/// [EventLoop::ack](crate::EventLoop::ack) once the publish is processed.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub struct AckHandle {
+    pub(crate) qos: QoS,
+    pub(crate) pid: Pid,
+}
+
+impl AckHandle {
+    /// Packet identifier of the publish
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+}
+
+/// Outcome of a QoS 1 or 2 publish
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub enum DeliveryStatus {
+    /// Acknowledged by the broker, by a puback for QoS 1 or a pubcomp for
+    /// QoS 2
+    Acknowledged,
+    /// Rejected by the broker with an MQTT 5 reason code
+    Failed,
+    /// Dropped by the eventloop before it was acknowledged
+    Dropped,
+    /// Not acknowledged within the attempts allowed by the `RetryPolicy`, and
+    /// no longer retransmitted
+    Expired,
+}
+
+/// Delivery report of a publish sent by a `Client`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub struct Delivery {
+    pub token: DeliveryToken,
+    /// Packet identifier the publish was sent with, if any
+    pub pid: Option<Pid>,
+    pub status: DeliveryStatus,
 }
 
 /// Includes incoming packets from the network and other interesting events
 /// happening in the eventloop
 #[derive(Debug, PartialEq)]
 // #[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+#[allow(clippy::large_enum_variant)]
 pub enum Notification {
     /// Incoming connection acknowledge
     ConnAck,
@@ -53,13 +116,65 @@ pub enum Notification {
     Pubrec(Pid),
     /// Incoming pubcomp from the broker
     Pubcomp(Pid),
-    // TODO:
-    // Suback(Suback),
     /// Incoming suback from the broker
-    Suback(Pid),
+    Suback {
+        pid: Pid,
+        /// Granted QoS or failure of each topic, in the order the topics
+        /// were subscribed
+        return_codes: Vec<SubscribeReturnCodes, MAX_SUBACK_RETURN_CODES>,
+    },
     /// Incoming unsuback from the broker
     Unsuback(Pid),
-    // Eventloop error
+    /// Incoming publish exceeding the receive buffer, which got discarded
+    OversizedPublish {
+        /// Topic of the publish, truncated to 256 bytes
+        topic_name: String<256>,
+        /// Size of the discarded packet in bytes
+        size: usize,
+    },
+    /// Incoming publish exceeding the receive buffer, of which the payload
+    /// follows in `PublishChunk`s. Only used when streaming large publishes
+    /// is enabled in the `MqttOptions`. Publishes with topics exceeding 256
+    /// bytes are notified as `OversizedPublish` instead.
+    PublishStream {
+        topic_name: String<256>,
+        qos: QoS,
+        pid: Option<Pid>,
+        /// Length of the payload in bytes
+        payload_len: usize,
+    },
+    /// Payload chunk of the last `PublishStream`. The publish is acknowledged
+    /// along with its last chunk, or by its handle if manual acks are enabled.
+    PublishChunk {
+        /// Offset of the chunk within the payload
+        offset: usize,
+        payload: Vec<u8, STREAM_CHUNK_SIZE>,
+        /// Handle to acknowledge the publish, along with the last chunk
+        ack: Option<AckHandle>,
+    },
+    /// Delivery report of a QoS 1 or 2 publish, following its ack or expiry
+    Delivery(Delivery),
+    /// Retransmission of the streamed publish with this packet identifier is
+    /// due, see `EventLoop::retry_publish_stream`
+    RetryStreamedPublish(Pid),
+    /// The streamed publish with this packet identifier wasn't acknowledged
+    /// within the attempts allowed by the `RetryPolicy`, and is dropped
+    StreamedPublishExpired(Pid),
+    /// The connection to the broker is established, following the connack
+    Connected {
+        /// Whether the broker resumed the session of an earlier connection
+        session_present: bool,
+    },
+    /// The established connection to the broker was lost. Reconnection is
+    /// attempted by `EventLoop::connect` according to the `ReconnectPolicy`.
+    Disconnected {
+        /// Error which closed the connection
+        reason: EventError,
+    },
+    /// The connection was closed gracefully, as requested by
+    /// `EventLoop::disconnect_gracefully`
+    Closed,
+    /// Eventloop error while not connected
     Abort(EventError),
 }
 
@@ -76,12 +191,13 @@ impl<'a> TryFrom<Publish<'a>> for PublishNotification {
                 error!("Failed to convert payload to notification!");
                 StateError::PayloadEncoding
             })?,
+            ack: None,
         })
     }
 }
 
 /// Critical errors during eventloop polling
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
 pub enum EventError {
     MqttState(StateError),
@@ -91,9 +207,11 @@ pub enum EventError {
     BufferSize,
     Clock,
     RequestsNotAvailable,
+    /// Reading a streamed payload failed or ended prematurely
+    PayloadSource,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
 pub enum NetworkError {
     Read,
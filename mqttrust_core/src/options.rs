@@ -1,6 +1,9 @@
-use embedded_nal::{IpAddr, Ipv4Addr};
+use embedded_nal::{AddrType, IpAddr, Ipv4Addr};
 use mqttrust::encoding::v4::LastWill;
 
+/// Maximum number of MQTT 5 user properties in the connect packet
+pub(crate) const MAX_USER_PROPERTIES: usize = 8;
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Broker<'a> {
     Hostname(&'a str),
@@ -25,6 +28,182 @@ impl<'a> From<Ipv4Addr> for Broker<'a> {
     }
 }
 
+/// MQTT protocol version used when connecting to the broker
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub enum ProtocolVersion {
+    /// MQTT 3.1.1
+    MQTT311,
+    /// MQTT 5.0
+    MQTT5,
+}
+
+/// Address family a hostname broker is resolved to. Except for the single
+/// family modes, a failed connection attempt is retried once with the other
+/// family.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub enum AddressFamily {
+    /// IPv4 only
+    IPv4,
+    /// IPv6 only
+    IPv6,
+    /// Either family, as resolved by the network stack
+    Either,
+    /// IPv6, falling back to IPv4
+    PreferIPv6,
+}
+
+impl AddressFamily {
+    /// Address type resolved for the first connection attempt
+    pub(crate) fn addr_type(&self) -> AddrType {
+        match self {
+            AddressFamily::IPv4 => AddrType::IPv4,
+            AddressFamily::IPv6 | AddressFamily::PreferIPv6 => AddrType::IPv6,
+            AddressFamily::Either => AddrType::Either,
+        }
+    }
+
+    /// Address type resolved for the second connection attempt, given the
+    /// address of the first one
+    pub(crate) fn fallback(&self, first: Option<&IpAddr>) -> Option<AddrType> {
+        match (self, first) {
+            (AddressFamily::PreferIPv6, _) => Some(AddrType::IPv4),
+            (AddressFamily::Either, Some(IpAddr::V4(_))) => Some(AddrType::IPv6),
+            (AddressFamily::Either, Some(IpAddr::V6(_))) => Some(AddrType::IPv4),
+            _ => None,
+        }
+    }
+}
+
+/// Retransmission of outgoing QoS 1 and 2 publishes awaiting their ack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub struct RetryPolicy {
+    /// Time in milliseconds to wait for an ack before the first
+    /// retransmission
+    pub initial_interval_ms: u32,
+    /// Factor by which the interval grows with every retransmission. A
+    /// factor of 1 retransmits at a fixed interval.
+    pub backoff_factor: u32,
+    /// Upper bound of the interval in milliseconds, before adding jitter
+    pub max_interval_ms: u32,
+    /// Upper bound of a pseudo-random time in milliseconds added to every
+    /// interval, such that clients don't retransmit in lockstep
+    pub jitter_ms: u32,
+    /// Number of transmissions, including the first one, after which a
+    /// publish that still isn't acked is dropped. `None` retransmits until
+    /// acked.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_interval_ms: 10_000,
+            backoff_factor: 1,
+            max_interval_ms: 10_000,
+            jitter_ms: 0,
+            max_attempts: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Time in milliseconds to wait for an ack after the given number of
+    /// transmissions. The jitter is derived from `seed`.
+    pub(crate) fn interval_ms(&self, attempts: u32, seed: u32) -> u32 {
+        backoff_ms(
+            self.initial_interval_ms,
+            self.backoff_factor,
+            self.max_interval_ms,
+            self.jitter_ms,
+            attempts,
+            seed,
+        )
+    }
+
+    /// Whether a publish is dropped rather than retransmitted after the given
+    /// number of transmissions
+    pub(crate) fn is_exhausted(&self, attempts: u32) -> bool {
+        self.max_attempts.is_some_and(|max| attempts >= max)
+    }
+}
+
+/// Delay between attempts to connect to the broker, after connecting failed or
+/// the connection was lost
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub struct ReconnectPolicy {
+    /// Time in milliseconds to wait before the first reconnect attempt
+    pub initial_delay_ms: u32,
+    /// Factor by which the delay grows with every failed attempt. A factor of
+    /// 1 reconnects at a fixed interval.
+    pub multiplier: u32,
+    /// Upper bound of the delay in milliseconds, before adding jitter
+    pub max_delay_ms: u32,
+    /// Upper bound of a pseudo-random time in milliseconds added to every
+    /// delay, such that clients don't reconnect in lockstep
+    pub jitter_ms: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 1_000,
+            multiplier: 2,
+            max_delay_ms: 60_000,
+            jitter_ms: 0,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Time in milliseconds to wait after the given number of consecutive
+    /// failures. The jitter is derived from `seed`.
+    pub(crate) fn delay_ms(&self, failures: u32, seed: u32) -> u32 {
+        backoff_ms(
+            self.initial_delay_ms,
+            self.multiplier,
+            self.max_delay_ms,
+            self.jitter_ms,
+            failures,
+            seed,
+        )
+    }
+}
+
+/// Exponential backoff of `initial_ms`, growing by `factor` after the first
+/// attempt up to `max_ms`, with up to `jitter_ms` derived from `seed` added.
+fn backoff_ms(
+    initial_ms: u32,
+    factor: u32,
+    max_ms: u32,
+    jitter_ms: u32,
+    attempts: u32,
+    seed: u32,
+) -> u32 {
+    let backoff = factor.saturating_pow(attempts.saturating_sub(1));
+    let interval = initial_ms
+        .saturating_mul(backoff)
+        .min(max_ms.max(initial_ms));
+
+    let jitter = match jitter_ms {
+        0 => 0,
+        jitter_ms => mix(seed) % jitter_ms.saturating_add(1),
+    };
+    interval.saturating_add(jitter)
+}
+
+/// Integer hash scrambling the bits of `x`, used as a cheap source of jitter
+fn mix(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^ (x >> 16)
+}
+
 /// Options to configure the behaviour of mqtt connection
 ///
 /// **Lifetimes**:
@@ -36,6 +215,8 @@ pub struct MqttOptions<'a> {
     broker_addr: Broker<'a>,
     /// broker port
     port: u16,
+    /// server name for TLS SNI and certificate verification
+    server_name: Option<&'a str>,
     /// keep alive time to send pingreq to broker when the connection is idle
     keep_alive_ms: u32,
     /// clean (or) persistent session
@@ -50,6 +231,22 @@ pub struct MqttOptions<'a> {
     // throttle: Duration,
     /// Last will that will be issued on unexpected disconnect
     last_will: Option<LastWill<'a>>,
+    /// MQTT protocol version
+    protocol: ProtocolVersion,
+    /// MQTT 5 session expiry interval in seconds
+    session_expiry_interval: Option<u32>,
+    /// MQTT 5 user properties sent in the connect packet
+    user_properties: &'a [(&'a str, &'a str)],
+    /// deliver publishes exceeding the receive buffer in chunks
+    stream_large_publishes: bool,
+    /// retransmission of unacked publishes
+    retry_policy: RetryPolicy,
+    /// delay between connection attempts
+    reconnect_policy: ReconnectPolicy,
+    /// address family of hostname brokers
+    address_family: AddressFamily,
+    /// acknowledge incoming publishes once processed by the application
+    manual_acks: bool,
 }
 
 impl<'a> MqttOptions<'a> {
@@ -62,6 +259,7 @@ impl<'a> MqttOptions<'a> {
         MqttOptions {
             broker_addr: broker,
             port,
+            server_name: None,
             keep_alive_ms: 60_000,
             clean_session: true,
             client_id: id,
@@ -69,11 +267,19 @@ impl<'a> MqttOptions<'a> {
             credentials: None,
             // throttle: Duration::from_micros(0),
             last_will: None,
+            protocol: ProtocolVersion::MQTT311,
+            session_expiry_interval: None,
+            user_properties: &[],
+            stream_large_publishes: false,
+            retry_policy: RetryPolicy::default(),
+            reconnect_policy: ReconnectPolicy::default(),
+            address_family: AddressFamily::IPv4,
+            manual_acks: false,
         }
     }
 
     /// Broker address
-    pub fn broker(&self) -> (Broker, u16) {
+    pub fn broker(&self) -> (Broker<'_>, u16) {
         (self.broker_addr.clone(), self.port)
     }
 
@@ -89,6 +295,25 @@ impl<'a> MqttOptions<'a> {
         Self { port, ..self }
     }
 
+    /// Server name the TLS layer of the network stack presents through SNI
+    /// and verifies the broker certificate against. Only needed for IP address
+    /// brokers, as it defaults to the hostname of hostname brokers.
+    pub fn set_server_name(self, server_name: &'a str) -> Self {
+        Self {
+            server_name: Some(server_name),
+            ..self
+        }
+    }
+
+    /// Server name of the broker, if any
+    pub fn server_name(&self) -> Option<&'a str> {
+        match (self.server_name, &self.broker_addr) {
+            (Some(server_name), _) => Some(server_name),
+            (None, Broker::Hostname(hostname)) => Some(hostname),
+            (None, Broker::IpAddr(_)) => None,
+        }
+    }
+
     pub fn set_last_will(self, will: LastWill<'a>) -> Self {
         Self {
             last_will: Some(will),
@@ -152,7 +377,9 @@ impl<'a> MqttOptions<'a> {
         self.clean_session
     }
 
-    /// Username and password
+    /// Username and password. Along with the client id and last will, these
+    /// make up the connect packet, which must fit the `max_payload_size_*`
+    /// receive buffer.
     pub fn set_credentials(self, username: &'a str, password: &'a [u8]) -> Self {
         Self {
             credentials: Some((username, password)),
@@ -169,6 +396,127 @@ impl<'a> MqttOptions<'a> {
         }
     }
 
+    /// MQTT protocol version to connect with. Defaults to MQTT 3.1.1
+    pub fn set_protocol(self, protocol: ProtocolVersion) -> Self {
+        Self { protocol, ..self }
+    }
+
+    /// MQTT protocol version
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.protocol
+    }
+
+    /// Number of seconds the broker keeps the session after the connection is
+    /// closed. Only used by MQTT 5, where it replaces the persistence implied
+    /// by `clean_session = false` in MQTT 3.1.1.
+    pub fn set_session_expiry_interval(self, secs: u32) -> Self {
+        Self {
+            session_expiry_interval: Some(secs),
+            ..self
+        }
+    }
+
+    /// Session expiry interval
+    pub fn session_expiry_interval(&self) -> Option<u32> {
+        self.session_expiry_interval
+    }
+
+    /// User properties sent to the broker in the MQTT 5 connect packet
+    pub fn set_user_properties(self, user_properties: &'a [(&'a str, &'a str)]) -> Self {
+        if user_properties.len() > MAX_USER_PROPERTIES {
+            panic!(
+                "At most {} user properties are supported",
+                MAX_USER_PROPERTIES
+            );
+        }
+
+        Self {
+            user_properties,
+            ..self
+        }
+    }
+
+    /// User properties
+    pub fn user_properties(&self) -> &'a [(&'a str, &'a str)] {
+        self.user_properties
+    }
+
+    /// When set `true`, incoming publishes exceeding the receive buffer are
+    /// delivered as a `Notification::PublishStream`, followed by its payload
+    /// in `Notification::PublishChunk`s, instead of being discarded.
+    pub fn set_stream_large_publishes(self, stream_large_publishes: bool) -> Self {
+        Self {
+            stream_large_publishes,
+            ..self
+        }
+    }
+
+    /// Stream large publishes
+    pub fn stream_large_publishes(&self) -> bool {
+        self.stream_large_publishes
+    }
+
+    /// Sets how unacked QoS 1 and 2 publishes are retransmitted. Defaults to
+    /// retransmitting every 10 seconds until acked.
+    pub fn set_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            retry_policy,
+            ..self
+        }
+    }
+
+    /// Retry policy
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Sets how long `EventLoop::connect` waits between connection attempts,
+    /// reporting `WouldBlock` in the meantime. Defaults to 1 second, doubling
+    /// with every failed attempt up to a minute.
+    pub fn set_reconnect_policy(self, reconnect_policy: ReconnectPolicy) -> Self {
+        Self {
+            reconnect_policy,
+            ..self
+        }
+    }
+
+    /// Reconnect policy
+    pub fn reconnect_policy(&self) -> &ReconnectPolicy {
+        &self.reconnect_policy
+    }
+
+    /// Sets the address family a hostname broker is resolved to. Defaults to
+    /// IPv4.
+    pub fn set_address_family(self, address_family: AddressFamily) -> Self {
+        Self {
+            address_family,
+            ..self
+        }
+    }
+
+    /// Address family
+    pub fn address_family(&self) -> AddressFamily {
+        self.address_family
+    }
+
+    /// When set `true`, incoming QoS 1 and 2 publishes aren't acknowledged
+    /// until their `AckHandle` is passed to `EventLoop::ack`, such that the
+    /// broker sends them again if they aren't processed. Acks are still sent
+    /// in the order the publishes were received. While `INFLIGHT` publishes
+    /// await their acks, further publishes are held back, but other packets
+    /// from the broker are still handled.
+    pub fn set_manual_acks(self, manual_acks: bool) -> Self {
+        Self {
+            manual_acks,
+            ..self
+        }
+    }
+
+    /// Manual acks
+    pub fn manual_acks(&self) -> bool {
+        self.manual_acks
+    }
+
     // /// Enables throttling and sets outoing message rate to the specified 'rate'
     // pub fn set_throttle(self, duration: Duration) -> Self {
     //     self.throttle = duration;
@@ -183,7 +531,10 @@ impl<'a> MqttOptions<'a> {
 
 #[cfg(test)]
 mod test {
-    use super::{Ipv4Addr, MqttOptions};
+    use super::{
+        AddrType, AddressFamily, Broker, Ipv4Addr, MqttOptions, ProtocolVersion, ReconnectPolicy,
+        RetryPolicy,
+    };
     use embedded_nal::{IpAddr, Ipv6Addr};
     use mqttrust::{encoding::v4::LastWill, QoS};
 
@@ -274,4 +625,120 @@ mod test {
             (Some("some_user"), Some(&b""[..]))
         );
     }
+
+    #[test]
+    fn protocol() {
+        let opts = MqttOptions::new("client_a", Ipv4Addr::localhost().into(), 1883);
+        assert_eq!(opts.protocol(), ProtocolVersion::MQTT311);
+        assert_eq!(opts.session_expiry_interval(), None);
+        assert_eq!(opts.user_properties(), &[]);
+
+        let opts = opts
+            .set_protocol(ProtocolVersion::MQTT5)
+            .set_session_expiry_interval(3600)
+            .set_user_properties(&[("key", "value")]);
+        assert_eq!(opts.protocol(), ProtocolVersion::MQTT5);
+        assert_eq!(opts.session_expiry_interval(), Some(3600));
+        assert_eq!(opts.user_properties(), &[("key", "value")]);
+    }
+
+    #[test]
+    fn retry_policy() {
+        let opts = MqttOptions::new("client_a", Ipv4Addr::localhost().into(), 1883);
+        let default = opts.retry_policy();
+        assert_eq!(default.interval_ms(1, 0), 10_000);
+        assert_eq!(default.interval_ms(5, 0), 10_000);
+        assert!(!default.is_exhausted(1000));
+
+        let policy = RetryPolicy {
+            initial_interval_ms: 1_000,
+            backoff_factor: 2,
+            max_interval_ms: 5_000,
+            jitter_ms: 0,
+            max_attempts: Some(3),
+        };
+        let opts = opts.set_retry_policy(policy);
+        let policy = opts.retry_policy();
+        assert_eq!(policy.interval_ms(1, 0), 1_000);
+        assert_eq!(policy.interval_ms(2, 0), 2_000);
+        assert_eq!(policy.interval_ms(3, 0), 4_000);
+        assert_eq!(policy.interval_ms(4, 0), 5_000);
+        assert_eq!(policy.interval_ms(40, 0), 5_000);
+        assert!(!policy.is_exhausted(2));
+        assert!(policy.is_exhausted(3));
+
+        let jittered = RetryPolicy {
+            jitter_ms: 500,
+            ..*policy
+        };
+        let intervals: std::vec::Vec<_> =
+            (0..16).map(|seed| jittered.interval_ms(1, seed)).collect();
+        assert!(intervals.iter().all(|&i| (1_000..=1_500).contains(&i)));
+        assert!(intervals.iter().any(|&i| i != intervals[0]));
+    }
+
+    #[test]
+    fn reconnect_policy() {
+        let opts = MqttOptions::new("client_a", Ipv4Addr::localhost().into(), 1883);
+        let default = opts.reconnect_policy();
+        assert_eq!(default.delay_ms(1, 0), 1_000);
+        assert_eq!(default.delay_ms(3, 0), 4_000);
+        assert_eq!(default.delay_ms(10, 0), 60_000);
+
+        let opts = opts.set_reconnect_policy(ReconnectPolicy {
+            initial_delay_ms: 500,
+            multiplier: 1,
+            max_delay_ms: 500,
+            jitter_ms: 100,
+        });
+        let delays: std::vec::Vec<_> = (0..16)
+            .map(|seed| opts.reconnect_policy().delay_ms(7, seed))
+            .collect();
+        assert!(delays.iter().all(|&d| (500..=600).contains(&d)));
+    }
+
+    #[test]
+    fn address_family() {
+        let opts = MqttOptions::new("client_a", Broker::Hostname("broker"), 1883);
+        assert_eq!(opts.address_family(), AddressFamily::IPv4);
+
+        let v4: IpAddr = Ipv4Addr::localhost().into();
+        let v6: IpAddr = Ipv6Addr::localhost().into();
+        assert_eq!(AddressFamily::IPv4.fallback(Some(&v4)), None);
+        assert_eq!(AddressFamily::IPv6.fallback(Some(&v6)), None);
+        assert_eq!(AddressFamily::PreferIPv6.addr_type(), AddrType::IPv6);
+        assert_eq!(
+            AddressFamily::PreferIPv6.fallback(None),
+            Some(AddrType::IPv4)
+        );
+        assert_eq!(
+            AddressFamily::Either.fallback(Some(&v4)),
+            Some(AddrType::IPv6)
+        );
+        assert_eq!(
+            AddressFamily::Either.fallback(Some(&v6)),
+            Some(AddrType::IPv4)
+        );
+        assert_eq!(AddressFamily::Either.fallback(None), None);
+    }
+
+    #[test]
+    fn server_name() {
+        let opts = MqttOptions::new("client_a", Broker::Hostname("broker"), 1883);
+        assert_eq!(opts.server_name(), Some("broker"));
+
+        let opts = MqttOptions::new("client_a", Ipv4Addr::localhost().into(), 8883);
+        assert_eq!(opts.server_name(), None);
+        assert_eq!(
+            opts.set_server_name("broker.local").server_name(),
+            Some("broker.local")
+        );
+    }
+
+    #[test]
+    fn manual_acks() {
+        let opts = MqttOptions::new("client_a", Ipv4Addr::localhost().into(), 1883);
+        assert!(!opts.manual_acks());
+        assert!(opts.set_manual_acks(true).manual_acks());
+    }
 }
@@ -1,13 +1,155 @@
+use heapless::Vec;
 use mqttrust::encoding::v4::{
     decoder::{read_header, Header},
     packet::PacketType,
     utils::{Pid, QoS},
 };
 
+use crate::options::ProtocolVersion;
 use crate::state::StateError;
+use core::convert::TryFrom;
 
 pub struct SerializedPacket<'a>(pub &'a mut [u8]);
 
+/// A serialized MQTT 3.1.1 request, split up to be sent as MQTT 5.
+///
+/// Without properties, the MQTT 5 encoding of publish, subscribe and
+/// unsubscribe requests only differs by an empty property length following the
+/// variable header, which grows the remaining length by one. Requests are kept
+/// in their MQTT 3.1.1 encoding and converted on the way to the socket.
+pub struct V5Parts<'a> {
+    /// Fixed header with the updated remaining length
+    pub header: Vec<u8, 5>,
+    /// Variable header, up to the property length
+    pub variable_header: &'a [u8],
+    /// Payload following the property length
+    pub payload: &'a [u8],
+}
+
+impl<'a> V5Parts<'a> {
+    /// Empty property length
+    pub const PROPERTIES: [u8; 1] = [0];
+
+    pub fn new(packet: &'a [u8]) -> Result<Self, StateError> {
+        let mut offset = 0;
+        let (header, remaining_len) = read_header(packet, &mut offset)
+            .map_err(|_| StateError::InvalidHeader)?
+            .ok_or(StateError::InvalidHeader)?;
+
+        let variable_header_len = match (header.typ, header.qos) {
+            (PacketType::Publish, qos) => {
+                if packet[offset..].len() < 2 {
+                    return Err(StateError::InvalidHeader);
+                }
+                let topic_len = ((packet[offset] as usize) << 8) | packet[offset + 1] as usize;
+                match qos {
+                    QoS::AtMostOnce => 2 + topic_len,
+                    QoS::AtLeastOnce | QoS::ExactlyOnce => 4 + topic_len,
+                }
+            }
+            (PacketType::Subscribe | PacketType::Unsubscribe, _) => 2,
+            _ => return Err(StateError::InvalidHeader),
+        };
+
+        let end = offset + remaining_len;
+        if offset + variable_header_len > end || end > packet.len() {
+            return Err(StateError::InvalidHeader);
+        }
+
+        Ok(Self {
+            header: fixed_header(packet[0], remaining_len + Self::PROPERTIES.len())?,
+            variable_header: &packet[offset..offset + variable_header_len],
+            payload: &packet[offset + variable_header_len..end],
+        })
+    }
+
+    /// Length of the MQTT 5 encoding
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.header.len() + self.variable_header.len() + Self::PROPERTIES.len() + self.payload.len()
+    }
+}
+
+/// Headers of a publish of which the payload is streamed separately.
+pub struct PublishHead<'a> {
+    /// Fixed header and topic length
+    pub header: Vec<u8, 7>,
+    pub topic_name: &'a str,
+    /// Packet identifier and MQTT 5 property length following the topic
+    pub trailer: Vec<u8, 3>,
+    pub payload_len: usize,
+}
+
+impl<'a> PublishHead<'a> {
+    pub fn new(
+        topic_name: &'a str,
+        qos: QoS,
+        pid: Option<Pid>,
+        dup: bool,
+        retain: bool,
+        payload_len: usize,
+        protocol: ProtocolVersion,
+    ) -> Result<Self, StateError> {
+        let topic_len = u16::try_from(topic_name.len()).map_err(|_| StateError::InvalidHeader)?;
+
+        let mut trailer = Vec::new();
+        if qos != QoS::AtMostOnce {
+            let pid = pid.ok_or(StateError::InvalidHeader)?;
+            trailer.extend_from_slice(&pid.get().to_be_bytes()).ok();
+        }
+        if protocol == ProtocolVersion::MQTT5 {
+            trailer.extend_from_slice(&V5Parts::PROPERTIES).ok();
+        }
+
+        let mut byte = match qos {
+            QoS::AtMostOnce => 0b0011_0000,
+            QoS::AtLeastOnce => 0b0011_0010,
+            QoS::ExactlyOnce => 0b0011_0100,
+        };
+        if dup {
+            byte |= 0b0000_1000;
+        }
+        if retain {
+            byte |= 0b0000_0001;
+        }
+        let remaining_len = 2 + topic_name.len() + trailer.len() + payload_len;
+        let mut header: Vec<u8, 7> = Vec::from_slice(&fixed_header(byte, remaining_len)?)
+            .unwrap_or_else(|()| unreachable!("Fixed header is at most 5 bytes."));
+        header.extend_from_slice(&topic_len.to_be_bytes()).ok();
+
+        Ok(Self {
+            header,
+            topic_name,
+            trailer,
+            payload_len,
+        })
+    }
+
+    /// Length of the publish, including its payload
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.header.len() + self.topic_name.len() + self.trailer.len() + self.payload_len
+    }
+}
+
+/// Encodes a fixed header with the given remaining length.
+fn fixed_header(byte: u8, mut len: usize) -> Result<Vec<u8, 5>, StateError> {
+    let mut header = Vec::new();
+    header.push(byte).ok();
+    loop {
+        let mut byte = (len % 0x80) as u8;
+        len /= 0x80;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        header.push(byte).map_err(|_| StateError::InvalidHeader)?;
+        if len == 0 {
+            break;
+        }
+    }
+    Ok(header)
+}
+
 impl<'a> SerializedPacket<'a> {
     pub fn header(&self) -> Result<Header, StateError> {
         Header::new(self.0[0]).map_err(|_| StateError::InvalidHeader)
@@ -47,10 +189,11 @@ impl<'a> SerializedPacket<'a> {
             _ => return Ok(()),
         }
 
-        pid.to_buffer(&mut self.0, &mut offset)
+        pid.to_buffer(self.0, &mut offset)
             .map_err(|_| StateError::PidMissing)
     }
 
+    #[allow(clippy::wrong_self_convention)]
     pub fn to_inner(self) -> &'a mut [u8] {
         self.0
     }
@@ -61,7 +204,10 @@ mod tests {
     use core::convert::TryFrom;
 
     use mqttrust::{
-        encoding::v4::{decode_slice, encode_slice},
+        encoding::{
+            v4::{decode_slice, encode_slice},
+            v5,
+        },
         Packet, Publish, Subscribe, SubscribeTopic,
     };
 
@@ -140,4 +286,78 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    fn to_v5<'a>(packet: &[u8], buf: &'a mut [u8]) -> &'a [u8] {
+        let parts = V5Parts::new(packet).unwrap();
+        let mut len = 0;
+        for part in [
+            &parts.header[..],
+            parts.variable_header,
+            &V5Parts::PROPERTIES,
+            parts.payload,
+        ] {
+            buf[len..len + part.len()].copy_from_slice(part);
+            len += part.len();
+        }
+        assert_eq!(len, parts.len());
+        &buf[..len]
+    }
+
+    #[test]
+    fn publish_as_v5() {
+        let publish = Packet::Publish(Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            pid: None,
+            retain: true,
+            topic_name: "test",
+            payload: &[0xAB; 200],
+        });
+
+        let buf = &mut [0u8; 2048];
+        let len = encode_slice(&publish, buf).unwrap();
+        SerializedPacket(&mut buf[..len])
+            .set_pid(Pid::try_from(12).unwrap())
+            .unwrap();
+        let v5_buf = &mut [0u8; 2048];
+
+        assert_eq!(
+            v5::decode_slice(to_v5(&buf[..len], v5_buf)).unwrap(),
+            Some(v5::Packet::Publish(v5::Publish {
+                dup: false,
+                qos: QoS::AtLeastOnce,
+                pid: Some(Pid::try_from(12).unwrap()),
+                retain: true,
+                topic_name: "test",
+                payload: &[0xAB; 200],
+                properties: v5::Properties::default(),
+            }))
+        );
+    }
+
+    #[test]
+    fn subscribe_as_v5() {
+        let subscribe = Packet::Subscribe(Subscribe::new(&[SubscribeTopic {
+            topic_path: "AWESOME",
+            qos: QoS::ExactlyOnce,
+        }]));
+
+        let buf = &mut [0u8; 2048];
+        let len = encode_slice(&subscribe, buf).unwrap();
+        SerializedPacket(&mut buf[..len])
+            .set_pid(Pid::try_from(65).unwrap())
+            .unwrap();
+        let v5_buf = &mut [0u8; 2048];
+
+        match v5::decode_slice(to_v5(&buf[..len], v5_buf)).unwrap() {
+            Some(v5::Packet::Subscribe(s)) => {
+                assert_eq!(s.pid(), Some(Pid::try_from(65).unwrap()));
+                assert_eq!(
+                    s.topics().next(),
+                    Some(v5::SubscribeTopic::new("AWESOME", QoS::ExactlyOnce))
+                );
+            }
+            p => panic!("Unexpected packet {:?}", p),
+        }
+    }
 }
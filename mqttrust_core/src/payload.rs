@@ -0,0 +1,109 @@
+use core::convert::Infallible;
+
+/// Source of a publish payload of known length, which is read in chunks while
+/// being written to the socket by
+/// [EventLoop::publish_stream](crate::EventLoop::publish_stream).
+///
+/// Reads may start at any offset, such that a QoS 1 or 2 publish can be
+/// retransmitted by reading its payload again.
+pub trait PayloadSource {
+    type Error;
+
+    /// Total length of the payload in bytes
+    fn payload_len(&self) -> usize;
+
+    /// Reads payload bytes starting at `offset` into `buf`. Returns the
+    /// number of bytes read, which is only zero at the end of the payload.
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+impl PayloadSource for &[u8] {
+    type Error = Infallible;
+
+    fn payload_len(&self) -> usize {
+        self.len()
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let data = self.get(offset..).unwrap_or_default();
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+}
+
+/// Payload read from an iterator over its bytes.
+///
+/// The iterator is created by `iter`, which is called again whenever the
+/// payload is read from an earlier offset, e.g. on retransmission.
+pub struct IterPayload<F, I> {
+    iter: F,
+    len: usize,
+    current: Option<(usize, I)>,
+}
+
+impl<F, I> IterPayload<F, I>
+where
+    F: FnMut() -> I,
+    I: Iterator<Item = u8>,
+{
+    pub fn new(len: usize, iter: F) -> Self {
+        Self {
+            iter,
+            len,
+            current: None,
+        }
+    }
+}
+
+impl<F, I> PayloadSource for IterPayload<F, I>
+where
+    F: FnMut() -> I,
+    I: Iterator<Item = u8>,
+{
+    type Error = Infallible;
+
+    fn payload_len(&self) -> usize {
+        self.len
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let (position, mut iter) = match self.current.take() {
+            Some((position, iter)) if position <= offset => (position, iter),
+            _ => (0, (self.iter)()),
+        };
+        if offset > position {
+            iter.nth(offset - position - 1);
+        }
+
+        let mut len = 0;
+        for (byte, value) in buf.iter_mut().zip(&mut iter) {
+            *byte = value;
+            len += 1;
+        }
+
+        self.current = Some((offset + len, iter));
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iter_payload_rereads() {
+        let mut source = IterPayload::new(10, || 0..10u8);
+        let mut buf = [0u8; 4];
+
+        assert_eq!(source.read(0, &mut buf), Ok(4));
+        assert_eq!(buf, [0, 1, 2, 3]);
+        assert_eq!(source.read(6, &mut buf), Ok(4));
+        assert_eq!(buf, [6, 7, 8, 9]);
+        assert_eq!(source.read(10, &mut buf), Ok(0));
+
+        // Reading from an earlier offset restarts the iterator
+        assert_eq!(source.read(2, &mut buf), Ok(4));
+        assert_eq!(buf, [2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,124 @@
+use heapless::Vec;
+
+use crate::state::StateError;
+
+/// Packet identifier and length preceding each stored packet
+const ENTRY_HEADER_LEN: usize = 4;
+
+/// Serialized outgoing publishes awaiting their ack, stored back to back in a
+/// single buffer for retransmission.
+///
+/// Publishes are kept in the order they were sent, and are read and updated
+/// in place when retransmitted. Removing a publish shifts the ones following
+/// it, such that the free space is always at the end of the buffer.
+#[derive(Debug)]
+pub(crate) struct RetransmitBuffer<const N: usize> {
+    buffer: Vec<u8, N>,
+}
+
+impl<const N: usize> RetransmitBuffer<N> {
+    pub(crate) fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    /// Checks whether a packet of `len` bytes can be stored right now.
+    /// Returns `InflightTooLarge` if it would never fit, and
+    /// `MaxMessagesInflight` if it has to wait for other packets to be acked.
+    pub(crate) fn reserve(&self, len: usize) -> Result<(), StateError> {
+        let entry_len = ENTRY_HEADER_LEN + len;
+        if len > u16::MAX as usize || entry_len > N {
+            Err(StateError::InflightTooLarge)
+        } else if entry_len > N - self.buffer.len() {
+            Err(StateError::MaxMessagesInflight)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Stores a packet after the ones already stored.
+    pub(crate) fn push(&mut self, pid: u16, packet: &[u8]) -> Result<(), StateError> {
+        self.reserve(packet.len())?;
+        let len = packet.len() as u16;
+        self.buffer
+            .extend_from_slice(&pid.to_be_bytes())
+            .and_then(|()| self.buffer.extend_from_slice(&len.to_be_bytes()))
+            .and_then(|()| self.buffer.extend_from_slice(packet))
+            .unwrap_or_else(|()| unreachable!("Space is reserved."));
+        Ok(())
+    }
+
+    /// Returns the stored packet with the given packet identifier.
+    pub(crate) fn get_mut(&mut self, pid: u16) -> Option<&mut [u8]> {
+        let (start, end) = self.find(pid)?;
+        Some(&mut self.buffer[start + ENTRY_HEADER_LEN..end])
+    }
+
+    /// Removes the packet with the given packet identifier. Returns `false`
+    /// if no such packet is stored.
+    pub(crate) fn remove(&mut self, pid: u16) -> bool {
+        match self.find(pid) {
+            Some((start, end)) => {
+                let len = self.buffer.len();
+                self.buffer.copy_within(end.., start);
+                self.buffer.truncate(len - (end - start));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes all stored packets.
+    pub(crate) fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Packet identifiers of the stored packets, in the order they were sent.
+    pub(crate) fn pids(&self) -> impl Iterator<Item = u16> + '_ {
+        self.entries().map(|(pid, _, _)| pid)
+    }
+
+    fn find(&self, pid: u16) -> Option<(usize, usize)> {
+        self.entries()
+            .find(|(entry_pid, _, _)| *entry_pid == pid)
+            .map(|(_, start, end)| (start, end))
+    }
+
+    /// Iterates the packet identifier, start and end of each entry.
+    fn entries(&self) -> impl Iterator<Item = (u16, usize, usize)> + '_ {
+        let mut start = 0;
+        core::iter::from_fn(move || {
+            let header = self.buffer.get(start..start + ENTRY_HEADER_LEN)?;
+            let pid = u16::from_be_bytes([header[0], header[1]]);
+            let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let entry = (pid, start, start + ENTRY_HEADER_LEN + len);
+            start = entry.2;
+            Some(entry)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_and_remove_out_of_order() {
+        let mut store = RetransmitBuffer::<32>::new();
+        store.push(1, &[1; 4]).unwrap();
+        store.push(2, &[2; 8]).unwrap();
+        store.push(3, &[3; 2]).unwrap();
+        assert_eq!(store.reserve(8), Err(StateError::MaxMessagesInflight));
+        assert_eq!(store.reserve(40), Err(StateError::InflightTooLarge));
+
+        assert!(store.remove(2));
+        assert!(!store.remove(2));
+        assert_eq!(store.pids().collect::<std::vec::Vec<_>>(), [1, 3]);
+        assert_eq!(store.get_mut(3).unwrap(), &[3; 2]);
+
+        // Freed space is reusable, and order is kept
+        store.push(4, &[4; 8]).unwrap();
+        assert_eq!(store.pids().collect::<std::vec::Vec<_>>(), [1, 3, 4]);
+        assert_eq!(store.get_mut(1).unwrap(), &[1; 4]);
+        assert_eq!(store.get_mut(4).unwrap(), &[4; 8]);
+    }
+}
@@ -0,0 +1,143 @@
+use heapless::Vec;
+use mqttrust::{topic, MqttError};
+
+use crate::{Notification, PublishNotification};
+
+/// Handler of the incoming publishes matching a topic filter
+pub type Handler<'a> = &'a mut dyn FnMut(&mut PublishNotification);
+
+/// Dispatches incoming publishes to the handlers registered for the topic
+/// filters they match, holding up to `N` routes.
+///
+/// A publish matching several topic filters is passed to each of their
+/// handlers, in the order they were registered.
+///
+/// ```
+/// # use mqttrust_core::{Notification, Router};
+/// let mut on_command = |publish: &mut mqttrust_core::PublishNotification| {
+///     // Handle `publish.payload`
+/// };
+///
+/// let mut router = Router::<4>::new();
+/// router.route("devices/+/commands/#", &mut on_command).unwrap();
+///
+/// # let notification = Notification::ConnAck;
+/// if let Some(notification) = router.dispatch(notification) {
+///     // Notifications other than publishes, and unrouted publishes
+/// }
+/// ```
+pub struct Router<'a, const N: usize> {
+    routes: Vec<(&'a str, Handler<'a>), N>,
+}
+
+impl<'a, const N: usize> Router<'a, N> {
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    /// Registers `handler` for the publishes matching `topic_filter`. Returns
+    /// `MqttError::InvalidTopic` if `topic_filter` is not a valid topic
+    /// filter, or `MqttError::Full` if all `N` routes are taken.
+    pub fn route(&mut self, topic_filter: &'a str, handler: Handler<'a>) -> Result<(), MqttError> {
+        topic::validate_topic_filter(topic_filter).map_err(|_| MqttError::InvalidTopic)?;
+        self.routes
+            .push((topic_filter, handler))
+            .map_err(|_| MqttError::Full)
+    }
+
+    /// Dispatches a `Notification::Publish` to the handlers of the topic
+    /// filters it matches. Returns the notification back if it is any other
+    /// notification, or a publish which no route matched.
+    pub fn dispatch(&mut self, notification: Notification) -> Option<Notification> {
+        match notification {
+            Notification::Publish(mut publish) => {
+                if self.dispatch_publish(&mut publish) {
+                    None
+                } else {
+                    Some(Notification::Publish(publish))
+                }
+            }
+            notification => Some(notification),
+        }
+    }
+
+    /// Passes `publish` to the handlers of the topic filters it matches.
+    /// Returns `false` if no route matched.
+    pub fn dispatch_publish(&mut self, publish: &mut PublishNotification) -> bool {
+        let mut routed = false;
+        for (topic_filter, handler) in self.routes.iter_mut() {
+            if topic::matches(&publish.topic_name, topic_filter) {
+                handler(publish);
+                routed = true;
+            }
+        }
+        routed
+    }
+}
+
+impl<'a, const N: usize> Default for Router<'a, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QoS;
+    use core::convert::TryFrom;
+    use heapless::String;
+
+    fn publish(topic_name: &str, payload: &[u8]) -> PublishNotification {
+        PublishNotification {
+            dup: false,
+            qospid: QoS::AtMostOnce,
+            retain: false,
+            topic_name: String::from(topic_name),
+            payload: Vec::from_slice(payload).unwrap(),
+            ack: None,
+        }
+    }
+
+    #[test]
+    fn dispatch_to_matching_routes() {
+        let mut commands = std::vec::Vec::new();
+        let mut all = 0;
+        let mut on_command = |publish: &mut PublishNotification| {
+            commands.push(publish.payload.clone());
+        };
+        let mut on_all = |_: &mut PublishNotification| all += 1;
+
+        let mut router = Router::<2>::new();
+        router.route("devices/+/commands", &mut on_command).unwrap();
+        router.route("devices/#", &mut on_all).unwrap();
+        let mut on_other = |_: &mut PublishNotification| {};
+        assert_eq!(router.route("other", &mut on_other), Err(MqttError::Full));
+
+        assert!(router.dispatch_publish(&mut publish("devices/a/commands", b"on")));
+        assert!(router.dispatch_publish(&mut publish("devices/a/status", b"ok")));
+        assert!(!router.dispatch_publish(&mut publish("other", b"")));
+        assert!(router
+            .dispatch(Notification::Puback(crate::Pid::try_from(1).unwrap()))
+            .is_some());
+        drop(router);
+
+        assert_eq!(commands, [Vec::<u8, 4>::from_slice(b"on").unwrap()]);
+        assert_eq!(all, 2);
+    }
+
+    #[test]
+    fn reject_invalid_filters() {
+        let mut handler = |_: &mut PublishNotification| {};
+        for filter in ["", "devices/#/status", "devices/a+", "devices/\0"] {
+            let mut router = Router::<1>::new();
+            assert_eq!(
+                router.route(filter, &mut handler),
+                Err(MqttError::InvalidTopic),
+                "{}",
+                filter
+            );
+            assert!(router.routes.is_empty());
+        }
+    }
+}
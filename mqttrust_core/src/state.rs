@@ -1,29 +1,45 @@
-use crate::packet::SerializedPacket;
-use crate::Notification;
-#[cfg(not(feature = "std"))]
-use crate::PublishNotification;
-use core::convert::TryInto;
+use crate::options::{ProtocolVersion, RetryPolicy};
+use crate::packet::{SerializedPacket, V5Parts};
+use crate::retransmit::RetransmitBuffer;
+use crate::subscriptions::{Subscriptions, MAX_SUBSCRIPTIONS};
+use crate::{
+    AckHandle, Delivery, DeliveryStatus, Notification, PublishNotification, STREAM_CHUNK_SIZE,
+};
+use core::convert::{TryFrom, TryInto};
+use fugit::ExtU32;
 use fugit::TimerDurationU32;
 use fugit::TimerInstantU32;
 #[cfg(not(feature = "std"))]
 use heapless::{pool, pool::singleton::Pool};
-use heapless::{FnvIndexMap, FnvIndexSet, IndexMap, IndexSet};
+use heapless::{Deque, FnvIndexMap, FnvIndexSet, IndexMap, IndexSet, String, Vec};
 use mqttrust::encoding::v4::*;
+use mqttrust::encoding::v5;
+use mqttrust::DeliveryToken;
 
+/// State of the connection to the broker, see `EventLoop::connection_state`
 #[allow(unused)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
 pub enum MqttConnectionStatus {
+    /// Connect packet sent, awaiting the connack
     Handshake,
+    /// Connection established
     Connected,
+    /// No connection, or one which isn't connected at the MQTT level yet
     Disconnected,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
 pub enum StateError {
     /// Broker's error reply to client's connect packet
     Connect(ConnectReturnCode),
+    /// Broker's MQTT 5 error reply to client's connect packet
+    ConnectReason(v5::ReasonCode),
+    /// Broker closed the MQTT 5 connection with a disconnect packet
+    ServerDisconnect(v5::ReasonCode),
+    /// Request exceeds the maximum packet size accepted by the broker
+    PacketTooLarge,
     /// Invalid state for a given operation
     InvalidState,
     /// Received a packet (ack) which isn't asked for
@@ -36,11 +52,41 @@ pub enum StateError {
     InvalidUtf8,
     /// The maximum number of messages allowed to be simultaneously in-flight has been reached.
     MaxMessagesInflight,
+    /// Publish exceeds the buffer storing messages while in-flight
+    InflightTooLarge,
     /// Non-zero QoS publications require PID
     PidMissing,
     InvalidHeader,
 }
 
+/// Limits announced by the broker in its MQTT 5 connack. Connections using
+/// MQTT 3.1.1 always use the defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub struct SessionLimits {
+    /// Maximum number of unacknowledged QoS 1 and 2 publishes the broker
+    /// accepts
+    pub receive_maximum: u16,
+    /// Maximum packet size the broker accepts, if limited
+    pub maximum_packet_size: Option<u32>,
+    /// Highest topic alias the broker accepts. Outgoing publishes never use
+    /// topic aliases, which keeps within any limit.
+    pub topic_alias_maximum: u16,
+    /// Keep alive in seconds, if the broker overrides the requested one
+    pub server_keep_alive: Option<u16>,
+}
+
+impl Default for SessionLimits {
+    fn default() -> Self {
+        Self {
+            receive_maximum: u16::MAX,
+            maximum_packet_size: None,
+            topic_alias_maximum: 0,
+            server_keep_alive: None,
+        }
+    }
+}
+
 #[cfg(not(feature = "std"))]
 pool!(
     #[allow(non_upper_case_globals)]
@@ -52,7 +98,17 @@ pool!(
 /// Methods will just modify the state of the object without doing any network
 /// operations This abstracts the functionality better so that it's easy to
 /// switch between synchronous code, tokio (or) async/await
-pub struct MqttState<const TIMER_HZ: u32> {
+///
+/// **Generics**:
+/// - `INFLIGHT`: Maximum number of outgoing and incoming QoS 1, 2 publishes in
+///   flight. Must be a power of two.
+/// - `RETRANSMIT_LEN`: Size in bytes of the buffer storing outgoing QoS 1, 2
+///   publishes for retransmission, which is shared by all of them.
+pub struct MqttState<
+    const TIMER_HZ: u32,
+    const INFLIGHT: usize = 2,
+    const RETRANSMIT_LEN: usize = 3072,
+> {
     /// Connection status
     pub connection_status: MqttConnectionStatus,
     /// Status of last ping
@@ -60,15 +116,40 @@ pub struct MqttState<const TIMER_HZ: u32> {
     /// Packet id of the last outgoing packet
     pub last_pid: Pid,
     /// Outgoing QoS 1, 2 publishes which aren't acked yet
-    pub(crate) outgoing_pub: FnvIndexMap<u16, Inflight<TIMER_HZ, 1536>, 2>,
-    /// Packet ids of released QoS 2 publishes
-    pub outgoing_rel: FnvIndexSet<u16, 2>,
+    pub(crate) outgoing_pub: FnvIndexMap<u16, Inflight<TIMER_HZ>, INFLIGHT>,
+    /// Serialized outgoing QoS 1, 2 publishes which aren't acked yet
+    pub(crate) retransmit: RetransmitBuffer<RETRANSMIT_LEN>,
+    /// Outgoing QoS 1, 2 publish of which the payload is streamed, and which
+    /// isn't acked yet
+    pub(crate) outgoing_stream: Option<OutgoingStream<TIMER_HZ>>,
+    /// Packet ids of released QoS 2 publishes, along with their delivery
+    /// tokens, in the order the pubrels were sent
+    pub outgoing_rel: Vec<(u16, Option<DeliveryToken>), INFLIGHT>,
     /// Packet ids on incoming QoS 2 publishes
-    pub incoming_pub: FnvIndexSet<u16, 2>,
+    pub incoming_pub: FnvIndexSet<u16, INFLIGHT>,
+    /// Whether incoming publishes are acked by the application
+    pub(crate) manual_acks: bool,
+    /// Acks of incoming QoS 1, 2 publishes held back until acked by the
+    /// application, in the order the publishes were received
+    pub(crate) incoming_acks: Deque<PendingAck, INFLIGHT>,
+    /// Protocol version of the current connection
+    pub protocol: ProtocolVersion,
+    /// Limits of the current connection
+    pub session_limits: SessionLimits,
+    /// Client identifier assigned by the broker
+    pub assigned_client_id: Option<String<64>>,
+    /// Whether the broker resumed the session of the current connection
+    pub session_present: bool,
+    /// Topic filters subscribed to
+    pub(crate) subscriptions: Subscriptions,
+    /// Delivery reports yet to be notified
+    pub(crate) deliveries: Deliveries<INFLIGHT>,
     last_ping: StartTime<TIMER_HZ>,
 }
 
-impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
+impl<const TIMER_HZ: u32, const INFLIGHT: usize, const RETRANSMIT_LEN: usize>
+    MqttState<TIMER_HZ, INFLIGHT, RETRANSMIT_LEN>
+{
     /// Creates new mqtt state. Same state should be used during a
     /// connection for persistent sessions while new state should
     /// instantiated for clean sessions
@@ -81,7 +162,7 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
                     % core::mem::align_of::<heapless::pool::Node<PublishNotification>>());
 
             static mut PUBLISH_MEM: [u8; LEN] = [0u8; LEN];
-            BoxedPublish::grow(unsafe { &mut PUBLISH_MEM });
+            BoxedPublish::grow(unsafe { &mut *core::ptr::addr_of_mut!(PUBLISH_MEM) });
         }
 
         MqttState {
@@ -90,8 +171,18 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
             last_pid: Pid::new(),
 
             outgoing_pub: IndexMap::new(),
-            outgoing_rel: IndexSet::new(),
+            retransmit: RetransmitBuffer::new(),
+            outgoing_stream: None,
+            outgoing_rel: Vec::new(),
             incoming_pub: IndexSet::new(),
+            manual_acks: false,
+            incoming_acks: Deque::new(),
+            protocol: ProtocolVersion::MQTT311,
+            session_limits: SessionLimits::default(),
+            assigned_client_id: None,
+            session_present: false,
+            subscriptions: Subscriptions::new(),
+            deliveries: Deliveries::new(),
             last_ping: StartTime::default(),
         }
     }
@@ -109,23 +200,42 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
     }
 
     /// Consolidates handling of all outgoing mqtt packet logic. Returns a
-    /// packet which should be put on to the network by the eventloop
+    /// packet which should be put on to the network by the eventloop. The
+    /// `token` of a publish is the one it was queued with by the client.
     pub fn handle_outgoing_request(
         &mut self,
         request: &mut SerializedPacket<'_>,
+        token: DeliveryToken,
         now: &TimerInstantU32<TIMER_HZ>,
     ) -> Result<(), StateError> {
+        if let Some(maximum) = self.session_limits.maximum_packet_size {
+            let len = match self.protocol {
+                ProtocolVersion::MQTT311 => request.0.len(),
+                ProtocolVersion::MQTT5 => V5Parts::new(request.0)?.len(),
+            };
+            if len > maximum as usize {
+                return Err(StateError::PacketTooLarge);
+            }
+        }
+
         match request.header()?.typ {
-            PacketType::Publish => self.handle_outgoing_publish(request, now)?,
+            PacketType::Publish => self.handle_outgoing_publish(request, token, now)?,
             PacketType::Subscribe => {
                 let pid = self.next_pid();
                 trace!("Sending Subscribe({:?})", pid);
-                request.set_pid(pid)?
+                request.set_pid(pid)?;
+                if let Ok(Some(Packet::Subscribe(subscribe))) = decode_slice(request.0) {
+                    self.subscriptions.handle_subscribe(pid.get(), &subscribe);
+                }
             }
             PacketType::Unsubscribe => {
                 let pid = self.next_pid();
                 trace!("Sending Unsubscribe({:?})", pid);
-                request.set_pid(pid)?
+                request.set_pid(pid)?;
+                if let Ok(Some(Packet::Unsubscribe(unsubscribe))) = decode_slice(request.0) {
+                    self.subscriptions
+                        .handle_unsubscribe(pid.get(), &unsubscribe);
+                }
             }
             _ => unreachable!(),
         }
@@ -148,9 +258,11 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
                 .map(|()| (Notification::ConnAck.into(), None)),
             Packet::Pingresp => self.handle_incoming_pingresp(),
             Packet::Publish(publish) => self.handle_incoming_publish(publish),
-            Packet::Suback(suback) => self.handle_incoming_suback(suback),
+            Packet::Suback(suback) => {
+                self.handle_incoming_suback(suback.pid, suback.return_codes())
+            }
             Packet::Unsuback(pid) => self.handle_incoming_unsuback(pid),
-            Packet::Puback(pid) => self.handle_incoming_puback(pid),
+            Packet::Puback(pid) => self.handle_incoming_puback(pid, DeliveryStatus::Acknowledged),
             Packet::Pubrec(pid) => self.handle_incoming_pubrec(pid),
             Packet::Pubrel(pid) => self.handle_incoming_pubrel(pid),
             Packet::Pubcomp(pid) => self.handle_incoming_pubcomp(pid),
@@ -161,37 +273,190 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
         }
     }
 
-    /// Adds next packet identifier to QoS 1 and 2 publish packets and returns
-    /// it by wrapping publish in packet
-    fn handle_outgoing_publish(
+    /// Handles incoming packets of a MQTT 5 connection. The connack is handled
+    /// here, while all other packets are mapped to their MQTT 3.1.1
+    /// counterparts and handled by `handle_incoming_packet`.
+    pub fn handle_incoming_packet_v5<'b>(
+        &mut self,
+        packet: v5::Packet<'b>,
+    ) -> Result<(Option<Notification>, Option<Packet<'static>>), StateError> {
+        let packet = match packet {
+            v5::Packet::Connack(connack) => {
+                return self
+                    .handle_incoming_connack_v5(connack)
+                    .map(|()| (Notification::ConnAck.into(), None))
+            }
+            v5::Packet::Publish(publish) => Packet::Publish(Publish {
+                dup: publish.dup,
+                qos: publish.qos,
+                pid: publish.pid,
+                retain: publish.retain,
+                topic_name: publish.topic_name,
+                payload: publish.payload,
+            }),
+            v5::Packet::Puback(ack) => {
+                let status = if ack.reason_code.is_success() {
+                    DeliveryStatus::Acknowledged
+                } else {
+                    warn!("Puback({:?}) with reason {:?}", ack.pid, ack.reason_code);
+                    DeliveryStatus::Failed
+                };
+                return self.handle_incoming_puback(ack.pid, status);
+            }
+            v5::Packet::Pubrec(ack) if !ack.reason_code.is_success() => {
+                // A failed pubrec ends the QoS 2 flow without a pubrel, and
+                // is only notified as a failed delivery
+                warn!("Pubrec({:?}) with reason {:?}", ack.pid, ack.reason_code);
+                if let Some(token) = self.remove_outgoing_pub(ack.pid) {
+                    self.report_delivery(token, Some(ack.pid), DeliveryStatus::Failed);
+                }
+                return Ok((None, None));
+            }
+            v5::Packet::Pubrec(ack) => Packet::Pubrec(ack.pid),
+            v5::Packet::Pubrel(ack) => Packet::Pubrel(ack.pid),
+            v5::Packet::Pubcomp(ack) => Packet::Pubcomp(ack.pid),
+            v5::Packet::Suback(suback) => {
+                let codes = suback.reason_codes().map(|code| match code {
+                    v5::ReasonCode::Success => SubscribeReturnCodes::Success(QoS::AtMostOnce),
+                    v5::ReasonCode::GrantedQoS1 => SubscribeReturnCodes::Success(QoS::AtLeastOnce),
+                    v5::ReasonCode::GrantedQoS2 => SubscribeReturnCodes::Success(QoS::ExactlyOnce),
+                    _ => SubscribeReturnCodes::Failure,
+                });
+                return self.handle_incoming_suback(suback.pid, codes);
+            }
+            v5::Packet::Unsuback(unsuback) => Packet::Unsuback(unsuback.pid),
+            v5::Packet::Pingresp => Packet::Pingresp,
+            v5::Packet::Disconnect(disconnect) => {
+                error!(
+                    "Disconnected by broker. Reason = {:?}",
+                    disconnect.reason_code
+                );
+                self.connection_status = MqttConnectionStatus::Disconnected;
+                return Err(StateError::ServerDisconnect(disconnect.reason_code));
+            }
+            _ => {
+                error!("Invalid incoming packet!");
+                return Ok((None, None));
+            }
+        };
+
+        self.handle_incoming_packet(packet)
+    }
+
+    /// Adds next packet identifier to QoS 1 and 2 publish packets and stores
+    /// them for retransmission
+    pub(crate) fn handle_outgoing_publish(
         &mut self,
         request: &mut SerializedPacket<'_>,
+        token: DeliveryToken,
         now: &TimerInstantU32<TIMER_HZ>,
     ) -> Result<(), StateError> {
         match request.header()?.qos {
             QoS::AtMostOnce => {
                 trace!("Sending Publish({:?})", QoS::AtMostOnce);
             }
-            QoS::AtLeastOnce => {
+            qos @ (QoS::AtLeastOnce | QoS::ExactlyOnce) => {
+                if self.outgoing_pub.len() == self.outgoing_pub.capacity() {
+                    return Err(StateError::MaxMessagesInflight);
+                }
+                self.retransmit.reserve(request.0.len())?;
+
                 let pid = self.next_pid();
-                trace!("Sending Publish({:?}, {:?})", pid, QoS::AtLeastOnce);
-                self.outgoing_pub
-                    .insert(pid.get(), Inflight::new(StartTime::new(*now), &request.0))
-                    .map_err(|_| StateError::MaxMessagesInflight)?;
+                trace!("Sending Publish({:?}, {:?})", pid, qos);
                 request.set_pid(pid)?;
-            }
-            QoS::ExactlyOnce => {
-                let pid = self.next_pid();
-                trace!("Sending Publish({:?}, {:?})", pid, QoS::ExactlyOnce);
+                self.retransmit.push(pid.get(), request.0)?;
                 self.outgoing_pub
-                    .insert(pid.get(), Inflight::new(StartTime::new(*now), &request.0))
+                    .insert(pid.get(), Inflight::new(StartTime::new(*now), token))
                     .map_err(|_| StateError::MaxMessagesInflight)?;
-                request.set_pid(pid)?;
             }
         }
         Ok(())
     }
 
+    /// Drops a request which can't be sent, reporting the delivery of a QoS 1
+    /// or 2 publish as dropped.
+    pub(crate) fn handle_dropped_request(
+        &mut self,
+        request: &SerializedPacket<'_>,
+        token: DeliveryToken,
+    ) -> Result<(), StateError> {
+        let header = request.header()?;
+        if header.typ == PacketType::Publish && header.qos != QoS::AtMostOnce {
+            self.report_delivery(Some(token), None, DeliveryStatus::Dropped);
+        }
+        Ok(())
+    }
+
+    /// Queues a delivery report, to be notified by the eventloop. The queue
+    /// has room for every publish in flight, given that the eventloop only
+    /// takes on new publishes once all earlier reports are notified.
+    fn report_delivery(
+        &mut self,
+        token: Option<DeliveryToken>,
+        pid: Option<Pid>,
+        status: DeliveryStatus,
+    ) {
+        if let Some(token) = token {
+            if let Err(delivery) = self.deliveries.push_back(Delivery { token, pid, status }) {
+                warn!("Delivery reports overflow, dropping the oldest!");
+                self.deliveries.pop_front();
+                self.deliveries.push_back(delivery).ok();
+            }
+        }
+    }
+
+    /// Adds next packet identifier to a QoS 1 or 2 publish of which the payload
+    /// is streamed, and keeps track of it until acked. Only one such publish
+    /// can be inflight at a time.
+    pub(crate) fn handle_outgoing_stream(
+        &mut self,
+        topic_name: &str,
+        qos: QoS,
+        retain: bool,
+        now: &TimerInstantU32<TIMER_HZ>,
+    ) -> Result<Option<Pid>, StateError> {
+        if qos == QoS::AtMostOnce {
+            trace!("Sending streamed Publish({:?})", qos);
+            return Ok(None);
+        }
+        if self.outgoing_stream.is_some() {
+            return Err(StateError::MaxMessagesInflight);
+        }
+
+        let mut topic = String::new();
+        topic
+            .push_str(topic_name)
+            .map_err(|_| StateError::InvalidHeader)?;
+        let pid = self.next_pid();
+        trace!("Sending streamed Publish({:?}, {:?})", pid, qos);
+        self.outgoing_stream = Some(OutgoingStream {
+            pid,
+            qos,
+            retain,
+            topic_name: topic,
+            last_touch: StartTime::new(*now),
+            attempts: 1,
+        });
+        Ok(Some(pid))
+    }
+
+    /// Removes an outgoing publish awaiting its ack, whether it is stored or
+    /// streamed. Returns its delivery token, if any, or `None` if no such
+    /// publish is inflight.
+    fn remove_outgoing_pub(&mut self, pid: Pid) -> Option<Option<DeliveryToken>> {
+        if let Some(inflight) = self.outgoing_pub.remove(&pid.get()) {
+            self.retransmit.remove(pid.get());
+            return Some(Some(inflight.token));
+        }
+        match &self.outgoing_stream {
+            Some(stream) if stream.pid == pid => {
+                self.outgoing_stream = None;
+                Some(None)
+            }
+            _ => None,
+        }
+    }
+
     /// Iterates through the list of stored publishes and removes the publish
     /// with the matching packet identifier. Removal is now a O(n) operation.
     /// This should be usually ok in case of acks due to ack ordering in normal
@@ -200,10 +465,10 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
     fn handle_incoming_puback(
         &mut self,
         pid: Pid,
+        status: DeliveryStatus,
     ) -> Result<(Option<Notification>, Option<Packet<'static>>), StateError> {
-        if self.outgoing_pub.contains_key(&pid.get()) {
-            let _publish = self.outgoing_pub.remove(&pid.get());
-
+        if let Some(token) = self.remove_outgoing_pub(pid) {
+            self.report_delivery(token, Some(pid), status);
             let request = None;
             let notification = Some(Notification::Puback(pid));
             trace!("Received Puback({:?})", pid);
@@ -215,14 +480,22 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
         }
     }
 
-    fn handle_incoming_suback<'a>(
+    fn handle_incoming_suback(
         &mut self,
-        suback: Suback<'a>,
+        pid: Pid,
+        codes: impl Iterator<Item = SubscribeReturnCodes>,
     ) -> Result<(Option<Notification>, Option<Packet<'static>>), StateError> {
         let request = None;
-        trace!("Received Suback({:?})", suback.pid);
-        // TODO: Add suback packet info here
-        let notification = Some(Notification::Suback(suback.pid));
+        trace!("Received Suback({:?})", pid);
+        let mut return_codes = Vec::new();
+        for code in codes {
+            if return_codes.push(code).is_err() {
+                warn!("Suback({:?}) has too many return codes, truncating!", pid);
+                break;
+            }
+        }
+        self.subscriptions.handle_suback(pid.get(), &return_codes);
+        let notification = Some(Notification::Suback { pid, return_codes });
         Ok((notification, request))
     }
 
@@ -232,6 +505,7 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
     ) -> Result<(Option<Notification>, Option<Packet<'static>>), StateError> {
         let request = None;
         trace!("Received Unsuback({:?})", pid);
+        self.subscriptions.handle_unsuback(pid.get());
         let notification = Some(Notification::Unsuback(pid));
         Ok((notification, request))
     }
@@ -244,10 +518,9 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
         &mut self,
         pid: Pid,
     ) -> Result<(Option<Notification>, Option<Packet<'static>>), StateError> {
-        if self.outgoing_pub.contains_key(&pid.get()) {
-            self.outgoing_pub.remove(&pid.get());
+        if let Some(token) = self.remove_outgoing_pub(pid) {
             self.outgoing_rel
-                .insert(pid.get())
+                .push((pid.get(), token))
                 .map_err(|_| StateError::InvalidState)?;
 
             let reply = Some(Packet::Pubrel(pid));
@@ -267,14 +540,191 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
         publish: Publish<'b>,
     ) -> Result<(Option<Notification>, Option<Packet<'static>>), StateError> {
         let qospid = (publish.qos, publish.pid);
+        if let Some(result) = self.filter_incoming_publish(qospid) {
+            return Ok(result);
+        }
+
+        let mut publish: PublishNotification = publish.try_into().unwrap();
+        let request = if self.manual_acks {
+            publish.ack = self.hold_incoming_ack(qospid)?;
+            None
+        } else {
+            self.ack_incoming_publish(qospid)?
+        };
 
         #[cfg(not(feature = "std"))]
         let boxed_publish = BoxedPublish::alloc().unwrap();
         #[cfg(not(feature = "std"))]
-        let notification = Notification::Publish(boxed_publish.init(publish.try_into().unwrap()));
+        let notification = Notification::Publish(boxed_publish.init(publish));
 
         #[cfg(feature = "std")]
-        let notification = Notification::Publish(std::boxed::Box::new(publish.try_into().unwrap()));
+        let notification = Notification::Publish(std::boxed::Box::new(publish));
+
+        Ok((Some(notification), request))
+    }
+
+    /// Acknowledges an incoming publish that was discarded for exceeding the
+    /// receive buffer, such that the broker doesn't redeliver it.
+    pub(crate) fn handle_incoming_oversized_publish(
+        &mut self,
+        topic_name: String<256>,
+        qospid: (QoS, Option<Pid>),
+        size: usize,
+    ) -> Result<(Option<Notification>, Option<Packet<'static>>), StateError> {
+        warn!("Discarded oversized publish of {:?} bytes", size);
+        if let Some(result) = self.filter_incoming_publish(qospid) {
+            return Ok(result);
+        }
+        let request = self.ack_incoming_publish(qospid)?;
+        Ok((
+            Some(Notification::OversizedPublish { topic_name, size }),
+            request,
+        ))
+    }
+
+    /// Notifies a payload chunk of a streamed incoming publish. The publish is
+    /// acked along with its last chunk.
+    pub(crate) fn handle_incoming_publish_chunk(
+        &mut self,
+        offset: usize,
+        payload: Vec<u8, STREAM_CHUNK_SIZE>,
+        last: Option<(QoS, Option<Pid>)>,
+    ) -> Result<(Option<Notification>, Option<Packet<'static>>), StateError> {
+        let (request, ack) = match last {
+            Some(qospid) if self.manual_acks => (None, self.hold_incoming_ack(qospid)?),
+            Some(qospid) => (self.ack_incoming_publish(qospid)?, None),
+            None => (None, None),
+        };
+        Ok((
+            Some(Notification::PublishChunk {
+                offset,
+                payload,
+                ack,
+            }),
+            request,
+        ))
+    }
+
+    /// Returns the reply to an incoming publish which isn't delivered, or
+    /// `None` if it is to be delivered. A QoS 2 publish which is delivered
+    /// already and awaits its pubrel is acked again.
+    pub(crate) fn filter_incoming_publish(
+        &self,
+        qospid: (QoS, Option<Pid>),
+    ) -> Option<(Option<Notification>, Option<Packet<'static>>)> {
+        match qospid {
+            (_, Some(pid)) if self.incoming_acks.iter().any(|ack| ack.pid == pid) => {
+                debug!("Duplicate Publish({:?}), awaiting its ack", pid);
+                Some((None, None))
+            }
+            (QoS::ExactlyOnce, Some(pid)) if self.incoming_pub.contains(&pid.get()) => {
+                debug!("Duplicate Publish({:?}), acking again", pid);
+                Some((None, Some(Packet::Pubrec(pid))))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether an incoming publish has to wait until a QoS 2 publish is
+    /// released, or until the application acks a publish. It is left in the
+    /// receive buffer meanwhile, while the packets behind it are handled.
+    pub(crate) fn is_incoming_publish_blocked(&self, qospid: (QoS, Option<Pid>)) -> bool {
+        if self.filter_incoming_publish(qospid).is_some() {
+            return false;
+        }
+        match qospid {
+            (QoS::ExactlyOnce, Some(_))
+                if self.incoming_pub.len() == self.incoming_pub.capacity() =>
+            {
+                true
+            }
+            (QoS::AtLeastOnce | QoS::ExactlyOnce, Some(_)) => {
+                self.manual_acks && self.incoming_acks.is_full()
+            }
+            _ => false,
+        }
+    }
+
+    /// Number of incoming QoS 1 and 2 publishes which can be awaiting their
+    /// ack at once, advertised to MQTT 5 brokers as the receive maximum.
+    /// With manual acks, QoS 1 publishes are held back as well.
+    pub(crate) fn receive_maximum(&self) -> u16 {
+        let mut capacity = self.incoming_pub.capacity();
+        if self.manual_acks {
+            capacity = capacity.min(self.incoming_acks.capacity());
+        }
+        capacity.min(u16::MAX as usize) as u16
+    }
+
+    /// Holds back the ack of an incoming QoS 1 or 2 publish until the
+    /// application acks it. A QoS 2 publish is tracked right away, such that
+    /// it isn't delivered again.
+    fn hold_incoming_ack(
+        &mut self,
+        qospid: (QoS, Option<Pid>),
+    ) -> Result<Option<AckHandle>, StateError> {
+        let (qos, pid) = match qospid {
+            (QoS::AtMostOnce, _) => return Ok(None),
+            (qos, Some(pid)) => (qos, pid),
+            _ => return Err(StateError::InvalidHeader),
+        };
+        if qos == QoS::ExactlyOnce {
+            self.incoming_pub
+                .insert(pid.get())
+                .map_err(|_| StateError::InvalidState)?;
+        }
+        self.incoming_acks
+            .push_back(PendingAck {
+                qos,
+                pid,
+                acked: false,
+            })
+            .map_err(|_| StateError::InvalidState)?;
+        Ok(Some(AckHandle { qos, pid }))
+    }
+
+    /// Marks an incoming publish as acked by the application. Returns the
+    /// acks which can be sent now, without overtaking the acks of publishes
+    /// received earlier.
+    pub(crate) fn handle_outgoing_ack(
+        &mut self,
+        handle: AckHandle,
+    ) -> Vec<Packet<'static>, INFLIGHT> {
+        match self
+            .incoming_acks
+            .iter_mut()
+            .find(|ack| ack.pid == handle.pid && ack.qos == handle.qos)
+        {
+            Some(ack) => ack.acked = true,
+            None => warn!("Ack of unknown Publish({:?})", handle.pid),
+        }
+
+        let mut acks = Vec::new();
+        while self.incoming_acks.front().is_some_and(|ack| ack.acked) {
+            if let Some(ack) = self.incoming_acks.pop_front() {
+                let packet = match ack.qos {
+                    QoS::ExactlyOnce => Packet::Pubrec(ack.pid),
+                    _ => Packet::Puback(ack.pid),
+                };
+                acks.push(packet).ok();
+            }
+        }
+        acks
+    }
+
+    fn ack_incoming_publish(
+        &mut self,
+        qospid: (QoS, Option<Pid>),
+    ) -> Result<Option<Packet<'static>>, StateError> {
+        // Queue up behind acks held back by the application
+        if !self.incoming_acks.is_empty() {
+            if let Some(handle) = self.hold_incoming_ack(qospid)? {
+                // The oldest held back ack is still pending, so this is sent
+                // along with it
+                self.handle_outgoing_ack(handle);
+            }
+            return Ok(None);
+        }
 
         let request = match qospid {
             (QoS::AtMostOnce, _) => None,
@@ -289,7 +739,7 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
             }
             _ => return Err(StateError::InvalidHeader),
         };
-        Ok((Some(notification), request))
+        Ok(request)
     }
 
     fn handle_incoming_pubrel(
@@ -311,8 +761,12 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
         &mut self,
         pid: Pid,
     ) -> Result<(Option<Notification>, Option<Packet<'static>>), StateError> {
-        if self.outgoing_rel.contains(&pid.get()) {
-            self.outgoing_rel.remove(&pid.get());
+        let released = self
+            .outgoing_rel
+            .iter()
+            .position(|(released, _)| *released == pid.get());
+        if let Some((_, token)) = released.map(|index| self.outgoing_rel.remove(index)) {
+            self.report_delivery(token, Some(pid), DeliveryStatus::Acknowledged);
             let notification = Some(Notification::Pubcomp(pid));
             let reply = None;
             Ok((notification, reply))
@@ -359,6 +813,10 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
             {
                 debug!("MQTT connected!");
                 self.connection_status = MqttConnectionStatus::Connected;
+                self.session_present = connack.session_present;
+                if !connack.session_present {
+                    self.discard_session();
+                }
                 Ok(())
             }
             ConnectReturnCode::Accepted
@@ -380,6 +838,83 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
         }
     }
 
+    pub fn handle_incoming_connack_v5(
+        &mut self,
+        connack: v5::Connack<'_>,
+    ) -> Result<(), StateError> {
+        if !connack.reason_code.is_success() {
+            error!(
+                "Connection failed. Connection error = {:?}",
+                connack.reason_code.as_u8()
+            );
+            self.connection_status = MqttConnectionStatus::Disconnected;
+            return Err(StateError::ConnectReason(connack.reason_code));
+        }
+
+        if self.connection_status != MqttConnectionStatus::Handshake {
+            error!(
+                "Invalid state. Expected = {:?}, Current = {:?}",
+                MqttConnectionStatus::Handshake,
+                self.connection_status
+            );
+            self.connection_status = MqttConnectionStatus::Disconnected;
+            return Err(StateError::InvalidState);
+        }
+
+        let mut limits = SessionLimits::default();
+        for property in connack.properties.iter() {
+            match property {
+                v5::Property::ReceiveMaximum(max) => limits.receive_maximum = max,
+                v5::Property::MaximumPacketSize(max) => limits.maximum_packet_size = Some(max),
+                v5::Property::TopicAliasMaximum(max) => limits.topic_alias_maximum = max,
+                v5::Property::ServerKeepAlive(secs) => limits.server_keep_alive = Some(secs),
+                v5::Property::AssignedClientIdentifier(id) => {
+                    let mut client_id = String::new();
+                    match client_id.push_str(id) {
+                        Ok(()) => self.assigned_client_id = Some(client_id),
+                        Err(()) => warn!("Assigned client identifier is too long!"),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        debug!("MQTT connected!");
+        self.session_limits = limits;
+        self.connection_status = MqttConnectionStatus::Connected;
+        self.session_present = connack.session_present;
+        if !connack.session_present {
+            self.discard_session();
+        }
+        Ok(())
+    }
+
+    /// Discards the state of the previous session, when the broker didn't
+    /// resume it. Publishes awaiting their acks are reported as
+    /// `DeliveryStatus::Dropped`.
+    fn discard_session(&mut self) {
+        let mut dropped: Vec<(u16, Option<DeliveryToken>), INFLIGHT> = Vec::new();
+        for pid in self.retransmit.pids() {
+            if let Some(inflight) = self.outgoing_pub.get(&pid) {
+                dropped.push((pid, Some(inflight.token))).ok();
+            }
+        }
+        if !dropped.is_empty() || !self.outgoing_rel.is_empty() {
+            warn!("Session not present, dropping unacked publishes!");
+        }
+
+        self.outgoing_pub.clear();
+        self.retransmit.clear();
+        self.outgoing_stream = None;
+        self.incoming_pub.clear();
+        self.incoming_acks.clear();
+        let released = core::mem::take(&mut self.outgoing_rel);
+        for (pid, token) in dropped.into_iter().chain(released) {
+            let pid = Pid::try_from(pid).ok();
+            self.report_delivery(token, pid, DeliveryStatus::Dropped);
+        }
+    }
+
     fn next_pid(&mut self) -> Pid {
         self.last_pid = self.last_pid + 1;
         self.last_pid
@@ -389,14 +924,122 @@ impl<const TIMER_HZ: u32> MqttState<TIMER_HZ> {
         &mut self.last_ping
     }
 
-    pub(crate) fn retries(
+    /// Selects the first stored publish staying longer than its retry
+    /// interval, in the order they were sent, and counts it as attempted
+    /// again. Those exhausting the attempts of the `policy` are dropped
+    /// instead, and reported as `DeliveryStatus::Expired`.
+    pub(crate) fn next_retry(
         &mut self,
         now: TimerInstantU32<TIMER_HZ>,
-        interval: TimerDurationU32<TIMER_HZ>,
-    ) -> impl Iterator<Item = (&u16, &mut Inflight<TIMER_HZ, 1536>)> + '_ {
-        self.outgoing_pub
-            .iter_mut()
-            .filter(move |(_, inflight)| inflight.last_touch.has_elapsed(&now, interval))
+        policy: &RetryPolicy,
+    ) -> Option<u16> {
+        let mut retry = None;
+        let mut expired: Vec<u16, INFLIGHT> = Vec::new();
+        for pid in self.retransmit.pids() {
+            if let Some(inflight) = self.outgoing_pub.get_mut(&pid) {
+                if !inflight.retry_due(pid, &now, policy) {
+                    continue;
+                }
+                if policy.is_exhausted(inflight.attempts) {
+                    expired.push(pid).ok();
+                } else {
+                    inflight.attempts += 1;
+                    inflight.last_touch.insert(now);
+                    retry = Some(pid);
+                    break;
+                }
+            }
+        }
+
+        if let Some(pid) = retry {
+            self.set_dup(pid);
+        }
+
+        for pid in expired {
+            warn!("Dropping PID {:?} after exhausting its retries", pid);
+            if let Ok(pid) = Pid::try_from(pid) {
+                let token = self.remove_outgoing_pub(pid).flatten();
+                self.report_delivery(token, Some(pid), DeliveryStatus::Expired);
+            }
+        }
+        retry
+    }
+
+    /// Selects all stored publishes in the order they were sent, to be sent
+    /// again after resuming the session. Updates their timestamps for later
+    /// retrials.
+    pub(crate) fn replay(&mut self, now: TimerInstantU32<TIMER_HZ>) -> Vec<u16, INFLIGHT> {
+        let pids: Vec<u16, INFLIGHT> = self.retransmit.pids().collect();
+        for &pid in &pids {
+            if let Some(inflight) = self.outgoing_pub.get_mut(&pid) {
+                inflight.last_touch.insert(now);
+            }
+            self.set_dup(pid);
+        }
+        pids
+    }
+
+    /// Subscribes to all topic filters of the registry again, after the
+    /// broker didn't keep the session. Returns the packet identifier of the
+    /// subscribe along with its topics, if any.
+    pub(crate) fn resubscribe(
+        &mut self,
+    ) -> Option<(Pid, Vec<SubscribeTopic<'_>, MAX_SUBSCRIPTIONS>)> {
+        let pid = self.last_pid + 1;
+        let topics = self.subscriptions.resubscribe(pid.get());
+        if topics.is_empty() {
+            return None;
+        }
+        self.last_pid = pid;
+        trace!("Sending Subscribe({:?}) to resubscribe", pid);
+        Some((pid, topics))
+    }
+
+    /// Sets the DUP flag of a stored publish, which is sent again
+    fn set_dup(&mut self, pid: u16) {
+        if let Some(packet) = self.retransmit.get_mut(pid) {
+            packet[0] |= 0b1000;
+        }
+    }
+
+    /// Serialized publish stored for retransmission
+    pub(crate) fn stored_publish(&mut self, pid: u16) -> Result<&[u8], StateError> {
+        self.retransmit
+            .get_mut(pid)
+            .map(|packet| &*packet)
+            .ok_or(StateError::InvalidState)
+    }
+
+    /// Returns a `Notification::RetryStreamedPublish` if the retry interval of
+    /// the streamed publish has elapsed, upon which its payload has to be
+    /// streamed again by the user. Once its attempts are exhausted, it is
+    /// dropped and notified as `Notification::StreamedPublishExpired`.
+    pub(crate) fn stream_retry(
+        &mut self,
+        now: TimerInstantU32<TIMER_HZ>,
+        policy: &RetryPolicy,
+    ) -> Option<Notification> {
+        let stream = self.outgoing_stream.as_mut()?;
+        let seed = stream.last_touch.ticks() ^ (stream.pid.get() as u32) << 16 ^ stream.attempts;
+        let interval = policy.interval_ms(stream.attempts, seed).millis();
+        if !stream.last_touch.has_elapsed(&now, interval) {
+            return None;
+        }
+
+        let pid = stream.pid;
+        if policy.is_exhausted(stream.attempts) {
+            warn!(
+                "Dropping streamed PID {:?} after exhausting its retries",
+                pid
+            );
+            self.outgoing_stream = None;
+            Some(Notification::StreamedPublishExpired(pid))
+        } else {
+            warn!("Retrying streamed PID {:?}", pid);
+            stream.attempts += 1;
+            stream.last_touch.insert(now);
+            Some(Notification::RetryStreamedPublish(pid))
+        }
     }
 }
 
@@ -422,6 +1065,11 @@ impl<const TIMER_HZ: u32> StartTime<TIMER_HZ> {
     pub fn insert(&mut self, now: TimerInstantU32<TIMER_HZ>) {
         self.0.replace(now);
     }
+
+    /// Ticks of the start time, or zero if not started
+    pub(crate) fn ticks(&self) -> u32 {
+        self.0.map_or(0, |start_time| start_time.ticks())
+    }
 }
 
 impl<const TIMER_HZ: u32> StartTime<TIMER_HZ> {
@@ -440,54 +1088,113 @@ impl<const TIMER_HZ: u32> StartTime<TIMER_HZ> {
     }
 }
 
-/// Client publication message data.
+/// Client publication message data. The publish itself is kept in the
+/// `RetransmitBuffer`.
 #[derive(Debug)]
-pub(crate) struct Inflight<const TIMER_HZ: u32, const L: usize> {
-    /// A publish of non-zero QoS.
-    publish: heapless::Vec<u8, L>,
+pub(crate) struct Inflight<const TIMER_HZ: u32> {
+    /// Delivery token of the publish.
+    token: DeliveryToken,
     /// A timestmap used for retry and expiry.
     last_touch: StartTime<TIMER_HZ>,
+    /// Number of times the publish has been sent.
+    attempts: u32,
 }
 
-impl<const TIMER_HZ: u32, const L: usize> Inflight<TIMER_HZ, L> {
-    pub(crate) fn new(last_touch: StartTime<TIMER_HZ>, publish: &[u8]) -> Self {
-        assert!(
-            !matches!(
-                decoder::Header::new(publish[0]).unwrap().qos,
-                QoS::AtMostOnce
-            ),
-            "Only non-zero QoSs are allowed."
-        );
+impl<const TIMER_HZ: u32> Inflight<TIMER_HZ> {
+    pub(crate) fn new(last_touch: StartTime<TIMER_HZ>, token: DeliveryToken) -> Self {
         Self {
-            publish: heapless::Vec::from_slice(publish).unwrap(),
+            token,
             last_touch,
+            attempts: 1,
         }
     }
 
-    pub(crate) fn last_touch_entry(&mut self) -> &mut StartTime<TIMER_HZ> {
-        &mut self.last_touch
+    fn retry_due(&self, pid: u16, now: &TimerInstantU32<TIMER_HZ>, policy: &RetryPolicy) -> bool {
+        let seed = self.last_touch.ticks() ^ (pid as u32) << 16 ^ self.attempts;
+        let interval = policy.interval_ms(self.attempts, seed).millis();
+        self.last_touch.has_elapsed(now, interval)
     }
 }
 
-impl<const TIMER_HZ: u32, const L: usize> Inflight<TIMER_HZ, L> {
-    pub(crate) fn packet<'b>(&'b mut self, pid: u16) -> Result<&'b [u8], StateError> {
-        let pid = pid.try_into().map_err(|_| StateError::PayloadEncoding)?;
-        let mut packet = SerializedPacket(self.publish.as_mut());
-        packet.set_pid(pid)?;
-        Ok(packet.to_inner())
+/// Ack of an incoming publish, held back until acked by the application
+#[derive(Debug)]
+pub(crate) struct PendingAck {
+    qos: QoS,
+    pid: Pid,
+    acked: bool,
+}
+
+/// Client publication of which the payload is streamed from a
+/// `PayloadSource`. Only its headers are kept for retransmission.
+#[derive(Debug)]
+pub(crate) struct OutgoingStream<const TIMER_HZ: u32> {
+    pub(crate) pid: Pid,
+    pub(crate) qos: QoS,
+    pub(crate) retain: bool,
+    pub(crate) topic_name: String<256>,
+    /// A timestmap used for retry.
+    last_touch: StartTime<TIMER_HZ>,
+    /// Number of times the publish has been sent.
+    attempts: u32,
+}
+
+/// Delivery reports yet to be notified, in the order they were made. Holds up
+/// to twice `INFLIGHT` reports, one for each publish awaiting its ack and each
+/// released QoS 2 publish, which are all reported at once when a session is
+/// discarded.
+#[derive(Debug)]
+pub(crate) struct Deliveries<const INFLIGHT: usize> {
+    front: Deque<Delivery, INFLIGHT>,
+    back: Deque<Delivery, INFLIGHT>,
+}
+
+impl<const INFLIGHT: usize> Deliveries<INFLIGHT> {
+    pub(crate) fn new() -> Self {
+        Self {
+            front: Deque::new(),
+            back: Deque::new(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.front.is_empty() && self.back.is_empty()
+    }
+
+    /// Queues a report, returning it back if the queue is full
+    pub(crate) fn push_back(&mut self, delivery: Delivery) -> Result<(), Delivery> {
+        // Reports only go in front once no older ones are left behind
+        let delivery = if self.back.is_empty() {
+            match self.front.push_back(delivery) {
+                Ok(()) => return Ok(()),
+                Err(delivery) => delivery,
+            }
+        } else {
+            delivery
+        };
+        self.back.push_back(delivery)
+    }
+
+    pub(crate) fn pop_front(&mut self) -> Option<Delivery> {
+        self.front.pop_front().or_else(|| self.back.pop_front())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{BoxedPublish, MqttConnectionStatus, MqttState, Packet, StateError};
-    use crate::{packet::SerializedPacket, Notification};
+    use super::{BoxedPublish, MqttConnectionStatus, MqttState, Packet, SessionLimits, StateError};
+    use crate::options::{ProtocolVersion, RetryPolicy};
+    use crate::{packet::SerializedPacket, Delivery, DeliveryStatus, Notification};
     use core::convert::TryFrom;
     use fugit::TimerInstantU32;
-    use heapless::pool::singleton::Pool;
+    use heapless::{pool::singleton::Pool, Vec};
     use mqttrust::{
-        encoding::v4::{decode_slice, encode_slice, Pid},
-        Publish, QoS,
+        encoding::{
+            v4::{
+                decode_slice, encode_slice, Connack, ConnectReturnCode, Pid, SubscribeReturnCodes,
+            },
+            v5,
+        },
+        DeliveryToken, Publish, QoS,
     };
 
     fn build_publish<'a>(qos: QoS, pid: Option<u16>) -> Publish<'a> {
@@ -514,7 +1221,7 @@ mod test {
         let state = MqttState::new();
         const LEN: usize = 1024 * 10;
         static mut PUBLISH_MEM: [u8; LEN] = [0u8; LEN];
-        BoxedPublish::grow(unsafe { &mut PUBLISH_MEM });
+        BoxedPublish::grow(unsafe { &mut *core::ptr::addr_of_mut!(PUBLISH_MEM) });
         state
     }
 
@@ -530,8 +1237,12 @@ mod test {
         let len = encode_slice(&publish, buf).unwrap();
 
         // Packet id shouldn't be set and publish shouldn't be saved in queue
-        mqtt.handle_outgoing_request(&mut SerializedPacket(&mut buf[..len]), &now)
-            .unwrap();
+        mqtt.handle_outgoing_request(
+            &mut SerializedPacket(&mut buf[..len]),
+            DeliveryToken::default(),
+            &now,
+        )
+        .unwrap();
         // assert_eq!(publish_out.qos, QoS::AtMostOnce);
         // assert_eq!(mqtt.outgoing_pub.len(), 0);
 
@@ -610,7 +1321,8 @@ mod test {
         let mut pkg = SerializedPacket(&mut buf[..len]);
 
         // Packet id shouldn't be set and publish shouldn't be saved in queue
-        mqtt.handle_outgoing_publish(&mut pkg, &now).unwrap();
+        mqtt.handle_outgoing_publish(&mut pkg, DeliveryToken::default(), &now)
+            .unwrap();
 
         let publish_out = match decode_slice(pkg.to_inner()).unwrap() {
             Some(Packet::Publish(p)) => p,
@@ -625,7 +1337,8 @@ mod test {
         let mut pkg = SerializedPacket(&mut buf[..len]);
 
         // Packet id should be set and publish should be saved in queue
-        mqtt.handle_outgoing_publish(&mut pkg, &now).unwrap();
+        mqtt.handle_outgoing_publish(&mut pkg, DeliveryToken::default(), &now)
+            .unwrap();
         let publish_out = match decode_slice(pkg.to_inner()).unwrap() {
             Some(Packet::Publish(p)) => p,
             _ => panic!(),
@@ -653,6 +1366,83 @@ mod test {
         assert!(mqtt.incoming_pub.contains(&3));
     }
 
+    #[test]
+    fn incoming_qos2_publish_should_be_delivered_once() {
+        let mut mqtt = build_mqttstate();
+        let pid = |pid| Pid::try_from(pid).unwrap();
+
+        let (notification, reply) = mqtt
+            .handle_incoming_publish(build_publish(QoS::ExactlyOnce, Some(1)))
+            .unwrap();
+        assert!(matches!(notification, Some(Notification::Publish(_))));
+        assert_eq!(reply, Some(Packet::Pubrec(pid(1))));
+
+        // Redelivery awaiting the pubrel is acked again, but not delivered
+        let duplicate = Publish {
+            dup: true,
+            ..build_publish(QoS::ExactlyOnce, Some(1))
+        };
+        assert_eq!(
+            mqtt.handle_incoming_publish(duplicate).unwrap(),
+            (None, Some(Packet::Pubrec(pid(1))))
+        );
+
+        // Without room for another, publishes are held back until one is
+        // released, unless they're duplicates or don't need room
+        mqtt.handle_incoming_publish(build_publish(QoS::ExactlyOnce, Some(2)))
+            .unwrap();
+        assert!(mqtt.is_incoming_publish_blocked((QoS::ExactlyOnce, Some(pid(3)))));
+        assert!(!mqtt.is_incoming_publish_blocked((QoS::ExactlyOnce, Some(pid(2)))));
+        assert!(!mqtt.is_incoming_publish_blocked((QoS::AtLeastOnce, Some(pid(3)))));
+        assert_eq!(mqtt.incoming_pub.len(), 2);
+
+        // Released publishes are delivered again when reusing the pid
+        assert_eq!(
+            mqtt.handle_incoming_packet(Packet::Pubrel(pid(1))).unwrap(),
+            (None, Some(Packet::Pubcomp(pid(1))))
+        );
+        assert!(!mqtt.is_incoming_publish_blocked((QoS::ExactlyOnce, Some(pid(3)))));
+        let (notification, _) = mqtt
+            .handle_incoming_publish(build_publish(QoS::ExactlyOnce, Some(3)))
+            .unwrap();
+        assert!(matches!(notification, Some(Notification::Publish(_))));
+    }
+
+    #[test]
+    fn manual_acks_should_be_sent_in_order() {
+        let mut mqtt = build_mqttstate();
+        mqtt.manual_acks = true;
+        assert_eq!(mqtt.receive_maximum(), 2);
+        let pid = |pid| Pid::try_from(pid).unwrap();
+        let receive = |mqtt: &mut MqttState<1000>, qos, p| match mqtt
+            .handle_incoming_publish(build_publish(qos, Some(p)))
+        {
+            Ok((Some(Notification::Publish(mut publish)), None)) => publish.ack.take(),
+            result => panic!("Unexpected result {:?}", result),
+        };
+
+        let first = receive(&mut mqtt, QoS::AtLeastOnce, 1).unwrap();
+        let second = receive(&mut mqtt, QoS::ExactlyOnce, 2).unwrap();
+        assert_eq!(second.pid(), pid(2));
+        assert!(receive(&mut mqtt, QoS::AtMostOnce, 3).is_none());
+
+        // Redeliveries aren't delivered again while awaiting the ack
+        assert_eq!(
+            mqtt.handle_incoming_publish(build_publish(QoS::ExactlyOnce, Some(2)))
+                .unwrap(),
+            (None, None)
+        );
+
+        // The second ack waits for the first
+        assert!(mqtt.handle_outgoing_ack(second).is_empty());
+        assert_eq!(
+            mqtt.handle_outgoing_ack(first),
+            [Packet::Puback(pid(1)), Packet::Pubrec(pid(2))]
+        );
+        assert!(mqtt.incoming_acks.is_empty());
+        assert!(mqtt.incoming_pub.contains(&2));
+    }
+
     #[test]
     fn incoming_qos2_publish_should_send_rec_to_network_and_publish_to_user() {
         let mut mqtt = build_mqttstate();
@@ -671,6 +1461,230 @@ mod test {
         }
     }
 
+    #[test]
+    fn outgoing_publish_should_respect_inflight_generics() {
+        let mut mqtt = MqttState::<1000, 4, 128>::new();
+        let buf = &mut [0u8; 256];
+        let now = TimerInstantU32::from_ticks(0);
+
+        // Publishes exceeding the retransmit buffer are refused
+        let payload = [0u8; 128];
+        let publish = Packet::Publish(Publish {
+            payload: &payload,
+            ..build_publish(QoS::ExactlyOnce, None)
+        });
+        let len = encode_slice(&publish, buf).unwrap();
+        let mut packet = SerializedPacket(&mut buf[..len]);
+        assert_eq!(
+            mqtt.handle_outgoing_publish(&mut packet, DeliveryToken::default(), &now),
+            Err(StateError::InflightTooLarge)
+        );
+        assert_eq!(mqtt.last_pid, Pid::new());
+
+        let publish = Packet::Publish(build_publish(QoS::AtLeastOnce, None));
+        for _ in 0..4 {
+            let len = encode_slice(&publish, buf).unwrap();
+            let mut packet = SerializedPacket(&mut buf[..len]);
+            mqtt.handle_outgoing_publish(&mut packet, DeliveryToken::default(), &now)
+                .unwrap();
+        }
+        assert_eq!(mqtt.outgoing_pub.len(), 4);
+        assert_eq!(mqtt.incoming_pub.capacity(), 4);
+
+        let len = encode_slice(&publish, buf).unwrap();
+        let mut packet = SerializedPacket(&mut buf[..len]);
+        assert_eq!(
+            mqtt.handle_outgoing_publish(&mut packet, DeliveryToken::default(), &now),
+            Err(StateError::MaxMessagesInflight)
+        );
+    }
+
+    #[test]
+    fn outgoing_publish_should_wait_for_retransmit_space() {
+        let mut mqtt = MqttState::<1000, 4, 64>::new();
+        let buf = &mut [0u8; 256];
+        let now = TimerInstantU32::from_ticks(0);
+
+        let publish = Packet::Publish(build_publish(QoS::AtLeastOnce, None));
+        let len = encode_slice(&publish, buf).unwrap();
+        for _ in 0..2 {
+            let mut packet = SerializedPacket(&mut buf[..len]);
+            mqtt.handle_outgoing_publish(&mut packet, DeliveryToken::default(), &now)
+                .unwrap();
+        }
+        let mut packet = SerializedPacket(&mut buf[..len]);
+        assert_eq!(
+            mqtt.handle_outgoing_publish(&mut packet, DeliveryToken::default(), &now),
+            Err(StateError::MaxMessagesInflight)
+        );
+
+        // Acks free up the stored publishes
+        mqtt.handle_incoming_packet(Packet::Puback(Pid::try_from(2).unwrap()))
+            .unwrap();
+        let mut packet = SerializedPacket(&mut buf[..len]);
+        mqtt.handle_outgoing_publish(&mut packet, DeliveryToken::default(), &now)
+            .unwrap();
+        assert_eq!(mqtt.retransmit.pids().collect::<std::vec::Vec<_>>(), [3, 4]);
+    }
+
+    #[test]
+    fn retries_should_back_off_and_expire() {
+        let mut mqtt = build_mqttstate();
+        let buf = &mut [0u8; 256];
+        let policy = RetryPolicy {
+            initial_interval_ms: 1_000,
+            backoff_factor: 2,
+            max_interval_ms: 60_000,
+            jitter_ms: 0,
+            max_attempts: Some(3),
+        };
+        let at = |ms: u32| TimerInstantU32::<1000>::from_ticks(ms);
+
+        let publish = Packet::Publish(build_publish(QoS::AtLeastOnce, None));
+        let len = encode_slice(&publish, buf).unwrap();
+        let mut packet = SerializedPacket(&mut buf[..len]);
+        mqtt.handle_outgoing_publish(&mut packet, DeliveryToken::default(), &at(0))
+            .unwrap();
+        let pid = Pid::try_from(2).unwrap();
+
+        assert!(mqtt.next_retry(at(999), &policy).is_none());
+        assert_eq!(mqtt.stored_publish(2).unwrap()[0] & 0b1000, 0);
+        assert_eq!(mqtt.next_retry(at(1_000), &policy), Some(2));
+        // Retransmissions are flagged as duplicates
+        assert_eq!(mqtt.stored_publish(2).unwrap()[0] & 0b1000, 0b1000);
+        // The interval doubles after each retransmission
+        assert!(mqtt.next_retry(at(2_999), &policy).is_none());
+        assert_eq!(mqtt.next_retry(at(3_000), &policy), Some(2));
+        assert!(mqtt.deliveries.is_empty());
+
+        // Third attempt is the last one
+        assert!(mqtt.next_retry(at(6_999), &policy).is_none());
+        assert!(mqtt.next_retry(at(7_000), &policy).is_none());
+        assert!(mqtt.outgoing_pub.is_empty());
+        assert!(mqtt.retransmit.pids().next().is_none());
+        assert_eq!(
+            mqtt.deliveries.pop_front(),
+            Some(Delivery {
+                token: DeliveryToken::default(),
+                pid: Some(pid),
+                status: DeliveryStatus::Expired,
+            })
+        );
+    }
+
+    #[test]
+    fn connack_without_session_should_discard_state() {
+        let mut mqtt = build_mqttstate();
+        let buf = &mut [0u8; 256];
+        let now = TimerInstantU32::from_ticks(0);
+
+        for qos in [QoS::ExactlyOnce, QoS::AtLeastOnce] {
+            let publish = Packet::Publish(build_publish(qos, None));
+            let len = encode_slice(&publish, buf).unwrap();
+            let mut packet = SerializedPacket(&mut buf[..len]);
+            mqtt.handle_outgoing_publish(&mut packet, DeliveryToken::default(), &now)
+                .unwrap();
+        }
+        mqtt.handle_incoming_packet(Packet::Pubrec(Pid::try_from(2).unwrap()))
+            .unwrap();
+        mqtt.incoming_pub.insert(7).unwrap();
+
+        mqtt.connection_status = MqttConnectionStatus::Handshake;
+        mqtt.handle_incoming_packet(Packet::Connack(Connack {
+            session_present: false,
+            code: ConnectReturnCode::Accepted,
+        }))
+        .unwrap();
+
+        assert!(mqtt.outgoing_pub.is_empty());
+        assert!(mqtt.outgoing_rel.is_empty());
+        assert!(mqtt.incoming_pub.is_empty());
+        assert!(mqtt.retransmit.pids().next().is_none());
+        let dropped: std::vec::Vec<_> = core::iter::from_fn(|| mqtt.deliveries.pop_front())
+            .map(|delivery| (delivery.pid.unwrap().get(), delivery.status))
+            .collect();
+        assert_eq!(
+            dropped,
+            [(3, DeliveryStatus::Dropped), (2, DeliveryStatus::Dropped)]
+        );
+    }
+
+    #[test]
+    fn discarded_session_should_report_every_publish() {
+        let mut mqtt = build_mqttstate();
+        let buf = &mut [0u8; 256];
+        let now = TimerInstantU32::from_ticks(0);
+
+        // Released QoS 2 publishes fill up `outgoing_rel`, and the ones after
+        // them `outgoing_pub`
+        for token in 0..4 {
+            let publish = Packet::Publish(build_publish(QoS::ExactlyOnce, None));
+            let len = encode_slice(&publish, buf).unwrap();
+            let mut packet = SerializedPacket(&mut buf[..len]);
+            mqtt.handle_outgoing_publish(&mut packet, DeliveryToken::new(token), &now)
+                .unwrap();
+            if token < 2 {
+                mqtt.handle_incoming_packet(Packet::Pubrec(mqtt.last_pid))
+                    .unwrap();
+            }
+        }
+        assert_eq!(mqtt.outgoing_pub.len(), mqtt.outgoing_pub.capacity());
+        assert_eq!(mqtt.outgoing_rel.len(), mqtt.outgoing_rel.capacity());
+
+        mqtt.connection_status = MqttConnectionStatus::Handshake;
+        mqtt.handle_incoming_packet(Packet::Connack(Connack {
+            session_present: false,
+            code: ConnectReturnCode::Accepted,
+        }))
+        .unwrap();
+
+        // None of the reports is evicted
+        let dropped: std::vec::Vec<_> = core::iter::from_fn(|| mqtt.deliveries.pop_front())
+            .map(|delivery| (delivery.token, delivery.status))
+            .collect();
+        assert_eq!(
+            dropped,
+            [2, 3, 0, 1].map(|token| (DeliveryToken::new(token), DeliveryStatus::Dropped))
+        );
+    }
+
+    #[test]
+    fn incoming_suback_should_notify_return_codes() {
+        let mut mqtt = build_mqttstate();
+        let pid = Pid::try_from(3).unwrap();
+
+        let buf = [0x90, 0x04, 0x00, 0x03, 0x01, 0x80];
+        let suback = decode_slice(&buf).unwrap().unwrap();
+        let (notification, _) = mqtt.handle_incoming_packet(suback).unwrap();
+        assert_eq!(
+            notification,
+            Some(Notification::Suback {
+                pid,
+                return_codes: Vec::from_slice(&[
+                    SubscribeReturnCodes::Success(QoS::AtLeastOnce),
+                    SubscribeReturnCodes::Failure,
+                ])
+                .unwrap(),
+            })
+        );
+
+        // MQTT 5 reason codes map onto the granted QoS or a failure
+        let codes = [v5::ReasonCode::GrantedQoS2, v5::ReasonCode::NotAuthorized];
+        let suback = v5::Packet::Suback(v5::Suback::new(pid, &codes));
+        let (notification, _) = mqtt.handle_incoming_packet_v5(suback).unwrap();
+        assert_eq!(
+            notification,
+            Some(Notification::Suback {
+                pid,
+                return_codes: Vec::from_slice(&[
+                    SubscribeReturnCodes::Success(QoS::ExactlyOnce),
+                    SubscribeReturnCodes::Failure,
+                ])
+                .unwrap(),
+            })
+        );
+    }
+
     #[test]
     fn incoming_puback_should_remove_correct_publish_from_queue() {
         let mut mqtt = build_mqttstate();
@@ -680,20 +1694,29 @@ mod test {
         let publish1 = Packet::Publish(build_publish(QoS::AtLeastOnce, None));
         let len = encode_slice(&publish1, buf).unwrap();
         let mut pkg1 = SerializedPacket(&mut buf[..len]);
-        mqtt.handle_outgoing_publish(&mut pkg1, &now).unwrap();
+        mqtt.handle_outgoing_publish(&mut pkg1, DeliveryToken::default(), &now)
+            .unwrap();
 
         assert_eq!(mqtt.outgoing_pub.len(), 1);
 
-        let backup = mqtt.outgoing_pub.get_mut(&2).unwrap().packet(1).unwrap();
+        let backup = mqtt.stored_publish(2).unwrap();
         let publish_out = match decode_slice(backup).unwrap() {
             Some(Packet::Publish(p)) => p,
             _ => panic!(),
         };
         assert_eq!(publish_out.qos, QoS::AtLeastOnce);
 
-        mqtt.handle_incoming_puback(Pid::try_from(2).unwrap())
+        mqtt.handle_incoming_puback(Pid::try_from(2).unwrap(), DeliveryStatus::Acknowledged)
             .unwrap();
         assert_eq!(mqtt.outgoing_pub.len(), 0);
+        assert_eq!(
+            mqtt.deliveries.pop_front(),
+            Some(Delivery {
+                token: DeliveryToken::new(0),
+                pid: Some(Pid::try_from(2).unwrap()),
+                status: DeliveryStatus::Acknowledged,
+            })
+        );
     }
 
     #[test]
@@ -705,7 +1728,8 @@ mod test {
         let publish = Packet::Publish(build_publish(QoS::ExactlyOnce, None));
         let len = encode_slice(&publish, buf).unwrap();
         let mut pkg = SerializedPacket(&mut buf[..len]);
-        mqtt.handle_outgoing_publish(&mut pkg, &now).unwrap();
+        mqtt.handle_outgoing_publish(&mut pkg, DeliveryToken::default(), &now)
+            .unwrap();
 
         mqtt.handle_incoming_pubrec(Pid::try_from(2).unwrap())
             .unwrap();
@@ -713,7 +1737,7 @@ mod test {
         assert_eq!(mqtt.outgoing_rel.len(), 1);
 
         // check if the  element's pid is 2
-        assert!(mqtt.outgoing_rel.contains(&2));
+        assert_eq!(mqtt.outgoing_rel[0].0, 2);
     }
 
     #[test]
@@ -727,7 +1751,8 @@ mod test {
         let publish = Packet::Publish(build_publish(QoS::ExactlyOnce, None));
         let len = encode_slice(&publish, buf).unwrap();
         let mut pkg = SerializedPacket(&mut buf[..len]);
-        mqtt.handle_outgoing_publish(&mut pkg, &now).unwrap();
+        mqtt.handle_outgoing_publish(&mut pkg, DeliveryToken::default(), &now)
+            .unwrap();
 
         let (notification, request) = mqtt.handle_incoming_pubrec(pid).unwrap();
 
@@ -735,6 +1760,36 @@ mod test {
         assert_eq!(request, Some(Packet::Pubrel(pid)));
     }
 
+    #[test]
+    fn incoming_failed_pubrec_v5_should_report_failed_delivery() {
+        let mut mqtt = build_mqttstate();
+        let buf = &mut [0u8; 256];
+        let now = TimerInstantU32::from_ticks(0);
+        let pid = Pid::try_from(2).unwrap();
+
+        let publish = Packet::Publish(build_publish(QoS::ExactlyOnce, None));
+        let len = encode_slice(&publish, buf).unwrap();
+        let mut pkg = SerializedPacket(&mut buf[..len]);
+        mqtt.handle_outgoing_publish(&mut pkg, DeliveryToken::default(), &now)
+            .unwrap();
+
+        let pubrec = v5::Packet::Pubrec(v5::Ack::new(pid, v5::ReasonCode::QuotaExceeded));
+        let (notification, request) = mqtt.handle_incoming_packet_v5(pubrec).unwrap();
+
+        assert_eq!(notification, None);
+        assert_eq!(request, None);
+        assert!(mqtt.outgoing_pub.is_empty());
+        assert!(mqtt.outgoing_rel.is_empty());
+        assert_eq!(
+            mqtt.deliveries.pop_front(),
+            Some(Delivery {
+                token: DeliveryToken::new(0),
+                pid: Some(pid),
+                status: DeliveryStatus::Failed,
+            })
+        );
+    }
+
     #[test]
     fn incoming_pubrel_should_send_comp_to_network_and_nothing_to_user() {
         let mut mqtt = build_mqttstate();
@@ -761,7 +1816,8 @@ mod test {
 
         let pid = Pid::try_from(2).unwrap();
 
-        mqtt.handle_outgoing_publish(&mut pkg, &now).unwrap();
+        mqtt.handle_outgoing_publish(&mut pkg, DeliveryToken::default(), &now)
+            .unwrap();
         mqtt.handle_incoming_pubrec(pid).unwrap();
 
         mqtt.handle_incoming_pubcomp(pid).unwrap();
@@ -782,7 +1838,8 @@ mod test {
         let len = encode_slice(&publish, buf).unwrap();
         let mut pkg = SerializedPacket(&mut buf[..len]);
 
-        mqtt.handle_outgoing_publish(&mut pkg, &now).unwrap();
+        mqtt.handle_outgoing_publish(&mut pkg, DeliveryToken::default(), &now)
+            .unwrap();
         mqtt.handle_incoming_packet(Packet::Puback(Pid::try_from(2).unwrap()))
             .unwrap();
 
@@ -809,4 +1866,76 @@ mod test {
         assert_eq!(mqtt.handle_outgoing_ping(), Ok(Packet::Pingreq));
         assert!(mqtt.await_pingresp);
     }
+
+    #[test]
+    fn incoming_v5_connack_should_apply_session_limits() {
+        let mut mqtt = build_mqttstate();
+        mqtt.protocol = ProtocolVersion::MQTT5;
+        mqtt.connection_status = MqttConnectionStatus::Handshake;
+
+        let properties = [
+            v5::Property::ReceiveMaximum(1),
+            v5::Property::MaximumPacketSize(32),
+            v5::Property::TopicAliasMaximum(10),
+            v5::Property::ServerKeepAlive(120),
+            v5::Property::AssignedClientIdentifier("assigned"),
+        ];
+        let connack = v5::Packet::Connack(v5::Connack {
+            session_present: false,
+            reason_code: v5::ReasonCode::Success,
+            properties: v5::Properties::new(&properties),
+        });
+
+        assert_eq!(
+            mqtt.handle_incoming_packet_v5(connack),
+            Ok((Some(Notification::ConnAck), None))
+        );
+        assert_eq!(mqtt.connection_status, MqttConnectionStatus::Connected);
+        assert_eq!(
+            mqtt.session_limits,
+            SessionLimits {
+                receive_maximum: 1,
+                maximum_packet_size: Some(32),
+                topic_alias_maximum: 10,
+                server_keep_alive: Some(120),
+            }
+        );
+        assert_eq!(mqtt.assigned_client_id.as_deref(), Some("assigned"));
+
+        // Requests larger than the maximum packet size are rejected
+        let buf = &mut [0u8; 256];
+        let now = TimerInstantU32::from_ticks(0);
+        let mut publish = build_publish(QoS::AtLeastOnce, None);
+        publish.payload = &[0; 32];
+        let len = encode_slice(&Packet::Publish(publish), buf).unwrap();
+        assert_eq!(
+            mqtt.handle_outgoing_request(
+                &mut SerializedPacket(&mut buf[..len]),
+                DeliveryToken::default(),
+                &now
+            ),
+            Err(StateError::PacketTooLarge)
+        );
+        assert_eq!(mqtt.outgoing_pub.len(), 0);
+    }
+
+    #[test]
+    fn incoming_v5_connack_failure() {
+        let mut mqtt = build_mqttstate();
+        mqtt.protocol = ProtocolVersion::MQTT5;
+        mqtt.connection_status = MqttConnectionStatus::Handshake;
+
+        let connack = v5::Connack {
+            session_present: false,
+            reason_code: v5::ReasonCode::NotAuthorized,
+            properties: v5::Properties::default(),
+        };
+
+        assert_eq!(
+            mqtt.handle_incoming_connack_v5(connack),
+            Err(StateError::ConnectReason(v5::ReasonCode::NotAuthorized))
+        );
+        assert_eq!(mqtt.connection_status, MqttConnectionStatus::Disconnected);
+        assert_eq!(mqtt.session_limits, SessionLimits::default());
+    }
 }
@@ -0,0 +1,221 @@
+use heapless::{String, Vec};
+use mqttrust::encoding::v4::{QoS, Subscribe, SubscribeReturnCodes, SubscribeTopic, Unsubscribe};
+
+/// Maximum number of topic filters kept for resubscribing
+pub const MAX_SUBSCRIPTIONS: usize = 8;
+
+/// Maximum length of a topic filter kept for resubscribing
+pub const MAX_TOPIC_FILTER_LEN: usize = 128;
+
+/// Request awaiting its ack, which a subscription is part of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pending {
+    /// Subscribe with the given packet identifier, along with the index of
+    /// the topic filter within it
+    Subscribe(u16, usize),
+    /// Unsubscribe with the given packet identifier
+    Unsubscribe(u16),
+}
+
+#[derive(Debug)]
+struct Subscription {
+    topic_filter: String<MAX_TOPIC_FILTER_LEN>,
+    qos: QoS,
+    pending: Option<Pending>,
+}
+
+/// Registry of the topic filters subscribed to, which are subscribed to again
+/// when the broker didn't keep the session.
+///
+/// Topic filters are added once their subscribe is acked, and removed once
+/// their unsubscribe is acked. Those exceeding `MAX_SUBSCRIPTIONS` or
+/// `MAX_TOPIC_FILTER_LEN` aren't kept.
+#[derive(Debug)]
+pub(crate) struct Subscriptions {
+    entries: Vec<Subscription, MAX_SUBSCRIPTIONS>,
+}
+
+impl Subscriptions {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Active subscriptions, in the order they were subscribed
+    pub(crate) fn iter(&self) -> impl Iterator<Item = SubscribeTopic<'_>> {
+        self.entries
+            .iter()
+            .filter(|entry| !matches!(entry.pending, Some(Pending::Subscribe(..))))
+            .map(|entry| SubscribeTopic {
+                topic_path: entry.topic_filter.as_str(),
+                qos: entry.qos,
+            })
+    }
+
+    /// Keeps track of the topic filters of a subscribe sent with `pid`, until
+    /// it is acked.
+    pub(crate) fn handle_subscribe(&mut self, pid: u16, subscribe: &Subscribe<'_>) {
+        for (index, topic) in subscribe.topics().enumerate() {
+            let mut topic_filter = String::new();
+            if topic_filter.push_str(topic.topic_path).is_err() {
+                warn!("Topic filter too long to be resubscribed!");
+                continue;
+            }
+            let subscription = Subscription {
+                topic_filter,
+                qos: topic.qos,
+                pending: Some(Pending::Subscribe(pid, index)),
+            };
+            if self.entries.push(subscription).is_err() {
+                warn!("Too many subscriptions to be resubscribed!");
+            }
+        }
+    }
+
+    /// Keeps track of the topic filters of an unsubscribe sent with `pid`,
+    /// until it is acked.
+    pub(crate) fn handle_unsubscribe(&mut self, pid: u16, unsubscribe: &Unsubscribe<'_>) {
+        for topic in unsubscribe.topics() {
+            for entry in self.entries.iter_mut() {
+                if entry.topic_filter == topic {
+                    entry.pending = Some(Pending::Unsubscribe(pid));
+                }
+            }
+        }
+    }
+
+    /// Activates the topic filters of the subscribe with `pid` which were
+    /// granted, replacing earlier subscriptions to the same topic filters.
+    /// Refused ones are removed.
+    pub(crate) fn handle_suback(&mut self, pid: u16, return_codes: &[SubscribeReturnCodes]) {
+        self.entries.retain(|entry| match entry.pending {
+            Some(Pending::Subscribe(p, index)) if p == pid => {
+                return_codes.get(index) != Some(&SubscribeReturnCodes::Failure)
+            }
+            _ => true,
+        });
+
+        let mut index = 0;
+        while index < self.entries.len() {
+            if matches!(self.entries[index].pending, Some(Pending::Subscribe(p, _)) if p == pid) {
+                self.entries[index].pending = None;
+                let topic_filter = self.entries[index].topic_filter.clone();
+                let replaced = self.entries.iter().position(|entry| {
+                    entry.pending.is_none() && entry.topic_filter == topic_filter
+                });
+                if let Some(replaced) = replaced.filter(|replaced| *replaced != index) {
+                    self.entries.remove(replaced);
+                    continue;
+                }
+            }
+            index += 1;
+        }
+    }
+
+    /// Removes the topic filters of the unsubscribe with `pid`.
+    pub(crate) fn handle_unsuback(&mut self, pid: u16) {
+        self.entries
+            .retain(|entry| entry.pending != Some(Pending::Unsubscribe(pid)));
+    }
+
+    /// Subscribes to all topic filters again with `pid`, after the broker
+    /// didn't keep the session. Unsubscribes which weren't acked are
+    /// completed, as the broker dropped their subscriptions along with the
+    /// session.
+    pub(crate) fn resubscribe(&mut self, pid: u16) -> Vec<SubscribeTopic<'_>, MAX_SUBSCRIPTIONS> {
+        self.entries
+            .retain(|entry| !matches!(entry.pending, Some(Pending::Unsubscribe(_))));
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            entry.pending = Some(Pending::Subscribe(pid, index));
+        }
+        self.entries
+            .iter()
+            .map(|entry| SubscribeTopic {
+                topic_path: entry.topic_filter.as_str(),
+                qos: entry.qos,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topics(subscriptions: &Subscriptions) -> std::vec::Vec<(&str, QoS)> {
+        subscriptions
+            .iter()
+            .map(|topic| (topic.topic_path, topic.qos))
+            .collect()
+    }
+
+    #[test]
+    fn subscribe_and_unsubscribe() {
+        let mut subscriptions = Subscriptions::new();
+        let requested = [
+            SubscribeTopic {
+                topic_path: "a/b",
+                qos: QoS::AtLeastOnce,
+            },
+            SubscribeTopic {
+                topic_path: "c/#",
+                qos: QoS::ExactlyOnce,
+            },
+        ];
+        subscriptions.handle_subscribe(2, &Subscribe::new(&requested));
+        assert!(topics(&subscriptions).is_empty());
+
+        // Refused topic filters aren't kept
+        subscriptions.handle_suback(
+            2,
+            &[
+                SubscribeReturnCodes::Success(QoS::AtLeastOnce),
+                SubscribeReturnCodes::Failure,
+            ],
+        );
+        assert_eq!(topics(&subscriptions), [("a/b", QoS::AtLeastOnce)]);
+
+        // Subscribing again replaces the subscription
+        let requested = [SubscribeTopic {
+            topic_path: "a/b",
+            qos: QoS::AtMostOnce,
+        }];
+        subscriptions.handle_subscribe(3, &Subscribe::new(&requested));
+        subscriptions.handle_suback(3, &[SubscribeReturnCodes::Success(QoS::AtMostOnce)]);
+        assert_eq!(topics(&subscriptions), [("a/b", QoS::AtMostOnce)]);
+
+        subscriptions.handle_unsubscribe(4, &Unsubscribe::new(&["a/b"]));
+        assert_eq!(topics(&subscriptions), [("a/b", QoS::AtMostOnce)]);
+        subscriptions.handle_unsuback(4);
+        assert!(topics(&subscriptions).is_empty());
+    }
+
+    #[test]
+    fn resubscribe() {
+        let mut subscriptions = Subscriptions::new();
+        let requested = [
+            SubscribeTopic {
+                topic_path: "a",
+                qos: QoS::AtLeastOnce,
+            },
+            SubscribeTopic {
+                topic_path: "b",
+                qos: QoS::AtMostOnce,
+            },
+        ];
+        subscriptions.handle_subscribe(2, &Subscribe::new(&requested));
+        subscriptions.handle_suback(2, &[SubscribeReturnCodes::Success(QoS::AtLeastOnce); 2]);
+        subscriptions.handle_unsubscribe(3, &Unsubscribe::new(&["a"]));
+
+        let resubscribed: std::vec::Vec<_> = subscriptions
+            .resubscribe(4)
+            .iter()
+            .map(|topic| (topic.topic_path, topic.qos))
+            .collect();
+        assert_eq!(resubscribed, [("b", QoS::AtMostOnce)]);
+
+        subscriptions.handle_suback(4, &[SubscribeReturnCodes::Success(QoS::AtMostOnce)]);
+        assert_eq!(topics(&subscriptions), [("b", QoS::AtMostOnce)]);
+    }
+}